@@ -1,13 +1,16 @@
-// This example demonstrates a Telegram bot agent that receives messages and logs them to files.
+// This example demonstrates a Telegram bot agent that receives messages, logs them to files
+// and answers them in the chat they came from.
 //
 // Key features:
 // - Telegram bot integration for receiving messages
 // - LLM-powered processing of messages
 // - File logging using SimpleFileWriter tool
+// - Threaded replies using the trigger's reply handler
 // - Automatic retry logic for reliability
 
 use forgeflow::{
-    agent::AgentBuilder, shutdown, SimpleFileWriterBuilder, TelegramBotTriggerBuilder,
+    agent::AgentBuilder, shutdown, ReplyToOrigin, SimpleFileWriterBuilder,
+    TelegramBotTriggerBuilder,
 };
 use rig::{
     client::CompletionClient, 
@@ -45,6 +48,11 @@ async fn main() {
     };
     info!("TelegramBotTrigger initialized");
 
+    // Answers go back to the chat of the message, as a reply to it
+    let replies = ReplyToOrigin::new()
+        .route("TelegramMessage", trigger.reply_handler())
+        .with_error_reply("Sorry, I couldn't process your message. Please try again later.");
+
     // Create the file writer tool for logging messages
     let output_dir = PathBuf::from("./telegram_logs");
     let file_writer = SimpleFileWriterBuilder::new(output_dir).build();
@@ -62,7 +70,7 @@ async fn main() {
     // Create the Gemini agent with the file writer tool
     let gemini_agent = gemini_client
         .agent(completion::GEMINI_2_0_FLASH_LITE)
-        .preamble("You are a helpful assistant that logs Telegram messages to files. When you receive a message, write it to a file using the file writer tool.")
+        .preamble("You are a helpful assistant that logs Telegram messages to files. When you receive a message, write it to a file using the file writer tool, then answer the user.")
         .temperature(0.7)
         .tool(file_writer)
         .additional_params(serde_json::to_value(cfg).unwrap())
@@ -75,6 +83,8 @@ async fn main() {
         .add_trigger(Box::new(trigger))
         .with_shutdown_handler(shutdown::CtrlCShutdown::new())
         .with_model(Box::new(gemini_agent))  // Retry will be added automatically by default
+        .add_response_handler(replies.clone())
+        .add_error_handler(replies)
         .with_prompt_template(
             "You received a Telegram message:\n\
             Message ID: {{payload.message_id}}\n\
@@ -82,7 +92,7 @@ async fn main() {
             From: {{payload.first_name}} (@{{payload.username}})\n\
             Text: {{payload.text}}\n\
             Date: {{payload.date}}\n\n\
-            Please log this message to a file using the file writer tool."
+            Please log this message to a file using the file writer tool, then write your answer to the user."
                 .to_string(),
         )
         .build();
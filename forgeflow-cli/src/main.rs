@@ -0,0 +1,167 @@
+// The `forgeflow` command runs, checks and dry-runs agents described in configuration
+// files, so that an agent made of built-in components needs no code of its own.
+//
+//     forgeflow run agent.toml
+//     forgeflow check agent.toml
+//     forgeflow render agent.toml --event sample.json
+
+use forgeflow::config::{AgentConfig, ComponentRegistry};
+use forgeflow::triggers::event::TEvent;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+
+const USAGE: &str = "\
+Usage:
+    forgeflow run <config>                   Run the agent until it is shut down
+    forgeflow check <config>                 Check the configuration, without network access
+    forgeflow render <config> --event <file> Print the prompt rendered for an event
+
+The configuration is a .toml, .yaml (or .yml) or .json file. The event file holds a JSON event:
+    { \"name\": \"NewEmail\", \"payload\": { \"subject\": \"Invoice\" } }";
+
+/// A command of the command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run { config: PathBuf },
+    Check { config: PathBuf },
+    Render { config: PathBuf, event: PathBuf },
+}
+
+impl Command {
+    /// Parses the arguments, without the program name.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = args.next().ok_or("missing command")?;
+        let config = args
+            .next()
+            .map(PathBuf::from)
+            .ok_or("missing configuration file")?;
+        let command = match command.as_str() {
+            "run" => Command::Run { config },
+            "check" => Command::Check { config },
+            "render" => match (args.next().as_deref(), args.next()) {
+                (Some("--event"), Some(event)) => Command::Render {
+                    config,
+                    event: PathBuf::from(event),
+                },
+                _ => return Err("render needs --event <file>".to_string()),
+            },
+            other => return Err(format!("unknown command `{other}`")),
+        };
+        match args.next() {
+            Some(extra) => Err(format!("unexpected argument `{extra}`")),
+            None => Ok(command),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    // Tokens and API keys can come from a .env file, like in the examples.
+    dotenv::dotenv().ok();
+
+    match execute(command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            let mut source = e.source();
+            while let Some(cause) = source {
+                eprintln!("  caused by: {cause}");
+                source = cause.source();
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn execute(command: Command) -> Result<(), Box<dyn Error>> {
+    let registry = ComponentRegistry::new();
+    match command {
+        Command::Run { config } => {
+            let subscriber = FmtSubscriber::builder()
+                .with_env_filter(
+                    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+                )
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)?;
+
+            let agent = AgentConfig::from_path(&config)?
+                .into_builder(&registry)
+                .await?
+                .build()?;
+            let report = agent.run().await?;
+            println!(
+                "processed: {}, failed: {}, dead-lettered: {}, abandoned: {}, duplicates: {}",
+                report.processed,
+                report.failed,
+                report.dead_lettered,
+                report.abandoned,
+                report.duplicates
+            );
+        }
+        Command::Check { config } => {
+            let agent = AgentConfig::from_path(&config)?;
+            agent.check(&registry)?;
+            // Unlike an agent built in code, the agent run here has no other model.
+            if agent.model.is_none() && agent.dry_run.is_none() {
+                return Err("the configuration names no model, which `run` needs".into());
+            }
+            println!(
+                "{}: ok ({} triggers, {} tools, {} handlers, {} routes)",
+                config.display(),
+                agent.triggers.len(),
+                agent.tools.len(),
+                agent.handlers.len(),
+                agent.routes.len()
+            );
+        }
+        Command::Render { config, event } => {
+            let agent = AgentConfig::from_path(&config)?;
+            let event: TEvent = serde_json::from_str(&std::fs::read_to_string(&event)?)?;
+            match agent.render(&event)? {
+                Some(prompt) => println!("{prompt}"),
+                None => return Err(format!("no route matches the event `{}`", event.name).into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parses_commands() {
+        assert_eq!(
+            parse(&["check", "agent.toml"]),
+            Ok(Command::Check {
+                config: PathBuf::from("agent.toml")
+            })
+        );
+        assert_eq!(
+            parse(&["render", "agent.toml", "--event", "sample.json"]),
+            Ok(Command::Render {
+                config: PathBuf::from("agent.toml"),
+                event: PathBuf::from("sample.json"),
+            })
+        );
+        assert!(parse(&["render", "agent.toml"]).is_err());
+        assert!(parse(&["run"]).is_err());
+        assert!(parse(&["run", "agent.toml", "extra"]).is_err());
+        assert!(parse(&["deploy", "agent.toml"]).is_err());
+    }
+}
@@ -1,17 +1,71 @@
 // The `Agent` module provides the core functionality for the Forgeflow framework.
 // It defines the `Agent` struct, which is responsible for managing triggers, interacting with language models, and executing actions using tools.
-use crate::llm::{LLM, LLMFactory, RetryConfig};
+use crate::dead_letter::{DeadLetter, DeadLetterError, DeadLetterStore, FailureStage};
+use crate::dedupe::DedupeStore;
+use crate::handlers::{ErrorHandler, PromptSink, ResponseHandler};
+use crate::llm::{LLM, LLMError, RetryConfig};
+use crate::metrics::Metrics;
+use crate::observer::{AgentObserver, ProcessingError, Timing, Verdict};
+use crate::recording::EventRecorder;
 use crate::shutdown::Shutdown;
-use crate::triggers::{Trigger, event::TEvent};
-use crate::utils::{TEngine, TEngineError};
+use crate::triggers::{
+    Trigger,
+    event::{Ack, KeyExtractor, Outcome, TEvent},
+};
+use crate::utils::{EventPattern, TEngine, TEngineError};
 use serde_json::json;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
-//use tokio_util::task::TaskTracker;
-use tracing::{debug, error, info};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, warn};
+
+mod admin;
+mod batch;
+mod control;
+mod dedupe;
+mod dispatch;
+mod drain;
+mod dry_run;
+mod handle;
+mod intake;
+mod journal;
+mod lanes;
+mod policy;
+mod routing;
+mod supervisor;
+
+pub use batch::Batch;
+use batch::{BatchTable, Collected, Sealed, Settlement};
+use control::Control;
+use dedupe::{Claim, Deduplicator};
+use dispatch::Dispatcher;
+use drain::Counters;
+pub use drain::{DrainPolicy, ShutdownReport};
+use dry_run::{NoModel, PromptRecorder};
+pub use handle::AgentHandle;
+use intake::{Intake, Queues};
+use journal::Journal;
+pub use lanes::Priority;
+use lanes::{DEFAULT_STARVATION_LIMIT, Lanes, PriorityRule};
+use policy::{Admission, Discard, PolicyTable};
+pub use policy::{EventPolicy, Overflow};
+pub use routing::Route;
+use routing::RouteTable;
+use supervisor::TriggerBoard;
+pub use supervisor::{Restart, RestartPolicy, TriggerInfo, TriggerState};
+
+/// How long the agent waits for its events to complete during shutdown, by default.
+const DEFAULT_DRAIN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// The number of events that can wait in each of the agent's queues, one per priority,
+/// before senders are held back.
+const EVENT_BUFFER: usize = 100;
 
 /// The `AgentError` enum defines the possible errors that can occur within the `Agent`.
 #[derive(Error, Debug)]
@@ -28,32 +82,173 @@ pub enum AgentError {
     /// An error occurred while building the agent.
     #[error("Agent build error: {0}")]
     BuildError(String),
+    /// The agent has stopped and no longer accepts events.
+    #[error("The agent is not running")]
+    NotRunning,
+    /// An error occurred within the dead-letter store.
+    #[error("Dead-letter store error")]
+    DeadLetterError(#[from] DeadLetterError),
+}
+
+/// An event on its way through the agent, with what the agent knows about it.
+pub(crate) struct Envelope {
+    pub(crate) event: TEvent,
+    /// How many times the agent has tried to process the event, this time included.
+    pub(crate) attempt: u32,
+    /// The id of the event's journal entry, once it has been journaled.
+    pub(crate) journal_id: Option<u64>,
+    /// Reports the outcome of the event to the trigger that emitted it.
+    pub(crate) ack: Ack,
+    /// The priority of the trigger that emitted the event.
+    pub(crate) priority: Priority,
+    /// The number of events held, if this is the event of a batch.
+    pub(crate) batch: Option<usize>,
+}
+
+impl Envelope {
+    /// Wraps an event received for the first time.
+    pub(crate) fn new(event: TEvent) -> Self {
+        Self {
+            event,
+            attempt: 1,
+            journal_id: None,
+            ack: Ack::none(),
+            priority: Priority::default(),
+            batch: None,
+        }
+    }
+
+    /// Returns the number of received events the envelope stands for: the events of
+    /// its batch, or itself.
+    pub(crate) fn events(&self) -> usize {
+        self.batch.unwrap_or(1)
+    }
+
+    /// Wraps an event named `name`, without a payload, with `priority`.
+    #[cfg(test)]
+    pub(crate) fn named(name: &str, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..Self::new(TEvent {
+                name: name.to_string(),
+                payload: None,
+            })
+        }
+    }
 }
 
 /// The `Agent` struct is the central component of the Forgeflow framework.
 /// It is responsible for coordinating the other components and executing the main logic.
 pub struct Agent {
-    /// A vector of triggers that can initiate agent actions.
-    triggers: Vec<Box<dyn Trigger>>,
+    /// The triggers that can initiate agent actions, shared with the handles.
+    trigger_board: Arc<TriggerBoard>,
+    /// The pause and shutdown switches, shared with the handles.
+    control: Arc<Control>,
     /// An optional shutdown handler that can be used to gracefully shut down the agent.
     shutdown_handler: Box<dyn Shutdown>,
-    /// An optional language model that the agent can use to process events and generate responses.
-    model: Box<dyn LLM>,
-    /// An optional prompt template that the agent can use to generate prompts for the language model.
-    prompt_template: String,
-    /// The Handlebars template engine used by the agent.
+    /// The state shared with the tasks that process events.
+    core: Arc<AgentCore>,
+    /// The maximum number of events processed at the same time.
+    concurrency: usize,
+    /// Derives the key of the events that must be processed in order.
+    ordering_key: Option<KeyExtractor>,
+    /// Tracks the event processing tasks so they can be drained at shutdown.
+    tasks: TaskTracker,
+    /// Cancels the event processing tasks still running at the drain deadline.
+    cancel: CancellationToken,
+    /// How long the agent waits for its events to complete during shutdown.
+    drain_deadline: Duration,
+    /// What happens to the queued events during shutdown.
+    drain_policy: DrainPolicy,
+    /// The address the metrics are served on, if any.
+    metrics_addr: Option<SocketAddr>,
+    /// The address the admin API is served on, if any.
+    admin_addr: Option<SocketAddr>,
+    /// The sending side of the event queues, handed to the triggers and the handles.
+    events: Option<Intake>,
+    /// The receiving side of the event queues, consumed by the event loop.
+    event_rx: Option<Queues>,
+    /// The batches the received events are collected in.
+    batches: BatchTable,
+    /// The rate limits, debounces and throttles applied to the received events.
+    policies: PolicyTable,
+    /// The priority lanes the events wait in for a processing slot.
+    lanes: Lanes,
+}
+
+/// The part of the agent that event processing tasks need access to.
+struct AgentCore {
+    /// The template and model used for each event name.
+    routes: RouteTable,
+    /// The Handlebars template engine used by the agent, holding every route template.
     handlebars: TEngine,
-    /// An atomic counter for the number of in-flight requests.
+    /// The handlers receiving the model's responses.
+    response_handlers: Vec<Arc<dyn ResponseHandler>>,
+    /// The handlers receiving the model's errors.
+    error_handlers: Vec<Arc<dyn ErrorHandler>>,
+    /// The observers called at each step of the processing of an event.
+    observers: Vec<Arc<dyn AgentObserver>>,
+    /// Where the events that failed to be processed are kept.
+    dead_letters: Option<Arc<dyn DeadLetterStore>>,
+    /// The write-ahead journal of the received events.
+    journal: Option<Arc<Journal>>,
+    /// Where the received events are recorded, if anywhere.
+    recorder: Option<EventRecorder>,
+    /// Skips the events whose key was already processed, if set.
+    dedupe: Option<Deduplicator>,
+    /// Records the prompts in place of prompting the model, in dry-run mode.
+    dry_run: Option<PromptRecorder>,
+    /// An atomic counter for the number of in-flight events.
     inflight: AtomicUsize,
+    /// What happened to the events, for the shutdown report.
+    counters: Counters,
+    /// The metrics of the agent.
+    metrics: Arc<Metrics>,
+}
+
+/// Keeps the in-flight counter accurate even if processing is cancelled or panics.
+struct InflightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InflightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// The `AgentBuilder` struct is used to construct an `Agent`.
 pub struct AgentBuilder {
-    triggers: Vec<Box<dyn Trigger>>,
+    triggers: Vec<(Box<dyn Trigger>, Option<RestartPolicy>, Priority)>,
+    restart_policy: RestartPolicy,
     shutdown_handler: Option<Box<dyn Shutdown>>,
     model: Option<Box<dyn LLM>>,
     prompt_template: Option<String>,
     retry_config: Option<RetryConfig>,
+    routes: Vec<Route>,
+    response_handlers: Vec<Arc<dyn ResponseHandler>>,
+    error_handlers: Vec<Arc<dyn ErrorHandler>>,
+    observers: Vec<Arc<dyn AgentObserver>>,
+    concurrency: usize,
+    ordering_key: Option<KeyExtractor>,
+    policies: Vec<(EventPattern, EventPolicy)>,
+    batches: Vec<Batch>,
+    priority_rules: Vec<PriorityRule>,
+    starvation_limit: usize,
+    dedupe: Option<(KeyExtractor, Arc<dyn DedupeStore>)>,
+    dead_letters: Option<Arc<dyn DeadLetterStore>>,
+    journal_path: Option<PathBuf>,
+    recording_path: Option<PathBuf>,
+    drain_deadline: Duration,
+    drain_policy: DrainPolicy,
+    metrics_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    dry_run: Option<Arc<dyn PromptSink>>,
 }
 
 impl Default for AgentBuilder {
@@ -67,10 +262,30 @@ impl AgentBuilder {
     pub fn new() -> Self {
         Self {
             triggers: Vec::new(),
+            restart_policy: RestartPolicy::default(),
             shutdown_handler: None,
             model: None,
             prompt_template: None,
             retry_config: None,
+            routes: Vec::new(),
+            response_handlers: Vec::new(),
+            error_handlers: Vec::new(),
+            observers: Vec::new(),
+            concurrency: 1,
+            ordering_key: None,
+            policies: Vec::new(),
+            batches: Vec::new(),
+            priority_rules: Vec::new(),
+            starvation_limit: DEFAULT_STARVATION_LIMIT,
+            dedupe: None,
+            dead_letters: None,
+            journal_path: None,
+            recording_path: None,
+            drain_deadline: DEFAULT_DRAIN_DEADLINE,
+            drain_policy: DrainPolicy::default(),
+            metrics_addr: None,
+            admin_addr: None,
+            dry_run: None,
         }
     }
 
@@ -86,9 +301,161 @@ impl AgentBuilder {
         self
     }
 
+    /// Adds a route sending matching events to their own template and model.
+    ///
+    /// Routes are tried in the order they are added; events matching no route are
+    /// rendered with the default prompt template and sent to the default model.
+    pub fn add_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
     /// Adds a trigger to the agent.
+    ///
+    /// The trigger is relaunched according to the agent's restart policy, see
+    /// [`AgentBuilder::with_restart_policy`].
     pub fn add_trigger(mut self, t: Box<dyn Trigger>) -> Self {
-        self.triggers.push(t);
+        self.triggers.push((t, None, Priority::default()));
+        self
+    }
+
+    /// Adds a trigger to the agent, relaunched according to its own restart policy.
+    pub fn add_supervised_trigger(mut self, t: Box<dyn Trigger>, policy: RestartPolicy) -> Self {
+        self.triggers.push((t, Some(policy), Priority::default()));
+        self
+    }
+
+    /// Adds a trigger to the agent, whose events are processed with `priority`.
+    ///
+    /// See [`AgentBuilder::add_priority_rule`] for how the priorities apply.
+    pub fn add_prioritized_trigger(mut self, t: Box<dyn Trigger>, priority: Priority) -> Self {
+        self.triggers.push((t, None, priority));
+        self
+    }
+
+    /// Adds a trigger to the agent, relaunched according to its own restart policy,
+    /// whose events are processed with `priority`.
+    pub fn add_supervised_prioritized_trigger(
+        mut self,
+        t: Box<dyn Trigger>,
+        policy: RestartPolicy,
+        priority: Priority,
+    ) -> Self {
+        self.triggers.push((t, Some(policy), priority));
+        self
+    }
+
+    /// Sets the restart policy of the triggers added without their own.
+    ///
+    /// By default, a trigger whose task ends is not relaunched.
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Adds a handler receiving the model's response to each event.
+    ///
+    /// Handlers run in the order they are added. Without any handler, responses are
+    /// only logged.
+    pub fn add_response_handler(mut self, handler: impl ResponseHandler + 'static) -> Self {
+        self.response_handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Adds a handler receiving the error of each event whose LLM call failed.
+    pub fn add_error_handler(mut self, handler: impl ErrorHandler + 'static) -> Self {
+        self.error_handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Adds an observer whose hooks are called at each step of the processing of an event.
+    ///
+    /// Observers run in the order they are added, before the handlers.
+    pub fn add_observer(mut self, observer: impl AgentObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Keeps the events whose prompt failed to render, or whose LLM call failed after
+    /// every retry, in a dead-letter store.
+    ///
+    /// Dead letters can be listed and re-injected while the agent runs through its
+    /// [`AgentHandle`].
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::dead_letter::DeadLetterDir;
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .with_dead_letter_store(DeadLetterDir::new("./dead-letters"))
+    ///     .build()?;
+    /// let handle = agent.handle();
+    /// ```
+    pub fn with_dead_letter_store(mut self, store: impl DeadLetterStore + 'static) -> Self {
+        self.dead_letters = Some(Arc::new(store));
+        self
+    }
+
+    /// Journals every event to a file before it is queued, so queued and in-flight
+    /// events survive a crash or a restart.
+    ///
+    /// Events are marked done in the journal once their processing is over, whether
+    /// they got a response or reached the error handlers and the dead-letter store.
+    /// When the agent runs, the entries left unfinished by the previous run are
+    /// loaded and processed again before any new event. Delivery is at least once: an
+    /// event processed just before a crash may be processed twice.
+    pub fn with_journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal_path = Some(path.into());
+        self
+    }
+
+    /// Records every event the agent receives from its triggers, or through
+    /// [`AgentHandle::inject`], with the time it was received, in a JSON Lines file.
+    ///
+    /// A recording can be replayed with a
+    /// [`ReplayTrigger`](crate::triggers::ReplayTrigger), to reproduce what the agent
+    /// saw. Events re-processed from the journal or the dead-letter store are not
+    /// recorded again.
+    pub fn with_event_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.recording_path = Some(path.into());
+        self
+    }
+
+    /// Sets how long the agent waits for its events to complete once asked to shut
+    /// down. Defaults to 10 seconds.
+    ///
+    /// The deadline covers stopping the triggers and draining the events. Events still
+    /// being processed when it elapses are cancelled, and reported as abandoned.
+    pub fn with_drain_deadline(mut self, deadline: Duration) -> Self {
+        self.drain_deadline = deadline;
+        self
+    }
+
+    /// Sets whether the queued events are processed or dropped during shutdown.
+    /// Defaults to [`DrainPolicy::FinishQueue`].
+    pub fn with_drain_policy(mut self, policy: DrainPolicy) -> Self {
+        self.drain_policy = policy;
+        self
+    }
+
+    /// Serves the agent's metrics in the Prometheus text format on `GET /metrics` at `addr`,
+    /// while the agent runs.
+    ///
+    /// The metrics are also available from [`AgentHandle::metrics`] without a listener.
+    pub fn with_metrics_listener(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.metrics_addr = Some(addr.into());
+        self
+    }
+
+    /// Serves the admin API at `addr` while the agent runs.
+    ///
+    /// The API reports the agent's health and triggers, pauses and resumes triggers
+    /// or the event loop, injects events and requests a graceful shutdown. It has no
+    /// authentication, so it should only listen on a local address. Since it can
+    /// inject events, the agent keeps running until it is shut down, even once every
+    /// trigger has stopped.
+    pub fn with_admin_listener(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.admin_addr = Some(addr.into());
         self
     }
 
@@ -132,6 +499,176 @@ impl AgentBuilder {
         self
     }
 
+    /// Sets the maximum number of events processed at the same time.
+    ///
+    /// By default events are processed one at a time, in the order they are received.
+    /// With a higher limit, a slow LLM call no longer holds back the events queued
+    /// behind it; the model is shared between the concurrent tasks.
+    pub fn with_concurrency(mut self, n: usize) -> Self {
+        self.concurrency = n;
+        self
+    }
+
+    /// Processes events that share a key in the order they were received.
+    ///
+    /// Events with the same key (e.g. the same Telegram chat) never run at the same
+    /// time, while events with different keys are processed in parallel up to the
+    /// [`with_concurrency`](Self::with_concurrency) limit. Events for which the
+    /// extractor returns `None` are not ordered.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::triggers::event::payload_key;
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .with_concurrency(8)
+    ///     .with_ordering_key(payload_key("chat_id"))
+    ///     .build()?;
+    /// ```
+    pub fn with_ordering_key(
+        mut self,
+        extractor: impl Fn(&TEvent) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.ordering_key = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Paces the events whose name matches `pattern` with a rate limit, a debounce or
+    /// a throttle.
+    ///
+    /// Policies are tried in the order they were added, and the first one whose
+    /// pattern matches applies; events that match no policy are not paced. The events
+    /// a policy holds back wait outside the agent's queue, so they do not hold back the
+    /// events of other names. They are released when the agent shuts down, unless the
+    /// queue is abandoned. The events a policy drops or supersedes are counted in the
+    /// [`ShutdownReport`].
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::agent::{EventPolicy, Overflow};
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .add_event_policy("NewEmail", EventPolicy::rate_limit(10, Duration::from_secs(60)))
+    ///     .add_event_policy("*", EventPolicy::throttle(100, Duration::from_secs(3600), Overflow::Drop))
+    ///     .build()?;
+    /// ```
+    pub fn add_event_policy(mut self, pattern: &str, policy: EventPolicy) -> Self {
+        self.policies.push((EventPattern::new(pattern), policy));
+        self
+    }
+
+    /// Collects the events of a name into batches, each processed as a single event
+    /// whose payload is the array of their payloads. See [`Batch`].
+    ///
+    /// Batches are collected before the event policies apply, so a policy paces the
+    /// batch events rather than the events they hold. With deduplication, each event is
+    /// checked before it joins a batch. On shutdown, the batches being collected are
+    /// processed, unless the queue is abandoned: the events of an abandoned batch are
+    /// refused.
+    pub fn add_batch(mut self, batch: Batch) -> Self {
+        self.batches.push(batch);
+        self
+    }
+
+    /// Processes the events matching `predicate` with `priority`.
+    ///
+    /// While every processing slot is busy, the events wait in a lane per priority,
+    /// and the highest lane holding events is served first. Rules are tried in the
+    /// order they were added, and the first one an event matches decides its priority,
+    /// over the priority of its trigger; other events keep the priority of their
+    /// trigger, [`Priority::Normal`] by default. Events ordered by
+    /// [`with_ordering_key`](Self::with_ordering_key) still wait for the events with
+    /// the same key, whatever their priority.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::agent::Priority;
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .add_prioritized_trigger(Box::new(telegram), Priority::High)
+    ///     .add_trigger(Box::new(gmail))
+    ///     .add_priority_rule(Priority::Low, |event| {
+    ///         let labels = event.payload.as_ref().and_then(|email| email["labelIds"].as_array());
+    ///         labels.is_some_and(|labels| labels.iter().any(|label| label == "CATEGORY_PROMOTIONS"))
+    ///     })
+    ///     .build()?;
+    /// ```
+    pub fn add_priority_rule(
+        mut self,
+        priority: Priority,
+        predicate: impl Fn(&TEvent) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.priority_rules.push((Arc::new(predicate), priority));
+        self
+    }
+
+    /// Sets how many events of higher priorities are processed, at most, while an
+    /// event of a lower priority waits. Defaults to 8.
+    ///
+    /// Without this limit, a steady flow of high-priority events would hold the other
+    /// events back forever.
+    pub fn with_starvation_limit(mut self, n: usize) -> Self {
+        self.starvation_limit = n;
+        self
+    }
+
+    /// Skips the events whose key was already processed.
+    ///
+    /// The key of each event that is processed successfully, or vetoed, is kept in
+    /// `store`. An event whose key is in the store is acknowledged without being
+    /// processed, so a trigger that polls the same items again, like the unread emails
+    /// of a [`GmailWatchTrigger`](crate::triggers::GmailWatchTrigger), does not prompt
+    /// the model twice. An event whose key is being processed is refused, so that it
+    /// can be emitted again if the other one fails. Events for which the extractor
+    /// returns `None` are always processed.
+    ///
+    /// With a [`FileDedupeStore`](crate::dedupe::FileDedupeStore), the processed keys
+    /// survive restarts. The keys are not kept in dry-run mode.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::dedupe::FileDedupeStore;
+    /// use forgeflow::triggers::event::payload_key;
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .add_trigger(Box::new(gmail))
+    ///     .with_deduplication(payload_key("id"), FileDedupeStore::new("./processed.jsonl"))
+    ///     .build()?;
+    /// ```
+    pub fn with_deduplication(
+        mut self,
+        extractor: impl Fn(&TEvent) -> Option<String> + Send + Sync + 'static,
+        store: impl DedupeStore + 'static,
+    ) -> Self {
+        self.dedupe = Some((Arc::new(extractor), Arc::new(store)));
+        self
+    }
+
+    /// Runs the agent in dry-run mode: prompts are rendered and recorded in `sink`,
+    /// but never sent to the model.
+    ///
+    /// Triggers, routing, templates and observers work as usual. The models, with
+    /// their tools, are replaced, so no quota is spent and no tool acts; the response
+    /// and error handlers are not called. Each event counts as processed once its
+    /// prompt is recorded, but is released without an outcome: its trigger sees it as
+    /// not processed, so a Gmail message is not marked as read, and its journal entry
+    /// is left unfinished. A dry-run agent needs no model.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// use forgeflow::handlers::JsonlFileHandler;
+    ///
+    /// let agent = AgentBuilder::new()
+    ///     .add_trigger(Box::new(gmail))
+    ///     .with_prompt_template(template)
+    ///     .dry_run(JsonlFileHandler::new("./prompts.jsonl"))
+    ///     .build()?;
+    /// ```
+    pub fn dry_run(mut self, sink: impl PromptSink + 'static) -> Self {
+        self.dry_run = Some(Arc::new(sink));
+        self
+    }
+
     /// Explicitly disable retry functionality.
     ///
     /// By default, retry is enabled. Use this method to explicitly opt-out.
@@ -142,20 +679,26 @@ impl AgentBuilder {
     }
 
     /// Builds the `Agent`.
-    pub fn build(self) -> Result<Agent, AgentError> {
-        if self.model.is_none() {
-            return Err(AgentError::BuildError("A model is required.".to_string()));
+    pub fn build(mut self) -> Result<Agent, AgentError> {
+        if self.concurrency == 0 {
+            return Err(AgentError::BuildError(
+                "Concurrency must be at least 1.".to_string(),
+            ));
         }
-
-        let mut handlebars = TEngine::new();
-        if let Some(template) = &self.prompt_template {
-            handlebars.register_template_string("prompt", template)?;
-        } else {
+        if self.starvation_limit == 0 {
             return Err(AgentError::BuildError(
-                "A prompt template is required.".to_string(),
+                "The starvation limit must be at least 1.".to_string(),
             ));
         }
 
+        let dry_run = self.dry_run.take().map(PromptRecorder);
+        if dry_run.is_some() {
+            self.model = Some(Box::new(NoModel));
+            self.routes = self.routes.into_iter().map(Route::without_model).collect();
+            // A dry run processes nothing, so it must not mark the events as processed.
+            self.dedupe = None;
+        }
+
         let shutdown_handler = self
             .shutdown_handler
             .unwrap_or_else(|| Box::new(crate::shutdown::CtrlCShutdown::new()));
@@ -166,142 +709,663 @@ impl AgentBuilder {
             RetryConfig::default()
         });
 
-        // Use the LLM factory to transparently apply retry configuration
-        let base_model = self.model.unwrap();
-        let final_model = LLMFactory::create(base_model, Some(retry_config));
+        // Register every template up front and let the LLM factory transparently
+        // apply the retry configuration to each route's model
+        let metrics = Arc::new(Metrics::new());
+        let mut handlebars = TEngine::new();
+        let routes = RouteTable::compile(
+            &mut handlebars,
+            self.model,
+            self.prompt_template,
+            retry_config,
+            self.routes,
+            &metrics,
+        )?;
+
+        let journal = self.journal_path.map(|path| Arc::new(Journal::new(path)));
+
+        let batches = BatchTable::compile(self.batches)?;
+        let policies = PolicyTable::compile(self.policies)?;
 
-        Ok(Agent {
-            triggers: self.triggers,
+        let trigger_board = Arc::new(TriggerBoard::new(self.restart_policy, metrics.clone()));
+        let (event_tx, event_rx) = intake::channel(self.priority_rules, EVENT_BUFFER);
+        let agent = Agent {
+            trigger_board,
+            control: Arc::new(Control::default()),
             shutdown_handler,
-            model: final_model,
-            prompt_template: self.prompt_template.unwrap(),
-            handlebars,
-            inflight: AtomicUsize::new(0),
-        })
+            core: Arc::new(AgentCore {
+                routes,
+                handlebars,
+                response_handlers: self.response_handlers,
+                error_handlers: self.error_handlers,
+                observers: self.observers,
+                dead_letters: self.dead_letters,
+                journal,
+                recorder: self.recording_path.map(EventRecorder::new),
+                dedupe: self
+                    .dedupe
+                    .map(|(key, store)| Deduplicator::new(key, store)),
+                dry_run,
+                inflight: AtomicUsize::new(0),
+                counters: Counters::default(),
+                metrics,
+            }),
+            concurrency: self.concurrency,
+            ordering_key: self.ordering_key,
+            tasks: TaskTracker::new(),
+            cancel: CancellationToken::new(),
+            drain_deadline: self.drain_deadline,
+            drain_policy: self.drain_policy,
+            metrics_addr: self.metrics_addr,
+            admin_addr: self.admin_addr,
+            events: Some(event_tx),
+            event_rx: Some(event_rx),
+            batches,
+            policies,
+            lanes: Lanes::new(self.starvation_limit),
+        };
+        // The triggers are launched when the agent runs.
+        let handle = agent.handle();
+        for (trigger, policy, priority) in self.triggers {
+            agent
+                .trigger_board
+                .add(Arc::from(trigger), policy, priority, &handle)?;
+        }
+        Ok(agent)
     }
 }
 
 impl Agent {
-    /// Runs the agent.
-    pub async fn run(mut self) -> Result<(), AgentError> {
-        let (_, event_rx, shutdown_tx, trigger_handles) = self.launch_triggers().await;
+    /// Returns a handle to inject events, manage triggers and dead letters while the agent runs.
+    pub fn handle(&self) -> AgentHandle {
+        let events = self
+            .events
+            .clone()
+            .expect("the event queue is open until run");
+        self.handle_for(events)
+    }
+
+    fn handle_for(&self, events: Intake) -> AgentHandle {
+        AgentHandle {
+            events,
+            dead_letters: self.core.dead_letters.clone(),
+            journal: self.core.journal.clone(),
+            triggers: self.trigger_board.clone(),
+            metrics: self.core.metrics.clone(),
+            control: self.control.clone(),
+            recorder: self.core.recorder.clone(),
+        }
+    }
+
+    /// Replaces the shutdown handler given to the builder.
+    pub(crate) fn set_shutdown_handler(&mut self, handler: Box<dyn Shutdown>) {
+        self.shutdown_handler = handler;
+    }
+
+    /// Runs the agent on a new task, and returns a handle to it with the task.
+    ///
+    /// The handle keeps the event loop waiting for events until it is dropped, see
+    /// [`Agent::run`].
+    pub fn spawn(self) -> (AgentHandle, JoinHandle<Result<ShutdownReport, AgentError>>) {
+        let handle = self.handle();
+        (handle, tokio::spawn(self.run()))
+    }
+
+    /// Runs the agent until it is shut down, and reports what happened to its events.
+    ///
+    /// The event loop ends when the shutdown handler fires, or once every trigger
+    /// and every [`AgentHandle`] is gone. On a shutdown signal the agent stops
+    /// accepting events and stops the triggers, while the events it holds are drained
+    /// according to the [`DrainPolicy`]. Those still running at the drain deadline are
+    /// cancelled.
+    pub async fn run(mut self) -> Result<ShutdownReport, AgentError> {
+        let event_rx = self.event_rx.take().expect("the agent runs only once");
+        let events = self.events.take().expect("the agent runs only once");
+        let listeners_stop = CancellationToken::new();
+        if let Some(addr) = self.metrics_addr {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            tokio::spawn(crate::metrics::serve(
+                listener,
+                self.core.metrics.clone(),
+                listeners_stop.clone(),
+            ));
+        }
+        if let Some(addr) = self.admin_addr {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            let handle = self.handle_for(events.clone());
+            tokio::spawn(admin::serve(listener, handle, listeners_stop.clone()));
+        }
+        let handle = self.handle_for(events);
+        let replay = match &self.core.journal {
+            Some(journal) => journal.take_replay().await?,
+            None => Vec::new(),
+        };
+        self.launch_triggers(handle, replay);
+        self.control.set_ready(true);
         let mut shutdown_handler = self.shutdown_handler.clone();
 
-        tokio::select! {
-            _ = self.event_loop(event_rx) => {
+        let stop = CancellationToken::new();
+        let batches = std::mem::take(&mut self.batches);
+        let policies = std::mem::take(&mut self.policies);
+        let lanes = std::mem::take(&mut self.lanes);
+        let event_loop = self.event_loop(event_rx, batches, policies, lanes, stop.clone());
+        tokio::pin!(event_loop);
+
+        let signalled = tokio::select! {
+            _ = &mut event_loop => {
                 info!("Event loop completed normally");
+                false
             },
             _ = shutdown_handler.wait_for_signal() => {
                 info!("External shutdown signal triggered termination");
+                true
             }
-        }
+            _ = self.control.shutdown_requested() => {
+                info!("Shutdown requested through an agent handle");
+                true
+            }
+        };
+        self.control.set_ready(false);
 
-        self.shutdown_triggers(shutdown_tx, trigger_handles).await;
+        if signalled {
+            let deadline = tokio::time::Instant::now() + self.drain_deadline;
+            // Events sent from now on are refused: their acks are dropped and, with a
+            // journal, they are processed at the next start.
+            stop.cancel();
+            let drain = async { tokio::join!(self.trigger_board.stop(), &mut event_loop) };
+            if tokio::time::timeout_at(deadline, drain).await.is_err() {
+                warn!(
+                    inflight = self.core.inflight.load(Ordering::SeqCst),
+                    "Drain deadline elapsed, cancelling the events still in flight"
+                );
+                self.cancel.cancel();
+                event_loop.await;
+            }
+        } else {
+            self.trigger_board.stop().await;
+        }
+        self.tasks.close();
+        self.tasks.wait().await;
+        listeners_stop.cancel();
 
-        info!("Agent has shut down gracefully");
-        Ok(())
+        let report = self.core.counters.report();
+        info!(
+            processed = report.processed,
+            failed = report.failed,
+            dead_lettered = report.dead_lettered,
+            abandoned = report.abandoned,
+            duplicates = report.duplicates,
+            dropped = report.dropped,
+            "Agent has shut down gracefully"
+        );
+        Ok(report)
     }
 
     /// The main event loop for the agent.
-    async fn event_loop(&mut self, mut event_rx: mpsc::Receiver<TEvent>) {
-        info!("Agent event loop started, waiting for events");
-        while let Some(event) = event_rx.recv().await {
-            info!(event_name = %event.name, "Received event");
-
-            self.process_single_event(event).await;
+    ///
+    /// Each event is processed in its own task, scheduled by the [`Dispatcher`]
+    /// according to the concurrency limit and the ordering key.
+    ///
+    /// While the agent is paused, queued events wait in the queue. Once `stop` is
+    /// cancelled, no new event is accepted and the queued ones are processed or
+    /// dropped according to the drain policy, even if the agent is paused.
+    async fn event_loop(
+        &self,
+        mut event_rx: Queues,
+        mut batches: BatchTable,
+        mut policies: PolicyTable,
+        lanes: Lanes,
+        stop: CancellationToken,
+    ) {
+        info!(
+            concurrency = self.concurrency,
+            ordered = self.ordering_key.is_some(),
+            batched = !batches.is_empty(),
+            paced = !policies.is_empty(),
+            "Agent event loop started, waiting for events"
+        );
+        let mut dispatcher = Dispatcher::new(
+            self.core.clone(),
+            self.tasks.clone(),
+            self.cancel.clone(),
+            self.concurrency,
+            self.ordering_key.clone(),
+            lanes,
+        );
+        let mut stopping = false;
+        let mut paused_rx = self.control.subscribe_paused();
+        let mut paused = *paused_rx.borrow_and_update();
+        loop {
+            // Whatever changed the queues in the last turn, the gauge is set here.
+            self.core
+                .metrics
+                .set_queue_depth(event_rx.len() + batches.held() + policies.held());
+            let room = dispatcher.room();
+            let next_flush = batches.next_flush();
+            let next_release = policies.next_release();
+            tokio::select! {
+                // The stop signal is checked before the queue, so that no event is
+                // accepted once it is given.
+                biased;
+                Some(key) = dispatcher.next_completion() => dispatcher.complete(key),
+                _ = stop.cancelled(), if !stopping => {
+                    stopping = true;
+                    event_rx.close();
+                    if self.drain_policy == DrainPolicy::AbandonQueue {
+                        let mut abandoned = dispatcher.abandon_queued();
+                        abandoned += policies.drain().iter().map(Envelope::events).sum::<usize>();
+                        // Dropping the event of a batch refuses its events.
+                        for batch in batches.drain() {
+                            abandoned += self.seal(batch).events();
+                        }
+                        while let Some(envelope) = event_rx.try_recv() {
+                            abandoned += envelope.events();
+                        }
+                        info!(abandoned, "Abandoning queued events");
+                        self.core.counters.abandoned.fetch_add(abandoned, Ordering::SeqCst);
+                        break;
+                    }
+                    info!("Processing queued events before shutting down");
+                    // The events held back by a policy are not held any longer.
+                    for envelope in policies.drain() {
+                        dispatcher.dispatch(envelope);
+                    }
+                }
+                // A pause holds back the queued events, except while draining.
+                Ok(()) = paused_rx.changed(), if !stopping => {
+                    paused = *paused_rx.borrow_and_update();
+                    info!(paused, "Event loop pause switched");
+                }
+                _ = tokio::time::sleep_until(next_flush.unwrap_or_else(tokio::time::Instant::now)),
+                    if next_flush.is_some() && !paused && !stopping =>
+                {
+                    let now = tokio::time::Instant::now();
+                    for batch in batches.flush(now) {
+                        let envelope = self.seal(batch);
+                        self.pace(&mut policies, &mut dispatcher, envelope, now);
+                    }
+                }
+                _ = tokio::time::sleep_until(next_release.unwrap_or_else(tokio::time::Instant::now)),
+                    if next_release.is_some() && !paused && !stopping =>
+                {
+                    for envelope in policies.release(tokio::time::Instant::now()) {
+                        dispatcher.dispatch(envelope);
+                    }
+                }
+                event = event_rx.recv(room), if !paused || stopping => match event {
+                    Some(envelope) => {
+                        info!(
+                            event_name = %envelope.event.name,
+                            attempt = envelope.attempt,
+                            "Received event"
+                        );
+                        // The events of a batch are deduplicated before they join it.
+                        let (envelope, key) = if batches.is_batched(&envelope.event.name) {
+                            match self.core.claim(envelope).await {
+                                Some(claimed) => claimed,
+                                None => continue,
+                            }
+                        } else {
+                            (envelope, None)
+                        };
+                        let now = tokio::time::Instant::now();
+                        match batches.collect(envelope, key, now) {
+                            Collected::Pass(envelope) => {
+                                // While draining, the queued events are no longer paced.
+                                if stopping {
+                                    dispatcher.dispatch(envelope);
+                                } else {
+                                    self.pace(&mut policies, &mut dispatcher, envelope, now);
+                                }
+                            }
+                            Collected::Held => {}
+                            Collected::Full(batch) => {
+                                let envelope = self.seal(batch);
+                                if stopping {
+                                    dispatcher.dispatch(envelope);
+                                } else {
+                                    self.pace(&mut policies, &mut dispatcher, envelope, now);
+                                }
+                            }
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+        // The batches still being collected are complete: no event comes anymore.
+        for batch in batches.drain() {
+            dispatcher.dispatch(self.seal(batch));
+        }
+        // No event is accepted anymore, but events queued behind a busy key still have to run.
+        while !dispatcher.is_idle() {
+            if let Some(key) = dispatcher.next_completion().await {
+                dispatcher.complete(key);
+            }
         }
         debug!("Event loop terminated - no more events to process");
     }
 
+    /// Applies the event policies to an event, and dispatches it if it passes.
+    fn pace(
+        &self,
+        policies: &mut PolicyTable,
+        dispatcher: &mut Dispatcher,
+        envelope: Envelope,
+        now: tokio::time::Instant,
+    ) {
+        match policies.admit(envelope, now) {
+            Admission::Pass(envelope) => dispatcher.dispatch(envelope),
+            Admission::Held => {}
+            Admission::Discard(envelope, discard) => {
+                let core = self.core.clone();
+                self.tasks
+                    .spawn(async move { core.discard(envelope, discard).await });
+            }
+        }
+    }
+
+    /// Builds the event of a batch, and settles its members once it is processed.
+    fn seal(&self, batch: Sealed) -> Envelope {
+        debug!(events = batch.len(), "Batch complete");
+        let (envelope, settlement) = batch.seal();
+        let core = self.core.clone();
+        self.tasks
+            .spawn(async move { core.settle_batch(settlement).await });
+        envelope
+    }
+
+    /// Launches the triggers for the agent, each under its own supervisor.
+    ///
+    /// The entries replayed from the journal are sent first, through `handle`, and
+    /// the triggers are launched once they are all queued. The queue closes once the
+    /// triggers and the handles are all gone.
+    fn launch_triggers(&self, handle: AgentHandle, replay: Vec<Envelope>) {
+        let board = self.trigger_board.clone();
+        tokio::spawn(async move {
+            for envelope in replay {
+                if handle.send(envelope).await.is_err() {
+                    return;
+                }
+            }
+            board.start(&handle, CancellationToken::new());
+        });
+    }
+}
+
+/// How an event that did not fail was handled.
+enum Handled {
+    /// The response of the model was handled by every response handler.
+    Responded,
+    /// The event was left unprocessed on purpose, for the given reason.
+    Skipped(String),
+    /// The prompt was recorded in place of prompting the model, in dry-run mode.
+    Rehearsed,
+}
+
+impl AgentCore {
     /// Processes a single event.
-    async fn process_single_event(&mut self, event: TEvent) {
-        let provider_client = &mut self.model;
-        let template = &self.prompt_template;
-        let json_context = &json!(event);
-        match self.handlebars.render_template(template, json_context) {
-            Ok(prompt) => {
-                debug!("Prompt: {}", prompt);
-                self.inflight.fetch_add(1, Ordering::Relaxed);
-                let response = provider_client.prompt(prompt).await;
-                self.inflight.fetch_sub(1, Ordering::Relaxed);
-                match response {
-                    Ok(response) => info!("here we are: {}", response),
-                    Err(x) => error!("troubles here {}", x),
+    async fn process_single_event(&self, envelope: Envelope) {
+        let _inflight = InflightGuard::new(&self.inflight);
+        // The events of a batch were deduplicated one by one, before they joined it.
+        let (envelope, key) = if envelope.batch.is_some() {
+            (envelope, None)
+        } else {
+            match self.claim(envelope).await {
+                Some(claimed) => claimed,
+                None => return,
+            }
+        };
+        let Envelope {
+            mut event,
+            attempt,
+            journal_id,
+            ack,
+            ..
+        } = envelope;
+
+        let outcome = self.process(&mut event).await;
+        self.counters.processed.fetch_add(1, Ordering::SeqCst);
+        if let (Some(dedupe), Some(key)) = (&self.dedupe, key) {
+            // A dry-run event is not processed, so its key is not remembered.
+            let processed = matches!(outcome, Ok(Handled::Responded | Handled::Skipped(_)));
+            dedupe.release(key, processed).await;
+        }
+        if let Err((stage, reason)) = &outcome {
+            self.counters.failed.fetch_add(1, Ordering::SeqCst);
+            self.dead_letter(event, attempt, *stage, reason.clone())
+                .await;
+        }
+
+        // A dry-run event stays in the journal, to be processed for real later.
+        if !matches!(outcome, Ok(Handled::Rehearsed)) {
+            self.complete_journal(journal_id).await;
+        }
+
+        match outcome {
+            Ok(Handled::Responded) => ack.ack(),
+            Ok(Handled::Skipped(reason)) => ack.skip(reason),
+            // Dropping the ack releases the event without an outcome.
+            Ok(Handled::Rehearsed) => drop(ack),
+            Err((_, reason)) => ack.nack(reason),
+        }
+    }
+
+    /// Checks an event against the processed keys, and holds its key if it is to be
+    /// processed.
+    ///
+    /// Returns the event with its key, or `None` if it was settled as a duplicate.
+    async fn claim(&self, envelope: Envelope) -> Option<(Envelope, Option<String>)> {
+        let Some(dedupe) = &self.dedupe else {
+            return Some((envelope, None));
+        };
+        match dedupe.claim(&envelope.event).await {
+            Claim::Fresh(key) => Some((envelope, key)),
+            Claim::Processed(key) => {
+                info!(event_name = %envelope.event.name, key = %key, "Event already processed, skipping it");
+                self.skip_duplicate(envelope.journal_id).await;
+                envelope.ack.ack();
+                None
+            }
+            Claim::InFlight(key) => {
+                info!(event_name = %envelope.event.name, key = %key, "Event already being processed, skipping it");
+                self.skip_duplicate(envelope.journal_id).await;
+                envelope
+                    .ack
+                    .nack("an event with the same key is being processed");
+                None
+            }
+        }
+    }
+
+    /// Settles the events of a batch with the outcome of the batch event, and
+    /// remembers their keys if it was processed.
+    ///
+    /// If the batch event is dropped before it is processed, its events are refused,
+    /// and left unfinished in the journal; in dry-run mode, they are released without
+    /// an outcome, like the other events.
+    async fn settle_batch(&self, settlement: Settlement) {
+        let outcome = settlement.outcome.await.ok();
+        let processed = matches!(outcome, Some(Outcome::Ack | Outcome::Skipped(_)));
+        for member in settlement.members {
+            if let (Some(dedupe), Some(key)) = (&self.dedupe, member.key) {
+                dedupe.release(key, processed).await;
+            }
+            match &outcome {
+                Some(outcome) => {
+                    self.complete_journal(member.journal_id).await;
+                    match outcome {
+                        Outcome::Ack => member.ack.ack(),
+                        Outcome::Nack(reason) => member.ack.nack(reason.clone()),
+                        Outcome::Skipped(reason) => member.ack.skip(reason.clone()),
+                    }
                 }
+                None if self.dry_run.is_some() => drop(member.ack),
+                None => member.ack.nack("the batch was not processed"),
             }
-            Err(e) => {
-                error!(error = %e, "Failed to render prompt template");
+        }
+    }
+
+    /// Settles an event set aside by its policy, without processing it.
+    async fn discard(&self, envelope: Envelope, discard: Discard) {
+        match discard {
+            Discard::Throttled => {
+                info!(event_name = %envelope.event.name, "Event throttled, dropping it")
+            }
+            Discard::Superseded => {
+                debug!(event_name = %envelope.event.name, "Event superseded by a later one under a debounce")
+            }
+            Discard::Overflowed => {
+                warn!(event_name = %envelope.event.name, "Too many events held by the event policy, dropping it")
             }
         }
+        self.refuse(envelope, discard.reason()).await;
     }
 
-    /// Launches the triggers for the agent.
-    async fn launch_triggers(
-        &self,
-    ) -> (
-        mpsc::Sender<TEvent>,
-        mpsc::Receiver<TEvent>,
-        broadcast::Sender<()>,
-        Vec<JoinHandle<()>>,
-    ) {
-        let (event_tx, event_rx) = mpsc::channel(100);
-        let (shutdown_tx, _) = broadcast::channel(1);
-        let mut trigger_handles = Vec::new();
-
-        info!(trigger_count = self.triggers.len(), "Launching triggers");
-        for (index, trigger) in self.triggers.iter().enumerate() {
-            let shutdown_rx = shutdown_tx.subscribe();
-            match trigger.launch(event_tx.clone(), shutdown_rx).await {
-                Ok(handle) => {
-                    debug!(trigger_index = index, "Trigger launched successfully");
-                    trigger_handles.push(handle);
+    /// Drops an event without processing it, and refuses it so that its trigger can
+    /// emit it again.
+    async fn refuse(&self, envelope: Envelope, reason: &str) {
+        self.counters.dropped.fetch_add(1, Ordering::SeqCst);
+        self.complete_journal(envelope.journal_id).await;
+        envelope.ack.nack(reason);
+    }
+
+    /// Counts an event skipped as a duplicate, and marks it done in the journal.
+    async fn skip_duplicate(&self, journal_id: Option<u64>) {
+        self.counters.duplicates.fetch_add(1, Ordering::SeqCst);
+        self.complete_journal(journal_id).await;
+    }
+
+    /// Marks an event done in the journal, if it was journaled.
+    async fn complete_journal(&self, journal_id: Option<u64>) {
+        if let (Some(journal), Some(id)) = (&self.journal, journal_id)
+            && let Err(e) = journal.complete(id).await
+        {
+            error!(journal_id = id, error = %e, "Failed to mark the event done in the journal");
+        }
+    }
+
+    /// Renders the prompt for an event, prompts the model and passes on the outcome.
+    ///
+    /// Returns the stage and the reason of the failure if the event failed.
+    async fn process(&self, event: &mut TEvent) -> Result<Handled, (FailureStage, String)> {
+        let started = Instant::now();
+        for observer in &self.observers {
+            if let Verdict::Veto(reason) = observer.on_event(event).await {
+                info!(event_name = %event.name, reason = %reason, "Event vetoed by an observer");
+                return Ok(Handled::Skipped(reason));
+            }
+        }
+        let event = &*event;
+        let Some(route) = self.routes.resolve(&event.name) else {
+            warn!(event_name = %event.name, "No route matches the event, skipping it");
+            return Ok(Handled::Skipped("no route matches the event".to_string()));
+        };
+        let json_context = &json!(event);
+        let mut prompt = match self.handlebars.render(&route.template, json_context) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                error!(error = %e, "Failed to render prompt template");
+                self.metrics.render_failed(&route.name);
+                let timing = Timing {
+                    render: started.elapsed(),
+                    total: started.elapsed(),
+                    ..Timing::default()
+                };
+                for observer in &self.observers {
+                    observer
+                        .on_error(event, &ProcessingError::Render(&e), &timing)
+                        .await;
+                }
+                return Err((FailureStage::Render, e.to_string()));
+            }
+        };
+        let render = started.elapsed();
+        for observer in &self.observers {
+            if let Verdict::Veto(reason) = observer.on_prompt_rendered(event, &mut prompt).await {
+                info!(event_name = %event.name, reason = %reason, "Prompt vetoed by an observer");
+                return Ok(Handled::Skipped(reason));
+            }
+        }
+        debug!(route = %route.name, "Prompt: {}", prompt);
+        if let Some(recorder) = &self.dry_run {
+            recorder.record(event, &prompt).await;
+            return Ok(Handled::Rehearsed);
+        }
+        let prompted = Instant::now();
+        let result = route.model.prompt(prompt).await;
+        let timing = Timing {
+            render,
+            llm: prompted.elapsed(),
+            total: started.elapsed(),
+        };
+        self.metrics
+            .llm_call(&route.name, timing.llm, result.is_ok());
+        match result {
+            Ok(response) => {
+                for observer in &self.observers {
+                    observer.on_response(event, &response, &timing).await;
                 }
-                Err(e) => {
-                    error!(trigger_index = index, error = %e, "Failed to launch trigger");
+                self.handle_response(event, &response)
+                    .await
+                    .map_err(|reason| (FailureStage::Respond, reason))?;
+                Ok(Handled::Responded)
+            }
+            Err(e) => {
+                for observer in &self.observers {
+                    observer
+                        .on_error(event, &ProcessingError::Model(&e), &timing)
+                        .await;
                 }
+                self.handle_error(event, &e).await;
+                Err((FailureStage::Prompt, e.to_string()))
             }
         }
-        info!(
-            launched_count = trigger_handles.len(),
-            "All triggers launched"
-        );
+    }
 
-        (event_tx, event_rx, shutdown_tx, trigger_handles)
+    /// Stores a failed event in the dead-letter store, if the agent has one.
+    async fn dead_letter(&self, event: TEvent, attempt: u32, stage: FailureStage, reason: String) {
+        let Some(store) = &self.dead_letters else {
+            return;
+        };
+        let letter = DeadLetter::new(event, stage, reason, attempt);
+        let (id, event_name) = (letter.id.clone(), letter.event.name.clone());
+        match store.push(letter).await {
+            Ok(()) => {
+                self.counters.dead_lettered.fetch_add(1, Ordering::SeqCst);
+                warn!(id = %id, event_name = %event_name, stage = %stage, "Event dead-lettered")
+            }
+            Err(e) => error!(event_name = %event_name, error = %e, "Failed to store dead letter"),
+        }
     }
 
-    /// Shuts down the triggers for the agent.
-    async fn shutdown_triggers(
-        &self,
-        shutdown_tx: broadcast::Sender<()>,
-        trigger_handles: Vec<JoinHandle<()>>,
-    ) {
-        info!(
-            trigger_count = trigger_handles.len(),
-            "Sending shutdown signal to all triggers"
-        );
-        let _ = shutdown_tx.send(());
-        debug!("Waiting for triggers to terminate");
-        for (index, handle) in trigger_handles.into_iter().enumerate() {
-            if let Err(e) = handle.await {
-                error!(
-                    trigger_index = index,
-                    error = %e,
-                    "Error waiting for trigger to terminate"
-                );
-            } else {
-                debug!(trigger_index = index, "Trigger terminated successfully");
+    /// Passes the response to every response handler.
+    ///
+    /// Returns the reason of the first failure if a handler failed; the handlers after
+    /// it still get the response.
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), String> {
+        if self.response_handlers.is_empty() {
+            info!(event_name = %event.name, response = %response, "Received response");
+            return Ok(());
+        }
+        let mut failure = None;
+        for handler in &self.response_handlers {
+            if let Err(e) = handler.handle_response(event, response).await {
+                error!(event_name = %event.name, error = %e, "Response handler failed");
+                failure.get_or_insert_with(|| format!("a response handler failed: {e}"));
             }
         }
-        info!("All triggers have been shut down");
-        let residual = self.inflight.load(Ordering::Relaxed);
-        if residual != 0 {
-            info!("residual inflight process: {}", residual);
-            tokio::time::sleep(Duration::from_secs(10)).await;
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Passes the error to every error handler.
+    async fn handle_error(&self, event: &TEvent, llm_error: &LLMError) {
+        error!(event_name = %event.name, error = %llm_error, "Failed to prompt the model");
+        for handler in &self.error_handlers {
+            if let Err(e) = handler.handle_error(event, llm_error).await {
+                error!(event_name = %event.name, error = %e, "Error handler failed");
+            }
         }
-        info!(
-            "waited for inflight request to complete, killed {}",
-            self.inflight.load(Ordering::Relaxed)
-        );
     }
 }
 
@@ -309,16 +1373,25 @@ impl Agent {
 mod tests {
     use super::*;
     use crate::llm::{RetryConfig, RetryStrategy};
+    use crate::testing::{AgentHarness, ManualTrigger, ScriptedLLM};
+    use crate::triggers::event::AckedEvent;
     use std::time::Duration;
+    use tokio::sync::{broadcast, mpsc};
 
-    // Mock LLM for testing
-    struct MockLLM;
+    // Answers every prompt with the prompt itself.
+    fn echo() -> ScriptedLLM {
+        ScriptedLLM::new().respond_with(|prompt| Ok(prompt.to_string()))
+    }
 
-    #[async_trait::async_trait]
-    impl LLM for MockLLM {
-        async fn prompt(&mut self, _prompt: String) -> Result<String, crate::llm::LLMError> {
-            Ok("test response".to_string())
-        }
+    // Fails every prompt that mentions "fail", and answers every other one.
+    fn picky() -> ScriptedLLM {
+        ScriptedLLM::new().respond_with(|prompt| {
+            if prompt.contains("fail") {
+                Err("refused".to_string())
+            } else {
+                Ok(format!("answer to {prompt}"))
+            }
+        })
     }
 
     #[test]
@@ -356,9 +1429,8 @@ mod tests {
     #[test]
     fn test_agent_builder_build_applies_default_retry() {
         // This test verifies that build() applies default retry when none is specified
-        let mock_llm = MockLLM;
         let builder = AgentBuilder::new()
-            .with_model(Box::new(mock_llm))
+            .with_model(Box::new(echo()))
             .with_prompt_template("test template".to_string());
 
         // Should not panic and should build successfully
@@ -367,6 +1439,1197 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_agent_builder_with_concurrency() {
+        assert_eq!(AgentBuilder::new().concurrency, 1);
+        let builder = AgentBuilder::new().with_concurrency(4);
+        assert_eq!(builder.concurrency, 4);
+    }
+
+    #[test]
+    fn test_agent_builder_rejects_zero_concurrency() {
+        let result = AgentBuilder::new()
+            .with_model(Box::new(echo()))
+            .with_prompt_template("test template".to_string())
+            .with_concurrency(0)
+            .build();
+        assert!(matches!(result, Err(AgentError::BuildError(_))));
+    }
+
+    // Emits one event per name and payload as soon as it is launched.
+    struct BurstTrigger(Vec<(&'static str, serde_json::Value)>);
+
+    #[async_trait::async_trait]
+    impl Trigger for BurstTrigger {
+        async fn launch(
+            &self,
+            tx: mpsc::Sender<TEvent>,
+            mut shutdown_rx: broadcast::Receiver<()>,
+        ) -> Result<JoinHandle<()>, crate::triggers::TriggerError> {
+            let events = self.0.clone();
+            Ok(tokio::spawn(async move {
+                for (name, payload) in events {
+                    let event = TEvent {
+                        name: name.to_string(),
+                        payload: Some(payload),
+                    };
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = shutdown_rx.recv().await;
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_processes_events_concurrently() {
+        let llm = echo().with_latency(Duration::from_millis(100));
+
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .with_prompt_template("{{name}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(vec![("Burst", json!({})); 8])))
+            .with_concurrency(4)
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(400),
+            ))
+            .build()
+            .unwrap();
+
+        agent.run().await.unwrap();
+
+        assert_eq!(llm.max_in_flight(), 4);
+        assert_eq!(llm.prompts().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_agent_orders_events_by_key() {
+        let llm = echo().with_latency(Duration::from_millis(50));
+        let handler = RecordingHandler::default();
+        let payloads = vec![
+            ("Chat", json!({"chat": "a", "seq": 1})),
+            ("Chat", json!({"chat": "a", "seq": 2})),
+            ("Chat", json!({"chat": "b", "seq": 1})),
+            ("Chat", json!({"chat": "a", "seq": 3})),
+            ("Chat", json!({"chat": "b", "seq": 2})),
+        ];
+
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .with_prompt_template("{{payload.chat}}{{payload.seq}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(payloads)))
+            .add_response_handler(handler.clone())
+            .with_concurrency(4)
+            .with_ordering_key(crate::triggers::event::payload_key("chat"))
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(400),
+            ))
+            .build()
+            .unwrap();
+
+        agent.run().await.unwrap();
+
+        let log = handler.0.lock().unwrap();
+        let position = |seq: &str| {
+            log.iter()
+                .position(|e| *e == format!("Chat -> {seq}"))
+                .unwrap()
+        };
+        assert_eq!(log.len(), 5);
+        // Same key: the events are answered in order, one at a time.
+        assert!(position("a1") < position("a2"));
+        assert!(position("a2") < position("a3"));
+        assert!(position("b1") < position("b2"));
+        assert_eq!(llm.max_in_flight(), 2);
+        // Different keys: the first events of both chats run side by side.
+        assert!(position("b1") < position("a2"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_keeps_accepting_events_while_a_key_is_backed_up() {
+        use crate::triggers::event::Outcome;
+
+        let llm = ScriptedLLM::new()
+            .respond("ok")
+            .respond("ok")
+            .with_latency(Duration::from_millis(500));
+        let trigger = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_model(Box::new(llm))
+                .with_prompt_template("{{payload.chat}}".to_string())
+                .add_trigger(Box::new(trigger.clone()))
+                .with_concurrency(2)
+                .with_ordering_key(crate::triggers::event::payload_key("chat"))
+                .with_drain_policy(DrainPolicy::AbandonQueue),
+        )
+        .unwrap();
+
+        // One event of the hot chat runs, 100 wait behind it and 50 are refused.
+        for _ in 0..151 {
+            trigger.fire("Chat", json!({ "chat": "hot" }));
+        }
+        let other = TEvent {
+            name: "Chat".to_string(),
+            payload: Some(json!({ "chat": "other" })),
+        };
+        let outcome = tokio::time::timeout(Duration::from_secs(2), trigger.process(other)).await;
+        assert_eq!(outcome, Ok(Some(Outcome::Ack)));
+
+        let report = harness.shutdown().await.unwrap();
+        assert_eq!(report.dropped, 50);
+    }
+
+    #[test]
+    fn test_agent_builder_rejects_invalid_templates() {
+        let result = AgentBuilder::new()
+            .with_model(Box::new(echo()))
+            .with_prompt_template("fine {{name}}".to_string())
+            .add_route(Route::new("Telegram*", "broken {{#if}}"))
+            .build();
+        assert!(matches!(result, Err(AgentError::TemplateError(_))));
+    }
+
+    #[test]
+    fn test_agent_builder_route_needs_a_model() {
+        let result = AgentBuilder::new()
+            .add_route(Route::new("Telegram*", "{{payload.text}}"))
+            .build();
+        assert!(matches!(result, Err(AgentError::BuildError(_))));
+
+        let result = AgentBuilder::new()
+            .add_route(Route::new("Telegram*", "{{payload.text}}").with_model(Box::new(echo())))
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_agent_routes_events_by_name() {
+        let default_llm = echo();
+        let telegram_llm = echo();
+        let events = vec![
+            ("TelegramMessage", json!({"text": "hi"})),
+            ("Tick", json!({})),
+        ];
+
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(default_llm.clone()))
+            .with_prompt_template("default {{name}}".to_string())
+            .add_route(
+                Route::new("Telegram*", "telegram {{payload.text}}")
+                    .with_model(Box::new(telegram_llm.clone()))
+                    .with_retry_config(RetryConfig::disabled()),
+            )
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(300),
+            ))
+            .build()
+            .unwrap();
+
+        agent.run().await.unwrap();
+
+        assert_eq!(telegram_llm.prompts(), ["telegram hi"]);
+        assert_eq!(default_llm.prompts(), ["default Tick"]);
+    }
+
+    #[tokio::test]
+    async fn test_agent_reports_unrouted_events_as_skipped() {
+        use crate::triggers::event::Outcome;
+
+        let trigger = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .add_route(
+                    Route::new("Ask", "{{payload.q}}")
+                        .with_model(Box::new(ScriptedLLM::new().respond("ok"))),
+                )
+                .add_trigger(Box::new(trigger.clone())),
+        )
+        .unwrap();
+
+        let tick = TEvent {
+            name: "Tick".to_string(),
+            payload: None,
+        };
+        assert_eq!(
+            trigger.process(tick).await,
+            Some(Outcome::Skipped("no route matches the event".to_string()))
+        );
+        harness.shutdown().await.unwrap();
+    }
+
+    // Collects every response and error it receives.
+    #[derive(Clone, Default)]
+    struct RecordingHandler(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl ResponseHandler for RecordingHandler {
+        async fn handle_response(
+            &self,
+            event: &TEvent,
+            response: &str,
+        ) -> Result<(), crate::handlers::HandlerError> {
+            let mut log = self.0.lock().unwrap();
+            log.push(format!("{} -> {}", event.name, response));
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ErrorHandler for RecordingHandler {
+        async fn handle_error(
+            &self,
+            event: &TEvent,
+            error: &LLMError,
+        ) -> Result<(), crate::handlers::HandlerError> {
+            let mut log = self.0.lock().unwrap();
+            log.push(format!("{} !! {}", event.name, error));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_passes_responses_and_errors_to_handlers() {
+        let handler = RecordingHandler::default();
+        let events = vec![("Ask", json!({"q": "ok"})), ("Ask", json!({"q": "fail"}))];
+
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .add_response_handler(handler.clone())
+            .add_error_handler(handler.clone())
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap();
+
+        agent.run().await.unwrap();
+
+        assert_eq!(
+            *handler.0.lock().unwrap(),
+            vec![
+                "Ask -> answer to ok",
+                "Ask !! Failed to prompt the model: refused"
+            ]
+        );
+    }
+
+    // Vetoes spam and secret prompts, rewrites the others and records what it sees.
+    #[derive(Clone, Default)]
+    struct EditingObserver(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl AgentObserver for EditingObserver {
+        async fn on_event(&self, event: &mut TEvent) -> Verdict {
+            if event.name == "Spam" {
+                return Verdict::Veto("spam".to_string());
+            }
+            event.name = event.name.to_uppercase();
+            Verdict::Continue
+        }
+
+        async fn on_prompt_rendered(&self, _event: &TEvent, prompt: &mut String) -> Verdict {
+            if prompt.contains("secret") {
+                return Verdict::Veto("secret".to_string());
+            }
+            prompt.push_str(" please");
+            Verdict::Continue
+        }
+
+        async fn on_response(&self, event: &TEvent, response: &str, timing: &Timing) {
+            assert!(timing.total >= timing.llm);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} -> {}", event.name, response));
+        }
+
+        async fn on_error(&self, event: &TEvent, error: &ProcessingError<'_>, _timing: &Timing) {
+            let stage = error.stage();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} !! {stage}", event.name));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_calls_observers_around_each_event() {
+        let observer = EditingObserver::default();
+        let handler = RecordingHandler::default();
+        let events = vec![
+            ("Ask", json!({"q": "ok"})),
+            ("Spam", json!({"q": "buy"})),
+            ("Ask", json!({"q": "secret"})),
+            ("Ask", json!({"q": "fail"})),
+        ];
+
+        let report = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .add_observer(observer.clone())
+            .add_response_handler(handler.clone())
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec!["ASK -> answer to ok please", "ASK !! prompt"]
+        );
+        assert_eq!(
+            *handler.0.lock().unwrap(),
+            vec!["ASK -> answer to ok please"]
+        );
+        // Vetoed events are processed without failing.
+        assert_eq!(report.processed, 4);
+        assert_eq!(report.failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_tells_observers_about_render_failures() {
+        let observer = EditingObserver::default();
+        let report = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{verbatim}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(vec![("Ask", json!({"q": "ok"}))])))
+            .add_observer(observer.clone())
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(*observer.0.lock().unwrap(), vec!["ASK !! render"]);
+        assert_eq!(report.failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_records_prompts_without_prompting_the_model_in_dry_run() {
+        let handler = RecordingHandler::default();
+        let (sink, mut prompts) = mpsc::unbounded_channel();
+        let events = vec![
+            ("Ask", json!({"q": "ok"})),
+            ("Spam", json!({"q": "buy"})),
+            ("Tell", json!({"q": "fail"})),
+        ];
+
+        let report = AgentBuilder::new()
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_route(Route::new("TELL", "tell {{payload.q}}").with_model(Box::new(picky())))
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .add_observer(EditingObserver::default())
+            .add_response_handler(handler.clone())
+            .add_error_handler(handler.clone())
+            .dry_run(sink)
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        let mut recorded = Vec::new();
+        while let Ok((event, prompt)) = prompts.try_recv() {
+            recorded.push(format!("{} -> {}", event.name, prompt));
+        }
+        // The prompts are recorded as the observers left them.
+        assert_eq!(
+            recorded,
+            vec!["ASK -> ok please", "TELL -> tell fail please"]
+        );
+        assert!(handler.0.lock().unwrap().is_empty());
+        assert_eq!(report.processed, 3);
+        assert_eq!(report.failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_agent_never_acks_events_in_dry_run() {
+        // Stands in for a trigger that marks its messages as read once acked.
+        let trigger = ManualTrigger::new();
+        let (sink, mut prompts) = mpsc::unbounded_channel();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_prompt_template("{{payload.q}}".to_string())
+                .add_trigger(Box::new(trigger.clone()))
+                .with_deduplication(
+                    crate::triggers::event::payload_key("q"),
+                    crate::dedupe::MemoryDedupeStore::new(Duration::from_secs(60)),
+                )
+                .dry_run(sink),
+        )
+        .unwrap();
+
+        let email = TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({"q": "hi"})),
+        };
+        // The key of a rehearsed event is not remembered: it is rehearsed again.
+        for _ in 0..2 {
+            assert_eq!(trigger.process(email.clone()).await, None);
+            assert_eq!(prompts.try_recv().unwrap().1, "hi");
+        }
+        harness.shutdown().await.unwrap();
+    }
+
+    // Fails to deliver every response.
+    struct FailingHandler;
+
+    #[async_trait::async_trait]
+    impl ResponseHandler for FailingHandler {
+        async fn handle_response(
+            &self,
+            _event: &TEvent,
+            _response: &str,
+        ) -> Result<(), crate::handlers::HandlerError> {
+            Err(crate::handlers::HandlerError::DeliveryError(
+                "chat not found".to_string(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_refuses_and_dead_letters_events_whose_response_was_not_handled() {
+        use crate::triggers::event::Outcome;
+
+        let dir = tempfile::tempdir().unwrap();
+        let handler = RecordingHandler::default();
+        let trigger = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_model(Box::new(echo()))
+                .with_prompt_template("{{payload.q}}".to_string())
+                .add_trigger(Box::new(trigger.clone()))
+                .add_response_handler(FailingHandler)
+                .add_response_handler(handler.clone())
+                .with_dead_letter_store(crate::dead_letter::DeadLetterDir::new(dir.path())),
+        )
+        .unwrap();
+
+        let ask = TEvent {
+            name: "Ask".to_string(),
+            payload: Some(json!({"q": "why"})),
+        };
+        assert_eq!(
+            trigger.process(ask).await,
+            Some(Outcome::Nack(
+                "a response handler failed: Failed to deliver the response: chat not found"
+                    .to_string()
+            ))
+        );
+        // The handlers after the failed one still get the response.
+        assert_eq!(*handler.0.lock().unwrap(), ["Ask -> why"]);
+        let letters = harness.handle().dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].stage, FailureStage::Respond);
+        let report = harness.shutdown().await.unwrap();
+        assert_eq!((report.failed, report.dead_lettered), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_agent_dead_letters_and_reinjects_failed_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = RecordingHandler::default();
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(
+                ScriptedLLM::new()
+                    .fail("overloaded")
+                    .respond_with(|prompt| Ok(format!("answer to {prompt}"))),
+            ))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(vec![("Ask", json!({"q": "why"}))])))
+            .add_response_handler(handler.clone())
+            .with_dead_letter_store(crate::dead_letter::DeadLetterDir::new(dir.path()))
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_secs(2),
+            ))
+            .build()
+            .unwrap();
+        let handle = agent.handle();
+        let running = tokio::spawn(agent.run());
+
+        let mut letters = Vec::new();
+        for _ in 0..100 {
+            letters = handle.dead_letters().await.unwrap();
+            if !letters.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].stage, FailureStage::Prompt);
+        assert_eq!(letters[0].attempts, 1);
+        assert_eq!(letters[0].event.name, "Ask");
+
+        assert!(handle.reinject_dead_letter(&letters[0].id).await.unwrap());
+        assert!(!handle.reinject_dead_letter(&letters[0].id).await.unwrap());
+        for _ in 0..100 {
+            if !handler.0.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*handler.0.lock().unwrap(), vec!["Ask -> answer to why"]);
+        assert!(handle.dead_letters().await.unwrap().is_empty());
+
+        drop(handle);
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_agent_replays_unfinished_journal_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.journal");
+        {
+            // Leave an entry behind, as a crash before processing would.
+            let journal = Journal::new(&path);
+            journal.take_replay().await.unwrap();
+            let event = TEvent {
+                name: "Ask".to_string(),
+                payload: Some(json!({"q": "left over"})),
+            };
+            journal.append(&event, 1).await.unwrap();
+        }
+
+        let handler = RecordingHandler::default();
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(vec![("Ask", json!({"q": "new"}))])))
+            .add_response_handler(handler.clone())
+            .with_journal(&path)
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(200),
+            ))
+            .build()
+            .unwrap();
+        agent.run().await.unwrap();
+
+        assert_eq!(
+            *handler.0.lock().unwrap(),
+            vec!["Ask -> answer to left over", "Ask -> answer to new"]
+        );
+        let journal = Journal::new(&path);
+        let replay = journal.take_replay().await.unwrap();
+        assert!(replay.is_empty());
+    }
+
+    // Emits one acknowledged event per payload and records their outcomes.
+    struct AckingTrigger {
+        payloads: Vec<serde_json::Value>,
+        outcomes: Arc<std::sync::Mutex<Vec<Option<crate::triggers::event::Outcome>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Trigger for AckingTrigger {
+        async fn launch(
+            &self,
+            _tx: mpsc::Sender<TEvent>,
+            _shutdown_rx: broadcast::Receiver<()>,
+        ) -> Result<JoinHandle<()>, crate::triggers::TriggerError> {
+            unreachable!("the agent launches triggers with acks")
+        }
+
+        async fn launch_acked(
+            &self,
+            tx: mpsc::Sender<AckedEvent>,
+            mut shutdown_rx: broadcast::Receiver<()>,
+        ) -> Result<JoinHandle<()>, crate::triggers::TriggerError> {
+            let payloads = self.payloads.clone();
+            let outcomes = self.outcomes.clone();
+            Ok(tokio::spawn(async move {
+                for payload in payloads {
+                    let (ack, outcome) = Ack::channel();
+                    let event = TEvent {
+                        name: "Ask".to_string(),
+                        payload: Some(payload),
+                    };
+                    tx.send(AckedEvent { event, ack }).await.unwrap();
+                    let outcome = outcome.await.ok();
+                    outcomes.lock().unwrap().push(outcome);
+                }
+                let _ = shutdown_rx.recv().await;
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_replays_recorded_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let events = vec![("Ask", json!({"q": "ok"})), ("Ask", json!({"q": "fail"}))];
+
+        // Runs an agent on a trigger and returns what its handler saw.
+        async fn run(
+            trigger: Box<dyn Trigger>,
+            recording: Option<&std::path::Path>,
+        ) -> Vec<String> {
+            let handler = RecordingHandler::default();
+            let mut builder = AgentBuilder::new()
+                .with_model(Box::new(picky()))
+                .with_prompt_template("{{payload.q}}".to_string())
+                .add_trigger(trigger)
+                .add_response_handler(handler.clone())
+                .add_error_handler(handler.clone())
+                .without_retry()
+                .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                    Duration::from_millis(100),
+                ));
+            if let Some(path) = recording {
+                builder = builder.with_event_recording(path);
+            }
+            builder.build().unwrap().run().await.unwrap();
+            handler.0.lock().unwrap().clone()
+        }
+
+        let live = run(Box::new(BurstTrigger(events)), Some(&path)).await;
+        let replay = crate::triggers::ReplayTriggerBuilder::new(&path)
+            .with_pace(crate::triggers::replay_trigger::ReplayPace::AsFastAsPossible)
+            .build()
+            .unwrap();
+        assert_eq!(replay.len(), 2);
+        let replayed = run(Box::new(replay), None).await;
+
+        assert_eq!(live.len(), 2);
+        assert_eq!(replayed, live);
+    }
+
+    #[tokio::test]
+    async fn test_agent_acks_processed_events_and_nacks_failed_ones() {
+        use crate::triggers::event::Outcome;
+
+        let outcomes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let trigger = AckingTrigger {
+            payloads: vec![json!({"q": "ok"}), json!({"q": "fail"})],
+            outcomes: outcomes.clone(),
+        };
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(trigger))
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap();
+        agent.run().await.unwrap();
+
+        assert_eq!(
+            *outcomes.lock().unwrap(),
+            vec![
+                Some(Outcome::Ack),
+                Some(Outcome::Nack(
+                    "Failed to prompt the model: refused".to_string()
+                ))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_agent_skips_events_processed_before_a_restart() {
+        use crate::dedupe::FileDedupeStore;
+        use crate::triggers::event::{Outcome, payload_key};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("processed.jsonl");
+        let email = |id: &str| TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({ "id": id })),
+        };
+        let start = |llm: &ScriptedLLM, trigger: &ManualTrigger| {
+            let agent = AgentBuilder::new()
+                .with_model(Box::new(llm.clone()))
+                .with_prompt_template("{{payload.id}}".to_string())
+                .add_trigger(Box::new(trigger.clone()))
+                .without_retry()
+                .with_deduplication(payload_key("id"), FileDedupeStore::new(&path))
+                .build()
+                .unwrap();
+            agent.spawn()
+        };
+
+        let llm = ScriptedLLM::new()
+            .respond("ok")
+            .fail("refused")
+            .respond("ok");
+        let trigger = ManualTrigger::new();
+        let (handle, task) = start(&llm, &trigger);
+        assert_eq!(trigger.process(email("a")).await, Some(Outcome::Ack));
+        assert_eq!(trigger.process(email("a")).await, Some(Outcome::Ack));
+        // A failed event is not remembered, so it is processed when it comes again.
+        assert!(matches!(
+            trigger.process(email("b")).await,
+            Some(Outcome::Nack(_))
+        ));
+        assert_eq!(trigger.process(email("b")).await, Some(Outcome::Ack));
+        handle.shutdown();
+        let report = task.await.unwrap().unwrap();
+        assert_eq!((report.processed, report.duplicates), (3, 1));
+        assert_eq!(llm.prompts(), ["a", "b", "b"]);
+
+        let llm = ScriptedLLM::new().respond("ok");
+        let trigger = ManualTrigger::new();
+        let (handle, task) = start(&llm, &trigger);
+        assert_eq!(trigger.process(email("a")).await, Some(Outcome::Ack));
+        assert_eq!(trigger.process(email("b")).await, Some(Outcome::Ack));
+        assert_eq!(trigger.process(email("c")).await, Some(Outcome::Ack));
+        handle.shutdown();
+        let report = task.await.unwrap().unwrap();
+        assert_eq!((report.processed, report.duplicates), (1, 2));
+        assert_eq!(llm.prompts(), ["c"]);
+    }
+
+    #[tokio::test]
+    async fn test_agent_throttles_and_debounces_events_by_name() {
+        use crate::triggers::event::Outcome;
+
+        let llm = ScriptedLLM::new().respond("ok").respond("ok");
+        let trigger = ManualTrigger::new();
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .with_prompt_template("{{name}} {{payload.n}}".to_string())
+            .add_trigger(Box::new(trigger.clone()))
+            .add_event_policy(
+                "Tick",
+                EventPolicy::throttle(1, Duration::from_secs(60), Overflow::Drop),
+            )
+            .add_event_policy("Chat", EventPolicy::debounce(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        let (handle, task) = agent.spawn();
+
+        let tick = |n: u32| TEvent {
+            name: "Tick".to_string(),
+            payload: Some(json!({ "n": n })),
+        };
+        assert_eq!(trigger.process(tick(0)).await, Some(Outcome::Ack));
+        assert_eq!(
+            trigger.process(tick(1)).await,
+            Some(Outcome::Nack("throttled".to_string()))
+        );
+        let chat = TEvent {
+            name: "Chat".to_string(),
+            payload: Some(json!({ "n": 0 })),
+        };
+        let (superseded, _) = tokio::join!(trigger.process(chat), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            for n in 1..3 {
+                trigger.fire("Chat", json!({ "n": n }));
+            }
+        });
+        assert_eq!(superseded, Some(Outcome::Nack("superseded".to_string())));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(handle.metrics().queue_depth(), 1);
+        tokio::time::sleep(Duration::from_millis(130)).await;
+        // The gauge follows the events released by the debounce.
+        assert_eq!(handle.metrics().queue_depth(), 0);
+
+        handle.shutdown();
+        let report = task.await.unwrap().unwrap();
+        assert_eq!(llm.prompts(), ["Tick 0", "Chat 2"]);
+        assert_eq!((report.processed, report.dropped), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_agent_processes_batches_of_events_as_one_event() {
+        use crate::triggers::event::Outcome;
+
+        let llm = ScriptedLLM::new().respond("ok").respond("ok");
+        let trigger = ManualTrigger::new();
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .add_route(Route::new(
+                "NewEmailBatch",
+                "{{#each payload}}{{this.n}};{{/each}}",
+            ))
+            .add_trigger(Box::new(trigger.clone()))
+            .add_batch(Batch::new("NewEmail", 3, Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        let (handle, task) = agent.spawn();
+
+        let email = |n: u32| TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({ "n": n })),
+        };
+        for n in 0..3 {
+            trigger.send(email(n));
+        }
+        // The last email waits for its batch to be due, and is acknowledged with it.
+        assert_eq!(trigger.process(email(3)).await, Some(Outcome::Ack));
+        assert_eq!(llm.prompts(), ["0;1;2;", "3;"]);
+
+        handle.shutdown();
+        let report = task.await.unwrap().unwrap();
+        assert_eq!(report.processed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_agent_deduplicates_events_before_batching_them() {
+        use crate::triggers::event::{Outcome, payload_key};
+
+        let llm = ScriptedLLM::new().respond("ok").respond("ok");
+        let trigger = ManualTrigger::new();
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .add_route(Route::new(
+                "NewEmailBatch",
+                "{{#each payload}}{{this.id}};{{/each}}",
+            ))
+            .add_trigger(Box::new(trigger.clone()))
+            .add_batch(Batch::new("NewEmail", 2, Duration::from_millis(50)))
+            .with_deduplication(
+                payload_key("id"),
+                crate::dedupe::MemoryDedupeStore::new(Duration::from_secs(60)),
+            )
+            .with_drain_policy(DrainPolicy::AbandonQueue)
+            .build()
+            .unwrap();
+        let (handle, task) = agent.spawn();
+
+        let email = |id: u32| TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({ "id": id })),
+        };
+        // A copy of an email waiting in a batch does not take a place in it.
+        let outcomes = tokio::join!(
+            trigger.process(email(1)),
+            trigger.process(email(1)),
+            trigger.process(email(2)),
+        );
+        assert_eq!(
+            outcomes,
+            (
+                Some(Outcome::Ack),
+                Some(Outcome::Nack(
+                    "an event with the same key is being processed".to_string()
+                )),
+                Some(Outcome::Ack),
+            )
+        );
+        // The keys of a processed batch are remembered.
+        assert_eq!(trigger.process(email(2)).await, Some(Outcome::Ack));
+        assert_eq!(trigger.process(email(3)).await, Some(Outcome::Ack));
+        assert_eq!(llm.prompts(), ["1;2;", "3;"]);
+
+        // The events of an abandoned batch are refused, and counted one by one.
+        let (abandoned, _) = tokio::join!(trigger.process(email(4)), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            handle.shutdown();
+        });
+        assert_eq!(
+            abandoned,
+            Some(Outcome::Nack("the batch was not processed".to_string()))
+        );
+        let report = task.await.unwrap().unwrap();
+        assert_eq!((report.duplicates, report.abandoned), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_agent_processes_higher_priority_events_first() {
+        let mut llm = ScriptedLLM::new();
+        for _ in 0..5 {
+            llm = llm.respond("ok");
+        }
+        let gmail = ManualTrigger::new();
+        let telegram = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_model(Box::new(llm.with_latency(Duration::from_millis(50))))
+                .with_prompt_template("{{name}} {{payload.n}}".to_string())
+                .add_trigger(Box::new(gmail.clone()))
+                .add_prioritized_trigger(Box::new(telegram.clone()), Priority::High)
+                .add_priority_rule(Priority::Low, |event| event.name == "Newsletter"),
+        )
+        .unwrap();
+
+        gmail.fire("NewEmail", json!({ "n": 0 }));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // These wait for the first email to be processed.
+        gmail.fire("Newsletter", json!({ "n": 1 }));
+        gmail.fire("Newsletter", json!({ "n": 2 }));
+        gmail.fire("NewEmail", json!({ "n": 3 }));
+        telegram.fire("TelegramMessage", json!({ "n": 4 }));
+
+        let prompts: Vec<_> = harness
+            .wait_for(5)
+            .await
+            .into_iter()
+            .map(|exchange| exchange.prompt)
+            .collect();
+        assert_eq!(
+            prompts,
+            [
+                "NewEmail 0",
+                "TelegramMessage 4",
+                "NewEmail 3",
+                "Newsletter 1",
+                "Newsletter 2"
+            ]
+        );
+        harness.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_agent_reads_high_priority_events_past_a_full_low_lane() {
+        use crate::triggers::event::Outcome;
+
+        let newsletters = ManualTrigger::new();
+        let telegram = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_model(Box::new(echo().with_latency(Duration::from_millis(20))))
+                .with_prompt_template("{{name}}".to_string())
+                .add_prioritized_trigger(Box::new(newsletters.clone()), Priority::Low)
+                .add_prioritized_trigger(Box::new(telegram.clone()), Priority::High)
+                .with_drain_policy(DrainPolicy::AbandonQueue),
+        )
+        .unwrap();
+
+        // More low-priority events than their lane holds: the rest wait in their queue.
+        for n in 0..150 {
+            newsletters.fire("Newsletter", json!({ "n": n }));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let message = TEvent {
+            name: "TelegramMessage".to_string(),
+            payload: None,
+        };
+        let outcome = tokio::time::timeout(Duration::from_millis(500), telegram.process(message))
+            .await
+            .expect("the high-priority event waited behind the low-priority ones");
+        assert_eq!(outcome, Some(Outcome::Ack));
+        harness.shutdown().await.unwrap();
+    }
+
+    // Runs an agent that receives 5 events taking 100ms each and is shut down after 20ms.
+    async fn run_until_shutdown(policy: DrainPolicy, deadline: Duration) -> ShutdownReport {
+        let events = (0..5).map(|n| ("Tick", json!({"n": n}))).collect();
+        AgentBuilder::new()
+            .with_model(Box::new(echo().with_latency(Duration::from_millis(100))))
+            .with_prompt_template("{{payload.n}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .with_drain_policy(policy)
+            .with_drain_deadline(deadline)
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(20),
+            ))
+            .build()
+            .unwrap()
+            .run()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_agent_finishes_queued_events_on_shutdown() {
+        let report = run_until_shutdown(DrainPolicy::FinishQueue, Duration::from_secs(5)).await;
+        assert_eq!(
+            report,
+            ShutdownReport {
+                processed: 5,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_agent_abandons_queued_events_on_shutdown() {
+        let report = run_until_shutdown(DrainPolicy::AbandonQueue, Duration::from_secs(5)).await;
+        assert_eq!(report.processed, 1);
+        assert_eq!(report.abandoned, 4);
+    }
+
+    #[tokio::test]
+    async fn test_agent_cancels_events_at_the_drain_deadline() {
+        let report = run_until_shutdown(DrainPolicy::FinishQueue, Duration::from_millis(150)).await;
+        assert_eq!(report.processed, 1);
+        assert_eq!(report.abandoned, 4);
+    }
+
+    #[tokio::test]
+    async fn test_agent_records_metrics() {
+        let events = vec![("Ask", json!({"q": "ok"})), ("Ask", json!({"q": "fail"}))];
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(picky()))
+            .with_prompt_template("{{payload.q}}".to_string())
+            .add_trigger(Box::new(BurstTrigger(events)))
+            .without_retry()
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(100),
+            ))
+            .build()
+            .unwrap();
+        let handle = agent.handle();
+        agent.run().await.unwrap();
+
+        let metrics = handle.metrics();
+        assert_eq!(metrics.events_received("trigger-0", "Ask"), 2);
+        let text = metrics.render();
+        assert!(text.contains(
+            "forgeflow_llm_request_duration_seconds_count{route=\"default\",outcome=\"success\"} 1"
+        ));
+        assert!(text.contains(
+            "forgeflow_llm_request_duration_seconds_count{route=\"default\",outcome=\"error\"} 1"
+        ));
+    }
+
+    // Panics the first `crashes` times it is launched, then emits a single event.
+    struct CrashingTrigger {
+        crashes: usize,
+        launches: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Trigger for CrashingTrigger {
+        async fn launch(
+            &self,
+            tx: mpsc::Sender<TEvent>,
+            mut shutdown_rx: broadcast::Receiver<()>,
+        ) -> Result<JoinHandle<()>, crate::triggers::TriggerError> {
+            let launch = self.launches.fetch_add(1, Ordering::SeqCst);
+            let crashes = self.crashes;
+            Ok(tokio::spawn(async move {
+                if launch < crashes {
+                    panic!("trigger crashed");
+                }
+                let event = TEvent {
+                    name: "Up".to_string(),
+                    payload: None,
+                };
+                let _ = tx.send(event).await;
+                let _ = shutdown_rx.recv().await;
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_adds_and_removes_triggers_while_running() {
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(echo()))
+            .with_prompt_template("{{name}}".to_string())
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_secs(60),
+            ))
+            .build()
+            .unwrap();
+        let (handle, run) = agent.spawn();
+        let trigger = CrashingTrigger {
+            crashes: 0,
+            launches: Arc::new(AtomicUsize::new(0)),
+        };
+        let id = handle.add_trigger(Box::new(trigger)).unwrap();
+        assert_eq!(id, "trigger-0");
+        while handle.metrics().events_received(&id, "Up") == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // The supervisor's updates reach the board entry of a trigger added late.
+        assert_eq!(handle.triggers()[0].state, TriggerState::Running);
+
+        assert!(handle.remove_trigger(&id).await);
+        assert!(handle.triggers().is_empty());
+        assert!(!handle.remove_trigger(&id).await);
+
+        handle.shutdown();
+        let report = run.await.unwrap().unwrap();
+        assert_eq!(report.processed, 1);
+        let trigger = CrashingTrigger {
+            crashes: 0,
+            launches: Arc::new(AtomicUsize::new(0)),
+        };
+        assert!(matches!(
+            handle.add_trigger(Box::new(trigger)),
+            Err(AgentError::NotRunning)
+        ));
+    }
+
+    // Runs an agent whose trigger crashes `crashes` times, for 300ms.
+    async fn run_crashing_trigger(
+        crashes: usize,
+        policy: RestartPolicy,
+    ) -> (ShutdownReport, Vec<TriggerInfo>, usize) {
+        let launches = Arc::new(AtomicUsize::new(0));
+        let trigger = CrashingTrigger {
+            crashes,
+            launches: launches.clone(),
+        };
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(echo()))
+            .with_prompt_template("{{name}}".to_string())
+            .add_supervised_trigger(Box::new(trigger), policy)
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(300),
+            ))
+            .build()
+            .unwrap();
+        let handle = agent.handle();
+        let report = agent.run().await.unwrap();
+        (report, handle.triggers(), launches.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_agent_restarts_crashed_triggers() {
+        let policy = RestartPolicy::on_failure()
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(10));
+        let (report, triggers, launches) = run_crashing_trigger(2, policy).await;
+        assert_eq!(report.processed, 1);
+        assert_eq!(launches, 3);
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].id, "trigger-0");
+        assert_eq!(triggers[0].restarts, 2);
+        assert_eq!(triggers[0].state, TriggerState::Stopped);
+        assert!(triggers[0].last_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_agent_gives_up_after_max_restarts() {
+        let policy = RestartPolicy::on_failure()
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(10))
+            .with_max_restarts(2);
+        let (report, triggers, launches) = run_crashing_trigger(10, policy).await;
+        assert_eq!(report.processed, 0);
+        assert_eq!(launches, 3);
+        assert_eq!(triggers[0].restarts, 2);
+        assert_eq!(triggers[0].state, TriggerState::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_agent_forgets_restarts_after_a_stable_run() {
+        // Every launch counts as stable, so the limit never applies.
+        let policy = RestartPolicy::on_failure()
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(10))
+            .with_max_restarts(2)
+            .with_stable_after(Duration::ZERO);
+        let (report, triggers, launches) = run_crashing_trigger(5, policy).await;
+        assert_eq!(report.processed, 1);
+        assert_eq!(launches, 6);
+        assert_eq!(triggers[0].restarts, 5);
+        assert_eq!(triggers[0].state, TriggerState::Stopped);
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(4, 4);
@@ -0,0 +1,197 @@
+// The `admin` module serves the agent's local admin API, which inspects and controls
+// a running agent through its `AgentHandle`.
+//
+// Endpoints:
+//   GET  /health                  200 while the agent runs
+//   GET  /ready                   200 once the triggers are launched, 503 when shutting down
+//   GET  /triggers                the status of every trigger, as JSON
+//   POST /triggers/{id}/pause     holds back the events of a trigger
+//   POST /triggers/{id}/resume    lets them through again
+//   DELETE /triggers/{id}         stops the trigger and removes it
+//   POST /pause                   holds back the queued events
+//   POST /resume                  lets them through again
+//   POST /events                  injects the `TEvent` in the JSON body
+//   POST /shutdown                requests a graceful shutdown
+
+use super::{AgentError, AgentHandle};
+use crate::triggers::event::TEvent;
+use crate::utils::http;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// The largest event body accepted by `POST /events`.
+const MAX_EVENT_SIZE: usize = 1024 * 1024;
+
+/// Serves the admin API until `stop` is cancelled.
+pub(super) async fn serve(listener: TcpListener, handle: AgentHandle, stop: CancellationToken) {
+    http::serve("admin", listener, stop, move |request| {
+        let handle = handle.clone();
+        async move { respond(request, &handle).await }
+    })
+    .await
+}
+
+async fn respond(request: Request<Incoming>, handle: &AgentHandle) -> Response<Full<Bytes>> {
+    let path: Vec<&str> = request
+        .uri()
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match (request.method(), path.as_slice()) {
+        (&Method::GET, ["health"]) => http::text(StatusCode::OK, "ok\n"),
+        (&Method::GET, ["ready"]) if handle.is_ready() => http::text(StatusCode::OK, "ready\n"),
+        (&Method::GET, ["ready"]) => http::text(StatusCode::SERVICE_UNAVAILABLE, "not ready\n"),
+        (&Method::GET, ["triggers"]) => json_response(StatusCode::OK, &json!(handle.triggers())),
+        (&Method::POST, ["triggers", id, action @ ("pause" | "resume")]) => {
+            let found = match *action {
+                "pause" => handle.pause_trigger(id),
+                _ => handle.resume_trigger(id),
+            };
+            if found {
+                info!(trigger = %id, action = %action, "Trigger updated through the admin API");
+                no_content()
+            } else {
+                http::text(StatusCode::NOT_FOUND, "No such trigger\n")
+            }
+        }
+        (&Method::DELETE, ["triggers", id]) => {
+            if handle.remove_trigger(id).await {
+                info!(trigger = %id, "Trigger removed through the admin API");
+                no_content()
+            } else {
+                http::text(StatusCode::NOT_FOUND, "No such trigger\n")
+            }
+        }
+        (&Method::POST, ["pause"]) => {
+            handle.pause();
+            no_content()
+        }
+        (&Method::POST, ["resume"]) => {
+            handle.resume();
+            no_content()
+        }
+        (&Method::POST, ["events"]) => inject(request, handle).await,
+        (&Method::POST, ["shutdown"]) => {
+            info!("Shutdown requested through the admin API");
+            handle.shutdown();
+            http::text(StatusCode::ACCEPTED, "shutting down\n")
+        }
+        _ => http::text(StatusCode::NOT_FOUND, "Not found\n"),
+    }
+}
+
+/// Injects the event in the request body.
+async fn inject(request: Request<Incoming>, handle: &AgentHandle) -> Response<Full<Bytes>> {
+    let body = match Limited::new(request.into_body(), MAX_EVENT_SIZE)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return http::text(StatusCode::BAD_REQUEST, format!("{e}\n")),
+    };
+    let event: TEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => return http::text(StatusCode::BAD_REQUEST, format!("Invalid event: {e}\n")),
+    };
+    match handle.inject(event).await {
+        Ok(()) => http::text(StatusCode::ACCEPTED, "queued\n"),
+        Err(AgentError::NotRunning) => http::text(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The agent is not running\n",
+        ),
+        Err(e) => http::text(StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n")),
+    }
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+    http::response(status, "application/json", body.to_string())
+}
+
+fn no_content() -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentBuilder;
+    use crate::testing::ScriptedLLM;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Sends a request and returns the status code and the body of the response.
+    async fn call(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+        (status, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_controls_the_agent() {
+        let llm = ScriptedLLM::new().respond_with(|prompt| Ok(prompt.to_string()));
+        let agent = AgentBuilder::new()
+            .with_model(Box::new(llm.clone()))
+            .with_prompt_template("{{payload.n}}".to_string())
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_secs(60),
+            ))
+            .build()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = CancellationToken::new();
+        tokio::spawn(serve(listener, agent.handle(), stop.clone()));
+        let run = tokio::spawn(agent.run());
+
+        assert_eq!(call(addr, "GET", "/health", "").await.0, 200);
+        while call(addr, "GET", "/ready", "").await.0 != 200 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            call(addr, "GET", "/triggers", "").await,
+            (200, "[]".to_string())
+        );
+        assert_eq!(call(addr, "POST", "/triggers/nope/pause", "").await.0, 404);
+        assert_eq!(call(addr, "DELETE", "/triggers/nope", "").await.0, 404);
+
+        // Injected events wait while the event loop is paused.
+        assert_eq!(call(addr, "POST", "/pause", "").await.0, 204);
+        let event = r#"{"name":"Ping","payload":{"n":1}}"#;
+        assert_eq!(call(addr, "POST", "/events", event).await.0, 202);
+        assert_eq!(call(addr, "POST", "/events", "{").await.0, 400);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(llm.prompts().is_empty());
+        assert_eq!(call(addr, "POST", "/resume", "").await.0, 204);
+
+        assert_eq!(call(addr, "POST", "/shutdown", "").await.0, 202);
+        let report = tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.processed, 1);
+        assert_eq!(llm.prompts().len(), 1);
+        stop.cancel();
+    }
+}
@@ -0,0 +1,342 @@
+// The `batch` module collects the events of a name into batches, each processed as a
+// single event whose payload is the array of their payloads.
+
+use super::{AgentError, Envelope};
+use crate::triggers::event::{Ack, Outcome, TEvent};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Collects the events of a name into batch events, given to
+/// [`AgentBuilder::add_batch`](super::AgentBuilder::add_batch).
+///
+/// A batch is emitted once it holds `max_size` events, or `max_wait` after its first
+/// event, whichever comes first. The batch event is named after the events, with a
+/// `Batch` suffix unless [`with_batch_name`](Self::with_batch_name) is used, and its
+/// payload is the array of their payloads, in the order they were received:
+///
+/// ```text
+/// {{#each payload}}
+/// - {{this.snippet}}
+/// {{/each}}
+/// ```
+///
+/// The batch event is processed like any other event, once; the events it holds are
+/// acknowledged, or not, with it. With deduplication, each event is checked before it
+/// joins a batch, and its key is remembered once the batch is processed.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::agent::{AgentBuilder, Batch, Route};
+///
+/// let agent = AgentBuilder::new()
+///     .add_trigger(Box::new(gmail))
+///     .add_batch(Batch::new("NewEmail", 20, Duration::from_secs(300)))
+///     .add_route(Route::new(
+///         "NewEmailBatch",
+///         "Triage these emails:\n{{#each payload}}- {{this.snippet}}\n{{/each}}",
+///     ))
+///     .build()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Batch {
+    event_name: String,
+    batch_name: String,
+    max_size: usize,
+    max_wait: Duration,
+}
+
+impl Batch {
+    /// Creates a batch of up to `max_size` events named `event_name`, emitted at the
+    /// latest `max_wait` after its first event.
+    pub fn new(event_name: &str, max_size: usize, max_wait: Duration) -> Self {
+        Self {
+            event_name: event_name.to_string(),
+            batch_name: format!("{event_name}Batch"),
+            max_size,
+            max_wait,
+        }
+    }
+
+    /// Sets the name of the batch events. Defaults to the name of the events, with a
+    /// `Batch` suffix.
+    pub fn with_batch_name(mut self, name: &str) -> Self {
+        self.batch_name = name.to_string();
+        self
+    }
+
+    fn validate(&self) -> Result<(), AgentError> {
+        if self.max_size == 0 || self.max_wait.is_zero() {
+            return Err(AgentError::BuildError(format!(
+                "The batch of `{}` needs a non-zero size and wait.",
+                self.event_name
+            )));
+        }
+        if self.batch_name == self.event_name {
+            return Err(AgentError::BuildError(format!(
+                "The batch of `{}` needs a name of its own.",
+                self.event_name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// An event held in a batch, with its dedupe key, if it has one.
+type Held = (Envelope, Option<String>);
+
+/// The events of a batch being collected.
+struct Pending {
+    members: Vec<Held>,
+    /// When the batch is emitted, if it is not full before.
+    deadline: Instant,
+}
+
+/// A batch ready to be processed.
+pub(super) struct Sealed {
+    name: String,
+    members: Vec<Held>,
+}
+
+/// An event of a batch, waiting for the outcome of the batch event.
+pub(super) struct Member {
+    pub(super) journal_id: Option<u64>,
+    pub(super) ack: Ack,
+    /// The dedupe key the event holds until the batch is processed.
+    pub(super) key: Option<String>,
+}
+
+/// What the members of a batch wait for: the outcome of the batch event.
+pub(super) struct Settlement {
+    pub(super) outcome: oneshot::Receiver<Outcome>,
+    pub(super) members: Vec<Member>,
+}
+
+impl Sealed {
+    /// Returns the number of events in the batch.
+    pub(super) fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Builds the batch event, with the highest priority of its members, and what
+    /// settles its members once it is processed.
+    pub(super) fn seal(self) -> (Envelope, Settlement) {
+        let priority = self
+            .members
+            .iter()
+            .map(|(envelope, _)| envelope.priority)
+            .max()
+            .unwrap_or_default();
+        let size = self.members.len();
+        let mut payloads = Vec::with_capacity(size);
+        let mut members = Vec::with_capacity(size);
+        for (envelope, key) in self.members {
+            payloads.push(envelope.event.payload.unwrap_or(Value::Null));
+            members.push(Member {
+                journal_id: envelope.journal_id,
+                ack: envelope.ack,
+                key,
+            });
+        }
+        let (ack, outcome) = Ack::channel();
+        let envelope = Envelope {
+            ack,
+            priority,
+            batch: Some(size),
+            ..Envelope::new(TEvent {
+                name: self.name,
+                payload: Some(Value::Array(payloads)),
+            })
+        };
+        (envelope, Settlement { outcome, members })
+    }
+}
+
+/// What becomes of an event given to the batches.
+pub(super) enum Collected {
+    /// The event is not batched.
+    Pass(Envelope),
+    /// The event is held in a batch.
+    Held,
+    /// The event completed its batch.
+    Full(Sealed),
+}
+
+/// The batches of an agent, with the events collected for each.
+#[derive(Default)]
+pub(super) struct BatchTable {
+    batches: HashMap<String, Batch>,
+    pending: HashMap<String, Pending>,
+}
+
+impl BatchTable {
+    pub(super) fn compile(batches: Vec<Batch>) -> Result<Self, AgentError> {
+        let mut table = HashMap::new();
+        for batch in batches {
+            batch.validate()?;
+            let event_name = batch.event_name.clone();
+            if table.insert(event_name.clone(), batch).is_some() {
+                return Err(AgentError::BuildError(format!(
+                    "The events `{event_name}` are batched twice."
+                )));
+            }
+        }
+        Ok(Self {
+            batches: table,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Returns `true` if the table has no batch.
+    pub(super) fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Returns `true` if the events named `name` are batched.
+    pub(super) fn is_batched(&self, name: &str) -> bool {
+        self.batches.contains_key(name)
+    }
+
+    /// Adds an event received at `now` to its batch, if its name is batched, with the
+    /// dedupe key it holds.
+    pub(super) fn collect(
+        &mut self,
+        envelope: Envelope,
+        key: Option<String>,
+        now: Instant,
+    ) -> Collected {
+        let Some(batch) = self.batches.get(&envelope.event.name) else {
+            return Collected::Pass(envelope);
+        };
+        let pending = self
+            .pending
+            .entry(batch.event_name.clone())
+            .or_insert_with(|| Pending {
+                members: Vec::with_capacity(batch.max_size),
+                deadline: now + batch.max_wait,
+            });
+        pending.members.push((envelope, key));
+        if pending.members.len() < batch.max_size {
+            return Collected::Held;
+        }
+        let members = self
+            .pending
+            .remove(&batch.event_name)
+            .map(|pending| pending.members)
+            .unwrap_or_default();
+        Collected::Full(Sealed {
+            name: batch.batch_name.clone(),
+            members,
+        })
+    }
+
+    /// Returns when the next batch is due, if any is being collected.
+    pub(super) fn next_flush(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Returns the batches due at `now`.
+    pub(super) fn flush(&mut self, now: Instant) -> Vec<Sealed> {
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|name| self.seal(&name))
+            .collect()
+    }
+
+    /// Returns every batch being collected, due or not.
+    pub(super) fn drain(&mut self) -> Vec<Sealed> {
+        let names: Vec<String> = self.pending.keys().cloned().collect();
+        names.iter().filter_map(|name| self.seal(name)).collect()
+    }
+
+    /// Returns the number of events held in the batches.
+    pub(super) fn held(&self) -> usize {
+        self.pending
+            .values()
+            .map(|pending| pending.members.len())
+            .sum()
+    }
+
+    fn seal(&mut self, event_name: &str) -> Option<Sealed> {
+        let pending = self.pending.remove(event_name)?;
+        Some(Sealed {
+            name: self.batches[event_name].batch_name.clone(),
+            members: pending.members,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn email(n: u32) -> Envelope {
+        Envelope::new(TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({ "n": n })),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_collects_events_until_the_batch_is_full_or_due() {
+        let start = Instant::now();
+        let mut table =
+            BatchTable::compile(vec![Batch::new("NewEmail", 2, Duration::from_secs(60))]).unwrap();
+
+        let other = Envelope::new(TEvent {
+            name: "Tick".to_string(),
+            payload: None,
+        });
+        assert!(matches!(
+            table.collect(other, None, start),
+            Collected::Pass(_)
+        ));
+        assert!(matches!(
+            table.collect(email(0), None, start),
+            Collected::Held
+        ));
+        let Collected::Full(full) = table.collect(email(1), None, start) else {
+            panic!("the batch should be full");
+        };
+        let (batch, settlement) = full.seal();
+        assert_eq!(batch.event.name, "NewEmailBatch");
+        assert_eq!(batch.event.payload, Some(json!([{ "n": 0 }, { "n": 1 }])));
+        assert_eq!(batch.events(), 2);
+
+        // The members wait for the outcome of the batch.
+        assert_eq!(settlement.members.len(), 2);
+        batch.ack.nack("refused");
+        assert_eq!(
+            settlement.outcome.await,
+            Ok(Outcome::Nack("refused".to_string()))
+        );
+
+        assert!(matches!(
+            table.collect(email(2), None, start),
+            Collected::Held
+        ));
+        assert_eq!(table.next_flush(), Some(start + Duration::from_secs(60)));
+        assert!(table.flush(start).is_empty());
+        let due = table.flush(start + Duration::from_secs(60));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].len(), 1);
+        assert_eq!(table.held(), 0);
+    }
+
+    #[test]
+    fn test_rejects_batches_named_like_their_events() {
+        let batch = Batch::new("NewEmail", 2, Duration::from_secs(1)).with_batch_name("NewEmail");
+        assert!(matches!(
+            BatchTable::compile(vec![batch]),
+            Err(AgentError::BuildError(_))
+        ));
+    }
+}
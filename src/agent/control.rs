@@ -0,0 +1,58 @@
+// The `control` module holds the switches that let code outside the agent pause its
+// event loop and ask it to shut down, through an `AgentHandle` or the admin API.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// The switches of a running agent, shared between the agent and its handles.
+pub(super) struct Control {
+    /// Whether the event loop holds back the queued events.
+    paused: watch::Sender<bool>,
+    /// Cancelled when a graceful shutdown is requested.
+    shutdown: CancellationToken,
+    /// Whether the agent has launched its triggers and is not shutting down.
+    ready: AtomicBool,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Self {
+            paused: watch::Sender::new(false),
+            shutdown: CancellationToken::new(),
+            ready: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Control {
+    pub(super) fn set_paused(&self, paused: bool) {
+        self.paused.send_replace(paused);
+    }
+
+    pub(super) fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns a receiver notified when the event loop is paused or resumed.
+    pub(super) fn subscribe_paused(&self) -> watch::Receiver<bool> {
+        self.paused.subscribe()
+    }
+
+    pub(super) fn request_shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Completes when a graceful shutdown is requested.
+    pub(super) async fn shutdown_requested(&self) {
+        self.shutdown.cancelled().await
+    }
+
+    pub(super) fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub(super) fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,72 @@
+// The `dedupe` module skips the events whose key was already processed, or is being
+// processed, by the agent.
+
+use crate::dedupe::DedupeStore;
+use crate::triggers::event::{KeyExtractor, TEvent};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// What the agent does with an event, given the keys already seen.
+pub(super) enum Claim {
+    /// The event is processed. Its key, if it has one, is held until it is released.
+    Fresh(Option<String>),
+    /// An event with the same key was processed: the event is acknowledged and skipped.
+    Processed(String),
+    /// An event with the same key is being processed: the event is refused, so that
+    /// its trigger can emit it again if the other one fails.
+    InFlight(String),
+}
+
+/// Derives the key of each event and checks it against the processed keys.
+pub(super) struct Deduplicator {
+    key: KeyExtractor,
+    store: Arc<dyn DedupeStore>,
+    /// The keys of the events being processed.
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl Deduplicator {
+    pub(super) fn new(key: KeyExtractor, store: Arc<dyn DedupeStore>) -> Self {
+        Self {
+            key,
+            store,
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Decides whether the event is processed, and holds its key if it is.
+    ///
+    /// If the store fails, the event is processed: a duplicate is better than a
+    /// lost event.
+    pub(super) async fn claim(&self, event: &TEvent) -> Claim {
+        let Some(key) = (self.key)(event) else {
+            return Claim::Fresh(None);
+        };
+        // The key is held before the store is checked: an event with the same key
+        // releases it only once its key is stored, so it is either seen in flight
+        // or found in the store.
+        if !self.in_flight.lock().unwrap().insert(key.clone()) {
+            return Claim::InFlight(key);
+        }
+        match self.store.contains(&key).await {
+            Ok(true) => {
+                self.in_flight.lock().unwrap().remove(&key);
+                Claim::Processed(key)
+            }
+            Ok(false) => Claim::Fresh(Some(key)),
+            Err(e) => {
+                warn!(key = %key, error = %e, "Failed to check the dedupe store, processing the event");
+                Claim::Fresh(Some(key))
+            }
+        }
+    }
+
+    /// Releases the key of a processed event, and remembers it if the event succeeded.
+    pub(super) async fn release(&self, key: String, succeeded: bool) {
+        if succeeded && let Err(e) = self.store.insert(&key).await {
+            warn!(key = %key, error = %e, "Failed to remember the key of a processed event");
+        }
+        self.in_flight.lock().unwrap().remove(&key);
+    }
+}
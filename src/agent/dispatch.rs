@@ -0,0 +1,209 @@
+// The `dispatch` module schedules received events onto processing tasks.
+//
+// Events are processed concurrently up to the agent's concurrency limit. When an
+// ordering key is configured, events sharing a key are processed one after the
+// other, in the order they were received, while events with different keys still
+// run in parallel. The events waiting for a processing slot are taken from their
+// priority lanes, see the `lanes` module.
+
+use super::lanes::Lanes;
+use super::{AgentCore, Envelope};
+use crate::triggers::event::KeyExtractor;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, warn};
+
+/// The number of events that can wait in each priority lane. Once a lane is full, the
+/// dispatcher stops reading the events of its priority, leaving their queue to apply
+/// backpressure to their triggers, while it keeps reading the other priorities.
+///
+/// The events queued behind a busy key are not counted, so that a busy key does not
+/// hold back the events of the other keys.
+const MAX_PENDING: usize = 100;
+
+/// The number of events that can wait behind a busy key; the next events with that
+/// key are refused until it catches up.
+const MAX_QUEUED_PER_KEY: usize = 100;
+
+/// Reports the completion of a processing task back to the dispatcher.
+///
+/// The report is sent on drop so a panicking task doesn't leave its key busy forever.
+struct Completion {
+    key: Option<String>,
+    done_tx: mpsc::UnboundedSender<Option<String>>,
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        let _ = self.done_tx.send(self.key.take());
+    }
+}
+
+/// Schedules events onto processing tasks.
+pub(super) struct Dispatcher {
+    core: Arc<AgentCore>,
+    tasks: TaskTracker,
+    /// Cancels the processing tasks at the drain deadline.
+    cancel: CancellationToken,
+    /// The maximum number of events processed at the same time.
+    concurrency: usize,
+    /// The number of events being processed.
+    running: usize,
+    /// The events waiting for a processing slot, by priority.
+    lanes: Lanes,
+    /// The number of events waiting in a lane above which no further event of its
+    /// priority is accepted.
+    limit: usize,
+    ordering_key: Option<KeyExtractor>,
+    /// Keys that have an event being processed, with the events waiting behind it.
+    busy: HashMap<String, VecDeque<Envelope>>,
+    /// Events dispatched but not completed yet, including the queued ones.
+    pending: usize,
+    done_tx: mpsc::UnboundedSender<Option<String>>,
+    done_rx: mpsc::UnboundedReceiver<Option<String>>,
+}
+
+impl Dispatcher {
+    pub(super) fn new(
+        core: Arc<AgentCore>,
+        tasks: TaskTracker,
+        cancel: CancellationToken,
+        concurrency: usize,
+        ordering_key: Option<KeyExtractor>,
+        lanes: Lanes,
+    ) -> Self {
+        let (done_tx, done_rx) = mpsc::unbounded_channel();
+        Self {
+            core,
+            tasks,
+            cancel,
+            concurrency,
+            running: 0,
+            lanes,
+            limit: MAX_PENDING.max(concurrency),
+            ordering_key,
+            busy: HashMap::new(),
+            pending: 0,
+            done_tx,
+            done_rx,
+        }
+    }
+
+    /// Returns, for each lane, the highest priority first, whether the dispatcher can
+    /// accept another event of its priority.
+    pub(super) fn room(&self) -> [bool; 3] {
+        self.lanes.waiting().map(|waiting| waiting < self.limit)
+    }
+
+    /// Returns `true` if no event is being processed or waiting to be.
+    pub(super) fn is_idle(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Queues an event in its priority lane, or behind an event with the same key, and
+    /// starts processing events while slots are free.
+    ///
+    /// An event whose key already has [`MAX_QUEUED_PER_KEY`] events waiting is refused.
+    pub(super) fn dispatch(&mut self, envelope: Envelope) {
+        let key = self
+            .ordering_key
+            .as_ref()
+            .and_then(|extract| extract(&envelope.event));
+        match key {
+            Some(key) => match self.busy.get_mut(&key) {
+                Some(queue) if queue.len() >= MAX_QUEUED_PER_KEY => {
+                    warn!(key = %key, "Too many events queued for the key, refusing the event");
+                    let core = self.core.clone();
+                    self.tasks.spawn(async move {
+                        core.refuse(envelope, "too many events queued for the same key")
+                            .await
+                    });
+                    return;
+                }
+                Some(queue) => {
+                    debug!(key = %key, queued = queue.len() + 1, "Key busy, queueing event");
+                    queue.push_back(envelope);
+                }
+                None => {
+                    self.busy.insert(key.clone(), VecDeque::new());
+                    self.lanes.push(Some(key), envelope);
+                }
+            },
+            None => self.lanes.push(None, envelope),
+        }
+        self.pending += 1;
+        self.fill();
+    }
+
+    /// Waits for the next processing task to complete and returns its key.
+    pub(super) async fn next_completion(&mut self) -> Option<Option<String>> {
+        self.done_rx.recv().await
+    }
+
+    /// Records a completed task, moving the next event queued under the same key to its
+    /// priority lane, and starts processing events while slots are free.
+    pub(super) fn complete(&mut self, key: Option<String>) {
+        self.pending -= 1;
+        self.running -= 1;
+        if let Some(key) = key {
+            match self.busy.get_mut(&key).and_then(VecDeque::pop_front) {
+                Some(next) => {
+                    self.lanes.push(Some(key), next);
+                }
+                None => {
+                    self.busy.remove(&key);
+                }
+            }
+        }
+        self.fill();
+    }
+
+    /// Drops the events that are not being processed yet.
+    ///
+    /// Returns how many events were waiting in the lanes or behind busy keys, counting
+    /// the events of a batch one by one. The events being processed are not affected.
+    pub(super) fn abandon_queued(&mut self) -> usize {
+        let mut abandoned = self.lanes.clear();
+        for queue in self.busy.values_mut() {
+            abandoned.extend(queue.drain(..));
+        }
+        self.pending -= abandoned.len();
+        abandoned.iter().map(Envelope::events).sum()
+    }
+
+    /// Starts processing the events of the highest lanes while slots are free.
+    fn fill(&mut self) {
+        while self.running < self.concurrency {
+            let Some((key, envelope)) = self.lanes.pop() else {
+                break;
+            };
+            self.running += 1;
+            self.spawn(key, envelope);
+        }
+    }
+
+    fn spawn(&self, key: Option<String>, envelope: Envelope) {
+        let completion = Completion {
+            key,
+            done_tx: self.done_tx.clone(),
+        };
+        let core = self.core.clone();
+        let cancel = self.cancel.clone();
+        let events = envelope.events();
+        self.tasks.spawn(async move {
+            let _completion = completion;
+            // Past the drain deadline, the events started from the lanes are dropped.
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    core.counters.abandoned.fetch_add(events, Ordering::SeqCst);
+                }
+                _ = core.process_single_event(envelope) => {}
+            }
+        });
+    }
+}
@@ -0,0 +1,65 @@
+// The `drain` module defines how the agent winds down once it is asked to shut down,
+// and the report of what happened to the events it received.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What happens to the queued events when the agent shuts down.
+///
+/// In both cases the events being processed are given until the drain deadline to
+/// complete, and are cancelled after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DrainPolicy {
+    /// Queued events are processed before the agent stops.
+    #[default]
+    FinishQueue,
+    /// Queued events are dropped; only the events being processed are completed.
+    ///
+    /// With a journal, the dropped events are processed at the next start.
+    AbandonQueue,
+}
+
+/// What happened to the events an agent received, returned by [`Agent::run`](super::Agent::run).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// The events whose processing completed, successfully or not.
+    pub processed: usize,
+    /// The processed events that failed to render or whose LLM call failed.
+    pub failed: usize,
+    /// The failed events that were stored in the dead-letter store.
+    pub dead_lettered: usize,
+    /// The events dropped from the queue or cancelled at the drain deadline. The
+    /// events of a batch are counted one by one.
+    pub abandoned: usize,
+    /// The events skipped because an event with the same key was already processed,
+    /// or was being processed.
+    pub duplicates: usize,
+    /// The events dropped by an event policy, throttled, superseded by a later
+    /// event under a debounce or past the events a policy holds, or refused because
+    /// too many events with the same ordering key were queued.
+    pub dropped: usize,
+}
+
+/// The counters behind the [`ShutdownReport`], shared with the processing tasks.
+#[derive(Default)]
+pub(super) struct Counters {
+    pub(super) processed: AtomicUsize,
+    pub(super) failed: AtomicUsize,
+    pub(super) dead_lettered: AtomicUsize,
+    pub(super) abandoned: AtomicUsize,
+    pub(super) duplicates: AtomicUsize,
+    pub(super) dropped: AtomicUsize,
+}
+
+impl Counters {
+    /// Returns the current value of the counters.
+    pub(super) fn report(&self) -> ShutdownReport {
+        ShutdownReport {
+            processed: self.processed.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            dead_lettered: self.dead_lettered.load(Ordering::SeqCst),
+            abandoned: self.abandoned.load(Ordering::SeqCst),
+            duplicates: self.duplicates.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+        }
+    }
+}
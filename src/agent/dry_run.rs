@@ -0,0 +1,39 @@
+// The `dry_run` module provides what replaces the model of an agent in dry-run mode:
+// a recorder for the rendered prompts, and a model that is never prompted.
+//
+// A dry-run event is released once its prompt is recorded, without an outcome: its
+// trigger sees it as not processed, and its journal entry is left unfinished.
+
+use crate::handlers::PromptSink;
+use crate::llm::{LLM, LLMError};
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::warn;
+
+/// The reason the stand-in model gives, should it be prompted.
+const DRY_RUN: &str = "dry run";
+
+/// Records the rendered prompts in place of prompting the model.
+pub(super) struct PromptRecorder(pub(super) Arc<dyn PromptSink>);
+
+impl PromptRecorder {
+    /// Records the prompt of an event, as the observers left it.
+    pub(super) async fn record(&self, event: &TEvent, prompt: &str) {
+        if let Err(e) = self.0.record_prompt(event, prompt).await {
+            warn!(event_name = %event.name, error = %e, "Failed to record the dry-run prompt");
+        }
+    }
+}
+
+/// Stands in for the models of a dry-run agent, which are never prompted.
+pub(super) struct NoModel;
+
+#[async_trait]
+impl LLM for NoModel {
+    async fn prompt(&self, _prompt: String) -> Result<String, LLMError> {
+        Err(LLMError::PromptError(format!(
+            "{DRY_RUN}: the model is not prompted"
+        )))
+    }
+}
@@ -0,0 +1,218 @@
+// The `handle` module provides the `AgentHandle`, which lets code outside the agent
+// interact with it while it runs.
+
+use super::control::Control;
+use super::intake::Intake;
+use super::supervisor::{RestartPolicy, TriggerBoard, TriggerInfo};
+use super::{AgentError, Envelope, Journal, Priority};
+use crate::dead_letter::{DeadLetter, DeadLetterStore};
+use crate::metrics::Metrics;
+use crate::recording::EventRecorder;
+use crate::triggers::event::{Ack, TEvent};
+use crate::triggers::traits::Trigger;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// A handle to an agent, obtained with [`Agent::handle`](super::Agent::handle).
+///
+/// Handles can be cloned and used from any task. While a handle exists, the agent's
+/// event loop keeps waiting for events even if every trigger has stopped.
+#[derive(Clone)]
+pub struct AgentHandle {
+    pub(super) events: Intake,
+    pub(super) dead_letters: Option<Arc<dyn DeadLetterStore>>,
+    pub(super) journal: Option<Arc<Journal>>,
+    pub(super) triggers: Arc<TriggerBoard>,
+    pub(super) metrics: Arc<Metrics>,
+    pub(super) control: Arc<Control>,
+    pub(super) recorder: Option<EventRecorder>,
+}
+
+impl AgentHandle {
+    /// Sends an event to the agent, as if a trigger had emitted it.
+    ///
+    /// Waits if the agent's event queue is full, and fails with
+    /// [`AgentError::NotRunning`] once the agent has stopped.
+    ///
+    /// Injected events are counted in the metrics under the `handle` trigger.
+    pub async fn inject(&self, event: TEvent) -> Result<(), AgentError> {
+        self.received("handle", &event).await;
+        self.send(Envelope::new(event)).await
+    }
+
+    /// Returns the status of the agent's triggers, in the order they were added.
+    pub fn triggers(&self) -> Vec<TriggerInfo> {
+        self.triggers.snapshot()
+    }
+
+    /// Adds a trigger to the agent, under the agent's default restart policy.
+    ///
+    /// The trigger is launched right away if the agent runs, or with the other
+    /// triggers once it does. Returns the id the trigger was assigned, or
+    /// [`AgentError::NotRunning`] once the agent has stopped.
+    pub fn add_trigger(&self, trigger: Box<dyn Trigger>) -> Result<String, AgentError> {
+        self.triggers
+            .add(Arc::from(trigger), None, Priority::default(), self)
+    }
+
+    /// Adds a trigger to the agent, restarted according to `policy`.
+    pub fn add_supervised_trigger(
+        &self,
+        trigger: Box<dyn Trigger>,
+        policy: RestartPolicy,
+    ) -> Result<String, AgentError> {
+        self.triggers
+            .add(Arc::from(trigger), Some(policy), Priority::default(), self)
+    }
+
+    /// Adds a trigger to the agent, whose events are processed with `priority`.
+    pub fn add_prioritized_trigger(
+        &self,
+        trigger: Box<dyn Trigger>,
+        priority: Priority,
+    ) -> Result<String, AgentError> {
+        self.triggers.add(Arc::from(trigger), None, priority, self)
+    }
+
+    /// Adds a trigger to the agent, restarted according to `policy`, whose events are
+    /// processed with `priority`.
+    pub fn add_supervised_prioritized_trigger(
+        &self,
+        trigger: Box<dyn Trigger>,
+        policy: RestartPolicy,
+        priority: Priority,
+    ) -> Result<String, AgentError> {
+        self.triggers
+            .add(Arc::from(trigger), Some(policy), priority, self)
+    }
+
+    /// Stops a trigger and removes it from the agent.
+    ///
+    /// Cancels the trigger's own shutdown signal and waits for it to return; the
+    /// events it already emitted are still processed. Returns `false` if the agent
+    /// has no trigger with this id.
+    pub async fn remove_trigger(&self, id: &str) -> bool {
+        self.triggers.remove(id).await
+    }
+
+    /// Holds back the events of a trigger until it is resumed.
+    ///
+    /// The trigger keeps running, but waits for its events to be accepted. Returns
+    /// `false` if the agent has no trigger with this id.
+    pub fn pause_trigger(&self, id: &str) -> bool {
+        self.triggers.set_paused(id, true)
+    }
+
+    /// Lets the events of a paused trigger through again.
+    ///
+    /// Returns `false` if the agent has no trigger with this id.
+    pub fn resume_trigger(&self, id: &str) -> bool {
+        self.triggers.set_paused(id, false)
+    }
+
+    /// Pauses the event loop: the events already being processed complete, while the
+    /// queued ones wait until it is resumed.
+    pub fn pause(&self) {
+        self.control.set_paused(true);
+    }
+
+    /// Resumes the event loop.
+    pub fn resume(&self) {
+        self.control.set_paused(false);
+    }
+
+    /// Returns whether the event loop is paused.
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Returns whether the agent has launched its triggers and is not shutting down.
+    pub fn is_ready(&self) -> bool {
+        self.control.is_ready()
+    }
+
+    /// Asks the agent to shut down gracefully, as its shutdown handler would.
+    pub fn shutdown(&self) {
+        self.control.request_shutdown();
+    }
+
+    /// Returns the agent's metrics.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Returns the agent's dead letters, oldest first.
+    ///
+    /// Returns an empty list if the agent has no dead-letter store.
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, AgentError> {
+        match &self.dead_letters {
+            Some(store) => Ok(store.list().await?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the dead letter with the given id, if any.
+    pub async fn dead_letter(&self, id: &str) -> Result<Option<DeadLetter>, AgentError> {
+        match &self.dead_letters {
+            Some(store) => Ok(store.get(id).await?),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes a dead letter from the store and sends its event to the agent again.
+    ///
+    /// The attempt count carries over, so a letter that fails again is stored with
+    /// one more attempt. Returns `false` if there is no dead letter with this id.
+    pub async fn reinject_dead_letter(&self, id: &str) -> Result<bool, AgentError> {
+        let Some(store) = &self.dead_letters else {
+            return Ok(false);
+        };
+        let Some(letter) = store.remove(id).await? else {
+            return Ok(false);
+        };
+        info!(id = %letter.id, event_name = %letter.event.name, "Re-injecting dead letter");
+        let envelope = Envelope {
+            event: letter.event.clone(),
+            attempt: letter.attempts + 1,
+            journal_id: None,
+            ack: Ack::none(),
+            priority: Priority::default(),
+            batch: None,
+        };
+        if let Err(e) = self.send(envelope).await {
+            // Keep the letter rather than losing the event.
+            store.push(letter).await?;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Counts a new event in the metrics and records it, if the agent records its events.
+    pub(super) async fn received(&self, trigger: &str, event: &TEvent) {
+        self.metrics.event_received(trigger, &event.name);
+        if let Some(recorder) = &self.recorder
+            && let Err(e) = recorder.record(event).await
+        {
+            warn!(event_name = %event.name, error = %e, "Failed to record event");
+        }
+    }
+
+    /// Journals the envelope, unless it already is, and queues it.
+    ///
+    /// An event that can't be journaled is still processed, only without durability.
+    pub(super) async fn send(&self, mut envelope: Envelope) -> Result<(), AgentError> {
+        if let (Some(journal), None) = (&self.journal, envelope.journal_id) {
+            match journal.append(&envelope.event, envelope.attempt).await {
+                Ok(id) => envelope.journal_id = Some(id),
+                Err(e) => {
+                    error!(event_name = %envelope.event.name, error = %e, "Failed to journal event")
+                }
+            }
+        }
+        self.events
+            .send(envelope)
+            .await
+            .map_err(|_| AgentError::NotRunning)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+// The `intake` module queues the events sent to the agent in a channel per priority,
+// so that a backlog of events of one priority does not hold back those of the others.
+
+use super::Envelope;
+use super::lanes::PriorityRule;
+use std::future::poll_fn;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::sync::mpsc;
+
+/// The sending side of the event queues, handed to the triggers and the handles.
+#[derive(Clone)]
+pub(super) struct Intake {
+    rules: Arc<[PriorityRule]>,
+    /// A channel per priority, the highest first.
+    senders: [mpsc::Sender<Envelope>; 3],
+}
+
+/// The receiving side of the event queues, consumed by the event loop.
+pub(super) struct Queues {
+    /// A channel per priority, the highest first.
+    receivers: [mpsc::Receiver<Envelope>; 3],
+}
+
+/// Creates the event queues, each holding up to `buffer` events.
+pub(super) fn channel(rules: Vec<PriorityRule>, buffer: usize) -> (Intake, Queues) {
+    let (high_tx, high_rx) = mpsc::channel(buffer);
+    let (normal_tx, normal_rx) = mpsc::channel(buffer);
+    let (low_tx, low_rx) = mpsc::channel(buffer);
+    let intake = Intake {
+        rules: rules.into(),
+        senders: [high_tx, normal_tx, low_tx],
+    };
+    let queues = Queues {
+        receivers: [high_rx, normal_rx, low_rx],
+    };
+    (intake, queues)
+}
+
+impl Intake {
+    /// Gives an event its priority, and queues it with the events of that priority.
+    ///
+    /// The first rule the event matches decides its priority, over the priority it
+    /// came with. Waits while the queue is full, and gives the event back once the
+    /// agent has stopped.
+    pub(super) async fn send(&self, mut envelope: Envelope) -> Result<(), Envelope> {
+        if let Some((_, priority)) = self
+            .rules
+            .iter()
+            .find(|(matches, _)| matches(&envelope.event))
+        {
+            envelope.priority = *priority;
+        }
+        self.senders[envelope.priority.lane()]
+            .send(envelope)
+            .await
+            .map_err(|e| e.0)
+    }
+}
+
+impl Queues {
+    /// Waits for the next event of the highest priority whose lane has `room`, indexed
+    /// like the lanes.
+    ///
+    /// Returns `None` once every queue is closed and empty.
+    pub(super) async fn recv(&mut self, room: [bool; 3]) -> Option<Envelope> {
+        poll_fn(|cx| {
+            let mut open = false;
+            for (receiver, room) in self.receivers.iter_mut().zip(room) {
+                if !room {
+                    open |= !(receiver.is_closed() && receiver.is_empty());
+                    continue;
+                }
+                match receiver.poll_recv(cx) {
+                    Poll::Ready(Some(envelope)) => return Poll::Ready(Some(envelope)),
+                    Poll::Ready(None) => {}
+                    Poll::Pending => open = true,
+                }
+            }
+            if open {
+                Poll::Pending
+            } else {
+                Poll::Ready(None)
+            }
+        })
+        .await
+    }
+
+    /// Takes an event waiting in the queues, if any, the highest priority first.
+    pub(super) fn try_recv(&mut self) -> Option<Envelope> {
+        self.receivers
+            .iter_mut()
+            .find_map(|receiver| receiver.try_recv().ok())
+    }
+
+    /// Closes the queues: the events already queued can still be received.
+    pub(super) fn close(&mut self) {
+        for receiver in &mut self.receivers {
+            receiver.close();
+        }
+    }
+
+    /// Returns the number of events waiting in the queues.
+    pub(super) fn len(&self) -> usize {
+        self.receivers.iter().map(mpsc::Receiver::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Priority;
+    use crate::triggers::event::TEvent;
+
+    #[tokio::test]
+    async fn test_queues_events_by_priority() {
+        let urgent: PriorityRule = (
+            Arc::new(|event: &TEvent| event.name.starts_with("Telegram")),
+            Priority::High,
+        );
+        let (intake, mut queues) = channel(vec![urgent], 1);
+        for (name, priority) in [
+            ("Newsletter", Priority::Low),
+            ("Email", Priority::Normal),
+            // The rule decides over the priority of the trigger.
+            ("TelegramMessage", Priority::Low),
+        ] {
+            assert!(intake.send(Envelope::named(name, priority)).await.is_ok());
+        }
+        assert_eq!(queues.len(), 3);
+
+        // A full queue does not hold back the others.
+        let next = queues.recv([false, true, true]).await.unwrap();
+        assert_eq!(next.event.name, "Email");
+        let next = queues.recv([true; 3]).await.unwrap();
+        assert_eq!(next.event.name, "TelegramMessage");
+        assert_eq!(next.priority, Priority::High);
+
+        queues.close();
+        assert_eq!(queues.len(), 1);
+        assert_eq!(queues.try_recv().unwrap().event.name, "Newsletter");
+        assert!(queues.recv([true; 3]).await.is_none());
+        assert!(
+            intake
+                .send(Envelope::named("Email", Priority::Normal))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// The `journal` module provides the agent's write-ahead journal.
+//
+// Every event is appended to the journal before it is queued, and marked done once
+// its processing is over. Events that were never marked done, because the process
+// crashed or was stopped with events still queued, are replayed on the next start.
+//
+// The journal is a JSON Lines file of `append` and `done` records. It is compacted
+// when it is opened and then every `COMPACT_EVERY` completed events, by rewriting
+// only the unfinished entries. Opening and compacting run on the blocking thread pool.
+
+use super::Envelope;
+use crate::triggers::event::TEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, OnceCell};
+use tracing::{debug, info, warn};
+
+/// The number of completed events after which the journal is compacted.
+const COMPACT_EVERY: usize = 1000;
+
+/// A line of the journal.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    /// An event was received.
+    Append {
+        id: u64,
+        attempt: u32,
+        event: TEvent,
+    },
+    /// The processing of an event is over.
+    Done { id: u64 },
+}
+
+struct JournalState {
+    file: tokio::fs::File,
+    next_id: u64,
+    /// The entries not marked done yet, by id.
+    unfinished: BTreeMap<u64, (u32, TEvent)>,
+    /// The number of events marked done since the last compaction.
+    completed: usize,
+}
+
+/// An append-only journal of the events received by the agent.
+///
+/// The file is opened on first use.
+pub(super) struct Journal {
+    path: PathBuf,
+    state: OnceCell<Mutex<JournalState>>,
+    /// The envelopes of the entries found unfinished when the journal was opened,
+    /// until they are taken.
+    replay: std::sync::Mutex<Vec<Envelope>>,
+}
+
+impl Journal {
+    /// Creates a journal at `path`, without opening it yet.
+    pub(super) fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            state: OnceCell::new(),
+            replay: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Opens the journal if it is not open yet, and takes the envelopes of the
+    /// entries it found unfinished.
+    pub(super) async fn take_replay(&self) -> std::io::Result<Vec<Envelope>> {
+        self.state().await?;
+        Ok(std::mem::take(&mut *self.replay.lock().unwrap()))
+    }
+
+    /// Returns the state of the journal, opening it on first use.
+    async fn state(&self) -> std::io::Result<&Mutex<JournalState>> {
+        self.state
+            .get_or_try_init(|| async {
+                let path = self.path.clone();
+                let (unfinished, file) = tokio::task::spawn_blocking(move || {
+                    let unfinished = read_unfinished(&path)?;
+                    let file = compact(&path, &unfinished)?;
+                    Ok::<_, std::io::Error>((unfinished, file))
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+                let next_id = unfinished.keys().next_back().map_or(1, |id| id + 1);
+
+                let replay: Vec<Envelope> = unfinished
+                    .iter()
+                    .map(|(id, (attempt, event))| Envelope {
+                        attempt: *attempt,
+                        journal_id: Some(*id),
+                        ..Envelope::new(event.clone())
+                    })
+                    .collect();
+                if !replay.is_empty() {
+                    info!(path = %self.path.display(), count = replay.len(), "Replaying unfinished journal entries");
+                }
+                *self.replay.lock().unwrap() = replay;
+
+                Ok(Mutex::new(JournalState {
+                    file: tokio::fs::File::from_std(file),
+                    next_id,
+                    unfinished,
+                    completed: 0,
+                }))
+            })
+            .await
+    }
+
+    /// Appends an event to the journal and returns the id of its entry.
+    ///
+    /// The entry is synced to disk before this returns.
+    pub(super) async fn append(&self, event: &TEvent, attempt: u32) -> std::io::Result<u64> {
+        let mut state = self.state().await?.lock().await;
+        let id = state.next_id;
+        let record = Record::Append {
+            id,
+            attempt,
+            event: event.clone(),
+        };
+        write_record(&mut state.file, &record).await?;
+        state.file.sync_data().await?;
+        state.next_id += 1;
+        if let Record::Append { attempt, event, .. } = record {
+            state.unfinished.insert(id, (attempt, event));
+        }
+        Ok(id)
+    }
+
+    /// Marks an entry done, so it is not replayed.
+    ///
+    /// Done records are not synced: losing one to a crash replays an event that was
+    /// already processed, which is preferred over losing events.
+    pub(super) async fn complete(&self, id: u64) -> std::io::Result<()> {
+        let mut state = self.state().await?.lock().await;
+        write_record(&mut state.file, &Record::Done { id }).await?;
+        // Tokio hands writes to a background task; flushing waits for it to finish.
+        state.file.flush().await?;
+        state.unfinished.remove(&id);
+        state.completed += 1;
+        if state.completed >= COMPACT_EVERY {
+            let path = self.path.clone();
+            let unfinished = state.unfinished.clone();
+            let file = tokio::task::spawn_blocking(move || compact(&path, &unfinished))
+                .await
+                .map_err(std::io::Error::other)??;
+            state.file = tokio::fs::File::from_std(file);
+            state.completed = 0;
+            debug!(unfinished = state.unfinished.len(), "Journal compacted");
+        }
+        Ok(())
+    }
+}
+
+async fn write_record(file: &mut tokio::fs::File, record: &Record) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    file.write_all(&line).await
+}
+
+/// Reads the entries of the journal at `path` that were never marked done.
+fn read_unfinished(path: &Path) -> std::io::Result<BTreeMap<u64, (u32, TEvent)>> {
+    let mut unfinished = BTreeMap::new();
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(unfinished),
+        Err(e) => return Err(e),
+    };
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Record>(&line) {
+            Ok(Record::Append { id, attempt, event }) => {
+                unfinished.insert(id, (attempt, event));
+            }
+            Ok(Record::Done { id }) => {
+                unfinished.remove(&id);
+            }
+            // A crash while appending can leave the last line truncated.
+            Err(e) => warn!(line = number + 1, error = %e, "Skipping unreadable journal line"),
+        }
+    }
+    Ok(unfinished)
+}
+
+/// Rewrites the journal with only the unfinished entries and returns it opened for appending.
+fn compact(
+    path: &Path,
+    unfinished: &BTreeMap<u64, (u32, TEvent)>,
+) -> std::io::Result<std::fs::File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("compact");
+    {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        for (id, (attempt, event)) in unfinished {
+            let record = Record::Append {
+                id: *id,
+                attempt: *attempt,
+                event: event.clone(),
+            };
+            serde_json::to_writer(&mut file, &record)?;
+            file.write_all(b"\n")?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    std::fs::rename(&tmp, path)?;
+    std::fs::OpenOptions::new().append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn event(name: &str) -> TEvent {
+        TEvent {
+            name: name.to_string(),
+            payload: Some(json!({"n": name})),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replays_only_unfinished_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("events.journal");
+
+        let journal = Journal::new(&path);
+        let replay = journal.take_replay().await.unwrap();
+        assert!(replay.is_empty());
+        let first = journal.append(&event("First"), 1).await.unwrap();
+        journal.append(&event("Second"), 2).await.unwrap();
+        journal.complete(first).await.unwrap();
+        drop(journal);
+
+        let journal = Journal::new(&path);
+        let replay = journal.take_replay().await.unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].event.name, "Second");
+        assert_eq!(replay[0].attempt, 2);
+        // Ids keep increasing after a restart.
+        assert!(journal.append(&event("Third"), 1).await.unwrap() > replay[0].journal_id.unwrap());
+
+        // Opening compacts the file down to the unfinished entries.
+        drop(journal);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_skips_truncated_lines() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("events.journal");
+        std::fs::write(
+            &path,
+            "{\"op\":\"append\",\"id\":1,\"attempt\":1,\"event\":{\"name\":\"A\",\"payload\":null}}\n{\"op\":\"app",
+        )
+        .unwrap();
+
+        let journal = Journal::new(&path);
+        let replay = journal.take_replay().await.unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].event.name, "A");
+    }
+}
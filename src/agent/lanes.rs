@@ -0,0 +1,159 @@
+// The `lanes` module orders the events waiting for a processing slot by priority,
+// without leaving the events of the lower priorities waiting forever.
+
+use super::Envelope;
+use crate::triggers::event::TEvent;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// How many events of higher priorities are processed, by default, before a waiting
+/// event of a lower priority is.
+pub(super) const DEFAULT_STARVATION_LIMIT: usize = 8;
+
+/// The priority of an event, which decides the order events waiting for a processing
+/// slot are processed in.
+///
+/// An event takes the priority of the first rule it matches, see
+/// [`AgentBuilder::add_priority_rule`](super::AgentBuilder::add_priority_rule), or
+/// else that of its trigger, see
+/// [`AgentBuilder::add_prioritized_trigger`](super::AgentBuilder::add_prioritized_trigger).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Processed once no event of a higher priority waits, such as newsletters.
+    Low,
+    /// The priority of every event, by default.
+    #[default]
+    Normal,
+    /// Processed before the other events, such as messages from a person.
+    High,
+}
+
+impl Priority {
+    /// The index of the priority's lane, the highest priority first.
+    pub(super) fn lane(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// A predicate giving its priority to the events it matches.
+pub(super) type PriorityRule = (Arc<dyn Fn(&TEvent) -> bool + Send + Sync>, Priority);
+
+/// The events of one priority, in the order they were queued.
+#[derive(Default)]
+struct Lane {
+    queue: VecDeque<(Option<String>, Envelope)>,
+    /// How many events of higher priorities were taken since this lane's last one.
+    skipped: usize,
+}
+
+/// The events waiting for a processing slot, with their ordering key, in a lane per
+/// priority.
+///
+/// The highest lane holding events is served first, except that a lane passed over
+/// `starvation_limit` times in a row is served next.
+pub(super) struct Lanes {
+    starvation_limit: usize,
+    lanes: [Lane; 3],
+}
+
+impl Default for Lanes {
+    fn default() -> Self {
+        Self::new(DEFAULT_STARVATION_LIMIT)
+    }
+}
+
+impl Lanes {
+    pub(super) fn new(starvation_limit: usize) -> Self {
+        Self {
+            starvation_limit,
+            lanes: Default::default(),
+        }
+    }
+
+    /// Queues an event in the lane of its priority.
+    pub(super) fn push(&mut self, key: Option<String>, envelope: Envelope) {
+        let lane = &mut self.lanes[envelope.priority.lane()];
+        if lane.queue.is_empty() {
+            lane.skipped = 0;
+        }
+        lane.queue.push_back((key, envelope));
+    }
+
+    /// Takes the next event to process.
+    pub(super) fn pop(&mut self) -> Option<(Option<String>, Envelope)> {
+        let starving = self
+            .lanes
+            .iter()
+            .position(|lane| !lane.queue.is_empty() && lane.skipped >= self.starvation_limit);
+        let chosen =
+            starving.or_else(|| self.lanes.iter().position(|lane| !lane.queue.is_empty()))?;
+        for (index, lane) in self.lanes.iter_mut().enumerate() {
+            if index == chosen {
+                lane.skipped = 0;
+            } else if index > chosen && !lane.queue.is_empty() {
+                lane.skipped += 1;
+            }
+        }
+        self.lanes[chosen].queue.pop_front()
+    }
+
+    /// Returns, for each lane, the highest priority first, the number of events waiting.
+    pub(super) fn waiting(&self) -> [usize; 3] {
+        self.lanes.each_ref().map(|lane| lane.queue.len())
+    }
+
+    /// Removes every waiting event.
+    pub(super) fn clear(&mut self) -> Vec<Envelope> {
+        self.lanes
+            .iter_mut()
+            .flat_map(|lane| lane.queue.drain(..).map(|(_, envelope)| envelope))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(lanes: &mut Lanes) -> Vec<String> {
+        std::iter::from_fn(|| lanes.pop())
+            .map(|(_, envelope)| envelope.event.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_serves_higher_lanes_first_without_starving_lower_ones() {
+        let mut lanes = Lanes::new(2);
+        for n in 0..4 {
+            lanes.push(
+                None,
+                Envelope::named(&format!("Newsletter{n}"), Priority::Low),
+            );
+        }
+        lanes.push(None, Envelope::named("Email", Priority::Normal));
+        lanes.push(None, Envelope::named("TelegramMessage", Priority::High));
+        lanes.push(None, Envelope::named("Email2", Priority::Normal));
+        lanes.push(None, Envelope::named("TelegramMessage2", Priority::High));
+        assert_eq!(lanes.waiting(), [2, 2, 4]);
+
+        assert_eq!(
+            drain(&mut lanes),
+            [
+                "TelegramMessage",
+                "TelegramMessage2",
+                // Each lane is served once it was passed over twice.
+                "Email",
+                "Newsletter0",
+                "Email2",
+                "Newsletter1",
+                "Newsletter2",
+                "Newsletter3",
+            ]
+        );
+        assert!(lanes.clear().is_empty());
+    }
+}
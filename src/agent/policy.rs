@@ -0,0 +1,598 @@
+// The `policy` module paces the events the agent receives before they are dispatched:
+// rate limits, debounces and throttles, chosen by event name.
+
+use super::{AgentError, Envelope};
+use crate::triggers::event::{KeyExtractor, TEvent};
+use crate::utils::EventPattern;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The number of events a rate limit or a queuing throttle holds for one group; the
+/// next events of the group are refused until it catches up.
+const MAX_HELD_PER_GROUP: usize = 100;
+
+/// What a throttle does with the events above its limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// The events wait for the next window, in the order they were received. Past 100
+    /// waiting events, the next ones are refused.
+    #[default]
+    Queue,
+    /// The events are refused, so that their trigger can emit them again later.
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PolicyKind {
+    RateLimit {
+        capacity: u32,
+        refill: Duration,
+    },
+    Debounce {
+        window: Duration,
+    },
+    Throttle {
+        max: u32,
+        window: Duration,
+        overflow: Overflow,
+    },
+}
+
+/// Paces the events matching a pattern, given to
+/// [`AgentBuilder::add_event_policy`](super::AgentBuilder::add_event_policy).
+///
+/// A policy applies to each event name on its own: with `Telegram*`, a burst of
+/// `TelegramMessage` events does not hold back `TelegramCallback` events. With
+/// [`with_key`](Self::with_key), it applies to each key on its own, e.g. each chat.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::agent::{AgentBuilder, EventPolicy, Overflow};
+/// use forgeflow::triggers::event::payload_key;
+///
+/// let agent = AgentBuilder::new()
+///     // At most 10 emails a minute; the others wait their turn.
+///     .add_event_policy("NewEmail", EventPolicy::rate_limit(10, Duration::from_secs(60)))
+///     // One prompt per chat for a burst of messages, with the last one.
+///     .add_event_policy(
+///         "TelegramMessage",
+///         EventPolicy::debounce(Duration::from_secs(2)).with_key(payload_key("chat.id")),
+///     )
+///     .build()?;
+/// ```
+#[derive(Clone)]
+pub struct EventPolicy {
+    kind: PolicyKind,
+    key: Option<KeyExtractor>,
+}
+
+impl EventPolicy {
+    /// A token bucket letting `events` events through every `per`, in bursts of up to
+    /// `events`.
+    ///
+    /// The excess events are delayed until a token is available, in order. Past 100
+    /// delayed events, the next ones are refused.
+    pub fn rate_limit(events: u32, per: Duration) -> Self {
+        Self::new(PolicyKind::RateLimit {
+            capacity: events,
+            refill: per.checked_div(events).unwrap_or_default(),
+        })
+    }
+
+    /// Holds each event for `window`, and lets it through if no other event came
+    /// meanwhile.
+    ///
+    /// An event received within the window replaces the one held, which is refused
+    /// without being processed, and starts the window again: a burst of events is
+    /// processed once, with its last event.
+    pub fn debounce(window: Duration) -> Self {
+        Self::new(PolicyKind::Debounce { window })
+    }
+
+    /// Lets `events` events through in each window of `per`, starting with the first
+    /// event, and queues or drops the excess events according to `overflow`.
+    pub fn throttle(events: u32, per: Duration, overflow: Overflow) -> Self {
+        Self::new(PolicyKind::Throttle {
+            max: events,
+            window: per,
+            overflow,
+        })
+    }
+
+    /// Applies the policy to the events of each key on their own, rather than to all
+    /// the events of a name. Events for which the extractor returns `None` share a
+    /// single group.
+    pub fn with_key(
+        mut self,
+        extractor: impl Fn(&TEvent) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Some(Arc::new(extractor));
+        self
+    }
+
+    fn new(kind: PolicyKind) -> Self {
+        Self { kind, key: None }
+    }
+
+    /// Checks that the policy lets events through at all.
+    fn validate(&self, pattern: &EventPattern) -> Result<(), AgentError> {
+        let valid = match self.kind {
+            PolicyKind::RateLimit { capacity, refill } => capacity > 0 && !refill.is_zero(),
+            PolicyKind::Debounce { window } => !window.is_zero(),
+            PolicyKind::Throttle { max, window, .. } => max > 0 && !window.is_zero(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(AgentError::BuildError(format!(
+                "The event policy of `{}` needs a non-zero number of events and duration.",
+                pattern.as_str()
+            )))
+        }
+    }
+}
+
+/// Why an event was set aside by its policy without being processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Discard {
+    /// A throttle dropped the event.
+    Throttled,
+    /// A later event replaced it under a debounce.
+    Superseded,
+    /// Its group already held as many events as it can.
+    Overflowed,
+}
+
+impl Discard {
+    /// The reason given to the trigger of the event.
+    pub(super) fn reason(self) -> &'static str {
+        match self {
+            Discard::Throttled => "throttled",
+            Discard::Superseded => "superseded",
+            Discard::Overflowed => "too many events held",
+        }
+    }
+}
+
+/// What becomes of an event given to the policies.
+pub(super) enum Admission {
+    /// The event goes on to be processed.
+    Pass(Envelope),
+    /// The event is held, until [`PolicyTable::release`] lets it through.
+    Held,
+    /// This event is set aside: either the event given, or the one it replaces.
+    Discard(Envelope, Discard),
+}
+
+/// The state of a policy for one event name, or one key.
+enum Group {
+    Bucket {
+        tokens: f64,
+        refilled: Instant,
+        queue: VecDeque<Envelope>,
+    },
+    Debounce {
+        held: Option<Envelope>,
+        until: Instant,
+    },
+    Window {
+        started: Instant,
+        admitted: u32,
+        queue: VecDeque<Envelope>,
+    },
+}
+
+impl Group {
+    fn new(kind: PolicyKind, now: Instant) -> Self {
+        match kind {
+            PolicyKind::RateLimit { capacity, .. } => Group::Bucket {
+                tokens: capacity as f64,
+                refilled: now,
+                queue: VecDeque::new(),
+            },
+            PolicyKind::Debounce { .. } => Group::Debounce {
+                held: None,
+                until: now,
+            },
+            PolicyKind::Throttle { .. } => Group::Window {
+                started: now,
+                admitted: 0,
+                queue: VecDeque::new(),
+            },
+        }
+    }
+
+    /// Adds the tokens earned since the last refill to a bucket, and rolls a window
+    /// over once it has elapsed.
+    fn advance(&mut self, kind: PolicyKind, now: Instant) {
+        match (self, kind) {
+            (
+                Group::Bucket {
+                    tokens, refilled, ..
+                },
+                PolicyKind::RateLimit { capacity, refill },
+            ) => {
+                let earned = now.duration_since(*refilled).as_secs_f64() / refill.as_secs_f64();
+                *tokens = (*tokens + earned).min(capacity as f64);
+                *refilled = now;
+            }
+            (
+                Group::Window {
+                    started, admitted, ..
+                },
+                PolicyKind::Throttle { window, .. },
+            ) if now >= *started + window => {
+                *started = now;
+                *admitted = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn admit(&mut self, kind: PolicyKind, envelope: Envelope, now: Instant) -> Admission {
+        self.advance(kind, now);
+        match (self, kind) {
+            (Group::Bucket { tokens, queue, .. }, _) => {
+                if queue.is_empty() && *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return Admission::Pass(envelope);
+                }
+                hold(queue, envelope)
+            }
+            (Group::Debounce { held, until }, PolicyKind::Debounce { window }) => {
+                *until = now + window;
+                match held.replace(envelope) {
+                    Some(replaced) => Admission::Discard(replaced, Discard::Superseded),
+                    None => Admission::Held,
+                }
+            }
+            (
+                Group::Window {
+                    admitted, queue, ..
+                },
+                PolicyKind::Throttle { max, overflow, .. },
+            ) => {
+                if queue.is_empty() && *admitted < max {
+                    *admitted += 1;
+                    return Admission::Pass(envelope);
+                }
+                match overflow {
+                    Overflow::Queue => hold(queue, envelope),
+                    Overflow::Drop => Admission::Discard(envelope, Discard::Throttled),
+                }
+            }
+            _ => unreachable!("a group is created for the kind of its policy"),
+        }
+    }
+
+    /// Returns when the group has an event to release, if it holds any.
+    fn deadline(&self, kind: PolicyKind) -> Option<Instant> {
+        match (self, kind) {
+            (
+                Group::Bucket {
+                    tokens,
+                    refilled,
+                    queue,
+                },
+                PolicyKind::RateLimit { refill, .. },
+            ) if !queue.is_empty() => Some(*refilled + refill.mul_f64((1.0 - tokens).max(0.0))),
+            (Group::Debounce { held, until }, _) if held.is_some() => Some(*until),
+            (Group::Window { started, queue, .. }, PolicyKind::Throttle { window, .. })
+                if !queue.is_empty() =>
+            {
+                Some(*started + window)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the events due at `now` to `released`.
+    fn release(&mut self, kind: PolicyKind, now: Instant, released: &mut Vec<Envelope>) {
+        self.advance(kind, now);
+        match (self, kind) {
+            (Group::Bucket { tokens, queue, .. }, _) => {
+                while *tokens >= 1.0 {
+                    let Some(envelope) = queue.pop_front() else {
+                        break;
+                    };
+                    *tokens -= 1.0;
+                    released.push(envelope);
+                }
+            }
+            (Group::Debounce { held, until }, _) => {
+                if now >= *until {
+                    released.extend(held.take());
+                }
+            }
+            (
+                Group::Window {
+                    admitted, queue, ..
+                },
+                PolicyKind::Throttle { max, .. },
+            ) => {
+                while *admitted < max {
+                    let Some(envelope) = queue.pop_front() else {
+                        break;
+                    };
+                    *admitted += 1;
+                    released.push(envelope);
+                }
+            }
+            _ => unreachable!("a group is created for the kind of its policy"),
+        }
+    }
+
+    /// Returns `true` if the group is back to its initial state, and can be forgotten.
+    fn is_idle(&self, kind: PolicyKind, now: Instant) -> bool {
+        match (self, kind) {
+            (Group::Bucket { tokens, queue, .. }, PolicyKind::RateLimit { capacity, .. }) => {
+                queue.is_empty() && *tokens >= capacity as f64
+            }
+            (Group::Debounce { held, .. }, _) => held.is_none(),
+            (Group::Window { started, queue, .. }, PolicyKind::Throttle { window, .. }) => {
+                queue.is_empty() && now >= *started + window
+            }
+            _ => false,
+        }
+    }
+
+    fn drain(&mut self, drained: &mut Vec<Envelope>) {
+        match self {
+            Group::Bucket { queue, .. } | Group::Window { queue, .. } => {
+                drained.extend(queue.drain(..))
+            }
+            Group::Debounce { held, .. } => drained.extend(held.take()),
+        }
+    }
+
+    fn held(&self) -> usize {
+        match self {
+            Group::Bucket { queue, .. } | Group::Window { queue, .. } => queue.len(),
+            Group::Debounce { held, .. } => usize::from(held.is_some()),
+        }
+    }
+}
+
+/// Queues an event in a group, unless the group is full.
+fn hold(queue: &mut VecDeque<Envelope>, envelope: Envelope) -> Admission {
+    if queue.len() >= MAX_HELD_PER_GROUP {
+        return Admission::Discard(envelope, Discard::Overflowed);
+    }
+    queue.push_back(envelope);
+    Admission::Held
+}
+
+/// Identifies a group: the policy, the event name and the key, if the policy has one.
+type GroupKey = (usize, String, Option<String>);
+
+/// The policies of an agent, with the state of each group of events.
+///
+/// The first policy whose pattern matches an event applies to it; the events that match
+/// no policy pass through.
+#[derive(Default)]
+pub(super) struct PolicyTable {
+    policies: Vec<(EventPattern, EventPolicy)>,
+    groups: HashMap<GroupKey, Group>,
+}
+
+impl PolicyTable {
+    pub(super) fn compile(policies: Vec<(EventPattern, EventPolicy)>) -> Result<Self, AgentError> {
+        for (pattern, policy) in &policies {
+            policy.validate(pattern)?;
+        }
+        Ok(Self {
+            policies,
+            groups: HashMap::new(),
+        })
+    }
+
+    /// Returns `true` if the table has no policy.
+    pub(super) fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Applies the matching policy, if any, to an event received at `now`.
+    pub(super) fn admit(&mut self, envelope: Envelope, now: Instant) -> Admission {
+        let event = &envelope.event;
+        let Some(index) = self
+            .policies
+            .iter()
+            .position(|(pattern, _)| pattern.matches(&event.name))
+        else {
+            return Admission::Pass(envelope);
+        };
+        let policy = &self.policies[index].1;
+        let key = policy.key.as_ref().and_then(|extract| extract(event));
+        let kind = policy.kind;
+        self.groups
+            .entry((index, event.name.clone(), key))
+            .or_insert_with(|| Group::new(kind, now))
+            .admit(kind, envelope, now)
+    }
+
+    /// Returns when the next held event is due, if any is held.
+    pub(super) fn next_release(&self) -> Option<Instant> {
+        self.groups
+            .iter()
+            .filter_map(|((index, ..), group)| group.deadline(self.policies[*index].1.kind))
+            .min()
+    }
+
+    /// Returns the held events due at `now`, and forgets the groups left idle.
+    pub(super) fn release(&mut self, now: Instant) -> Vec<Envelope> {
+        let mut released = Vec::new();
+        let policies = &self.policies;
+        self.groups.retain(|(index, ..), group| {
+            let kind = policies[*index].1.kind;
+            group.release(kind, now, &mut released);
+            !group.is_idle(kind, now)
+        });
+        released
+    }
+
+    /// Returns every held event, due or not.
+    pub(super) fn drain(&mut self) -> Vec<Envelope> {
+        let mut drained = Vec::new();
+        for group in self.groups.values_mut() {
+            group.drain(&mut drained);
+        }
+        self.groups.clear();
+        drained
+    }
+
+    /// Returns the number of events held.
+    pub(super) fn held(&self) -> usize {
+        self.groups.values().map(Group::held).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::triggers::event::payload_key;
+    use serde_json::json;
+
+    fn table(pattern: &str, policy: EventPolicy) -> PolicyTable {
+        PolicyTable::compile(vec![(EventPattern::new(pattern), policy)]).unwrap()
+    }
+
+    fn event(name: &str, n: u32) -> Envelope {
+        Envelope::new(TEvent {
+            name: name.to_string(),
+            payload: Some(json!({ "n": n, "chat": n % 2 })),
+        })
+    }
+
+    fn n(envelope: &Envelope) -> u64 {
+        envelope.event.payload.as_ref().unwrap()["n"]
+            .as_u64()
+            .unwrap()
+    }
+
+    fn passed(admission: Admission) -> Option<u64> {
+        match admission {
+            Admission::Pass(envelope) => Some(n(&envelope)),
+            _ => None,
+        }
+    }
+
+    fn released(table: &mut PolicyTable, now: Instant) -> Vec<u64> {
+        table.release(now).iter().map(n).collect()
+    }
+
+    #[test]
+    fn test_rate_limit_delays_the_events_above_the_bucket() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut table = table("Ne*", EventPolicy::rate_limit(2, Duration::from_secs(1)));
+
+        assert_eq!(passed(table.admit(event("NewEmail", 0), start)), Some(0));
+        assert_eq!(passed(table.admit(event("NewEmail", 1), start)), Some(1));
+        assert!(matches!(
+            table.admit(event("NewEmail", 2), start),
+            Admission::Held
+        ));
+        assert!(matches!(
+            table.admit(event("NewEmail", 3), start),
+            Admission::Held
+        ));
+        // Other names have their own bucket, and unmatched names no policy.
+        assert_eq!(passed(table.admit(event("NewsItem", 4), start)), Some(4));
+        assert_eq!(passed(table.admit(event("Tick", 5), start)), Some(5));
+        assert_eq!(table.held(), 2);
+
+        // A token is earned every 500ms.
+        assert_eq!(table.next_release(), Some(ms(500)));
+        assert!(released(&mut table, ms(400)).is_empty());
+        assert_eq!(released(&mut table, ms(500)), [2]);
+        assert_eq!(table.next_release(), Some(ms(1000)));
+        assert_eq!(released(&mut table, ms(1000)), [3]);
+        assert_eq!(table.next_release(), None);
+    }
+
+    #[test]
+    fn test_rate_limit_refuses_the_events_above_the_held_limit() {
+        let start = Instant::now();
+        let mut table = table("*", EventPolicy::rate_limit(1, Duration::from_secs(1)));
+
+        assert_eq!(passed(table.admit(event("Tick", 0), start)), Some(0));
+        for n in 1..=MAX_HELD_PER_GROUP as u32 {
+            assert!(matches!(
+                table.admit(event("Tick", n), start),
+                Admission::Held
+            ));
+        }
+        assert!(matches!(
+            table.admit(event("Tick", 101), start),
+            Admission::Discard(_, Discard::Overflowed)
+        ));
+        // Other groups are not held back.
+        assert_eq!(passed(table.admit(event("Tock", 102), start)), Some(102));
+        assert_eq!(table.held(), MAX_HELD_PER_GROUP);
+    }
+
+    #[test]
+    fn test_debounce_keeps_the_last_event_of_a_burst_for_each_key() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut table = table(
+            "Chat",
+            EventPolicy::debounce(Duration::from_millis(100)).with_key(payload_key("chat")),
+        );
+
+        assert!(matches!(
+            table.admit(event("Chat", 0), start),
+            Admission::Held
+        ));
+        assert!(matches!(
+            table.admit(event("Chat", 1), ms(10)),
+            Admission::Held
+        ));
+        match table.admit(event("Chat", 2), ms(50)) {
+            Admission::Discard(replaced, Discard::Superseded) => assert_eq!(n(&replaced), 0),
+            _ => panic!("the first event of the chat should be superseded"),
+        }
+        assert_eq!(table.next_release(), Some(ms(110)));
+        assert_eq!(released(&mut table, ms(110)), [1]);
+        assert_eq!(released(&mut table, ms(150)), [2]);
+        assert_eq!(table.held(), 0);
+    }
+
+    #[test]
+    fn test_throttle_queues_or_drops_the_events_above_its_limit() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let per = Duration::from_millis(100);
+
+        let mut queued = table("*", EventPolicy::throttle(1, per, Overflow::Queue));
+        assert_eq!(passed(queued.admit(event("Tick", 0), start)), Some(0));
+        assert!(matches!(
+            queued.admit(event("Tick", 1), ms(10)),
+            Admission::Held
+        ));
+        assert!(matches!(
+            queued.admit(event("Tick", 2), ms(20)),
+            Admission::Held
+        ));
+        assert_eq!(queued.next_release(), Some(ms(100)));
+        assert_eq!(released(&mut queued, ms(100)), [1]);
+        assert_eq!(queued.drain().iter().map(n).collect::<Vec<_>>(), [2]);
+
+        let mut dropping = table("*", EventPolicy::throttle(1, per, Overflow::Drop));
+        assert_eq!(passed(dropping.admit(event("Tick", 0), start)), Some(0));
+        assert!(matches!(
+            dropping.admit(event("Tick", 1), ms(10)),
+            Admission::Discard(_, Discard::Throttled)
+        ));
+        assert_eq!(passed(dropping.admit(event("Tick", 2), ms(100))), Some(2));
+    }
+
+    #[test]
+    fn test_rejects_policies_letting_nothing_through() {
+        let policy = EventPolicy::rate_limit(0, Duration::from_secs(1));
+        let result = PolicyTable::compile(vec![(EventPattern::new("*"), policy)]);
+        assert!(matches!(result, Err(AgentError::BuildError(_))));
+    }
+}
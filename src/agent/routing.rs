@@ -0,0 +1,176 @@
+// The `routing` module selects the prompt template and the model used for each event,
+// based on the event name.
+
+use super::AgentError;
+use crate::llm::{LLM, LLMFactory, RetryConfig, decorators::RetryHook};
+use crate::metrics::Metrics;
+use crate::utils::{EventPattern, TEngine};
+use std::sync::Arc;
+
+/// The name under which the agent's default prompt template is registered.
+const DEFAULT_TEMPLATE: &str = "prompt";
+
+/// A route sends the events whose name matches a glob pattern to their own prompt
+/// template and, optionally, their own model and retry configuration.
+///
+/// Routes are tried in the order they were added to the [`AgentBuilder`](super::AgentBuilder);
+/// events that match no route use the agent's default model and prompt template.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::agent::{AgentBuilder, Route};
+///
+/// let agent = AgentBuilder::new()
+///     .with_model(Box::new(default_model))
+///     .with_prompt_template("Summarize: {{verbatim payload}}".to_string())
+///     .add_route(
+///         Route::new("Telegram*", "Answer {{payload.first_name}}: {{payload.text}}")
+///             .with_model(Box::new(chat_model))
+///             .with_retry_config(RetryConfig::aggressive()),
+///     )
+///     .build()?;
+/// ```
+pub struct Route {
+    pattern: EventPattern,
+    prompt_template: String,
+    model: Option<Box<dyn LLM>>,
+    retry_config: Option<RetryConfig>,
+}
+
+impl Route {
+    /// Creates a route rendering the events matching `pattern` with `prompt_template`.
+    pub fn new(pattern: &str, prompt_template: impl Into<String>) -> Self {
+        Self {
+            pattern: EventPattern::new(pattern),
+            prompt_template: prompt_template.into(),
+            model: None,
+            retry_config: None,
+        }
+    }
+
+    /// Sets the model for this route. Defaults to the agent's model.
+    pub fn with_model(mut self, model: Box<dyn LLM>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Sets the retry configuration for this route. Defaults to the agent's configuration.
+    pub fn with_retry_config(mut self, config: RetryConfig) -> Self {
+        self.retry_config = Some(config);
+        self
+    }
+
+    /// Removes the model of this route, which then uses the agent's model.
+    pub(super) fn without_model(mut self) -> Self {
+        self.model = None;
+        self
+    }
+}
+
+/// A route ready to process events: its template is registered and its model decorated.
+pub(super) struct CompiledRoute {
+    /// The pattern of the route, or `default` for the fallback route.
+    pub(super) name: String,
+    /// The name of the route's template in the template engine.
+    pub(super) template: String,
+    /// The model the rendered prompts are sent to.
+    pub(super) model: Box<dyn LLM>,
+    pattern: EventPattern,
+}
+
+/// The routes of an agent, resolved by event name.
+pub(super) struct RouteTable {
+    routes: Vec<CompiledRoute>,
+    fallback: Option<CompiledRoute>,
+}
+
+impl RouteTable {
+    /// Registers every route template and wraps every model with its retry configuration.
+    ///
+    /// Templates are parsed here so a broken template fails the build instead of the
+    /// first event that uses it.
+    pub(super) fn compile(
+        handlebars: &mut TEngine,
+        default_model: Option<Box<dyn LLM>>,
+        default_template: Option<String>,
+        retry_config: RetryConfig,
+        routes: Vec<Route>,
+        metrics: &Arc<Metrics>,
+    ) -> Result<Self, AgentError> {
+        if default_model.is_none() && (routes.is_empty() || default_template.is_some()) {
+            return Err(AgentError::BuildError("A model is required.".to_string()));
+        }
+        if default_template.is_none() && routes.is_empty() {
+            return Err(AgentError::BuildError(
+                "A prompt template is required.".to_string(),
+            ));
+        }
+
+        // The default model is shared by the fallback route and the routes without a model.
+        let default_model: Option<Arc<dyn LLM>> = default_model.map(Arc::from);
+
+        let mut compiled = Vec::with_capacity(routes.len());
+        for (index, route) in routes.into_iter().enumerate() {
+            let template = format!("route-{index}");
+            handlebars.register_template_string(&template, &route.prompt_template)?;
+            let base_model = match (route.model, &default_model) {
+                (Some(model), _) => model,
+                (None, Some(shared)) => Box::new(shared.clone()),
+                (None, None) => {
+                    return Err(AgentError::BuildError(format!(
+                        "Route '{}' has no model and the agent has no default model.",
+                        route.pattern
+                    )));
+                }
+            };
+            let retry_config = route.retry_config.unwrap_or_else(|| retry_config.clone());
+            let name = route.pattern.to_string();
+            compiled.push(CompiledRoute {
+                model: LLMFactory::create_with_retry_hook(
+                    base_model,
+                    Some(retry_config),
+                    Some(count_retries(metrics, &name)),
+                ),
+                name,
+                template,
+                pattern: route.pattern,
+            });
+        }
+
+        let fallback = match (default_template, default_model) {
+            (Some(template), Some(model)) => {
+                handlebars.register_template_string(DEFAULT_TEMPLATE, &template)?;
+                Some(CompiledRoute {
+                    name: "default".to_string(),
+                    template: DEFAULT_TEMPLATE.to_string(),
+                    model: LLMFactory::create_with_retry_hook(
+                        Box::new(model),
+                        Some(retry_config),
+                        Some(count_retries(metrics, "default")),
+                    ),
+                    pattern: EventPattern::new("*"),
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            routes: compiled,
+            fallback,
+        })
+    }
+
+    /// Returns the first route matching the event name, or the fallback route.
+    pub(super) fn resolve(&self, event_name: &str) -> Option<&CompiledRoute> {
+        self.routes
+            .iter()
+            .find(|route| route.pattern.matches(event_name))
+            .or(self.fallback.as_ref())
+    }
+}
+
+/// Returns a retry hook counting the retries of a route in the metrics.
+fn count_retries(metrics: &Arc<Metrics>, route: &str) -> RetryHook {
+    let (metrics, route) = (metrics.clone(), route.to_string());
+    Arc::new(move |_| metrics.llm_retried(&route))
+}
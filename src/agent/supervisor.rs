@@ -0,0 +1,570 @@
+// The `supervisor` module watches the agent's triggers and relaunches the ones that
+// crash or stop, according to their restart policy.
+
+use super::{AgentError, AgentHandle, Envelope, Priority};
+use crate::metrics::Metrics;
+use crate::triggers::{Trigger, event::AckedEvent};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, warn};
+
+/// When a trigger is relaunched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Restart {
+    /// The trigger is never relaunched.
+    #[default]
+    Never,
+    /// The trigger is relaunched if its task panics or it fails to launch.
+    OnFailure,
+    /// The trigger is relaunched whenever its task ends, even without an error.
+    Always,
+}
+
+/// How the agent relaunches a trigger whose task ended before the agent shut down.
+///
+/// Relaunches are delayed by an exponential backoff, starting at `base_delay` and
+/// doubling up to `max_delay`. A trigger that ran for `stable_after` before ending
+/// starts over: its backoff starts again at `base_delay`, and its earlier relaunches
+/// no longer count towards `max_restarts`.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::agent::RestartPolicy;
+///
+/// let agent = AgentBuilder::new()
+///     .add_supervised_trigger(Box::new(trigger), RestartPolicy::on_failure().with_max_restarts(5))
+///     .build()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    /// When the trigger is relaunched.
+    pub restart: Restart,
+    /// The delay before the first relaunch.
+    pub base_delay: Duration,
+    /// The longest delay between two relaunches.
+    pub max_delay: Duration,
+    /// The maximum number of relaunches in a row, or `None` for no limit.
+    pub max_restarts: Option<u32>,
+    /// How long a launch must run to reset the backoff and the relaunch count.
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    /// Triggers are not relaunched by default.
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+impl RestartPolicy {
+    /// Creates a policy that never relaunches the trigger.
+    pub fn never() -> Self {
+        Self::new(Restart::Never)
+    }
+
+    /// Creates a policy that relaunches the trigger when it fails.
+    pub fn on_failure() -> Self {
+        Self::new(Restart::OnFailure)
+    }
+
+    /// Creates a policy that relaunches the trigger whenever it ends.
+    pub fn always() -> Self {
+        Self::new(Restart::Always)
+    }
+
+    fn new(restart: Restart) -> Self {
+        Self {
+            restart,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_restarts: None,
+            stable_after: Duration::from_secs(300),
+        }
+    }
+
+    /// Sets the delay before the first relaunch and the longest delay between relaunches.
+    pub fn with_backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the maximum number of relaunches in a row.
+    pub fn with_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self
+    }
+
+    /// Sets how long a launch must run to reset the backoff and the relaunch count.
+    /// Defaults to 5 minutes.
+    pub fn with_stable_after(mut self, stable_after: Duration) -> Self {
+        self.stable_after = stable_after;
+        self
+    }
+
+    /// Returns the delay before a relaunch, given how many relaunches in a row already
+    /// happened.
+    fn delay(&self, restarts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(restarts);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    fn allows(&self, exit: &Exit, restarts: u32) -> bool {
+        let wanted = match (self.restart, exit) {
+            (Restart::Never, _) => false,
+            (Restart::OnFailure, Exit::Failed(_)) => true,
+            (Restart::OnFailure, Exit::Finished) => false,
+            (Restart::Always, _) => true,
+        };
+        wanted && self.max_restarts.is_none_or(|max| restarts < max)
+    }
+}
+
+/// The state of a trigger.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerState {
+    /// The trigger is being launched.
+    Starting,
+    /// The trigger's task is running.
+    Running,
+    /// The trigger ended and waits to be relaunched.
+    Restarting,
+    /// The trigger's task ended without an error and was not relaunched.
+    Finished,
+    /// The trigger failed and was not relaunched.
+    Failed,
+    /// The trigger was stopped by the agent.
+    Stopped,
+}
+
+/// The status of one of the agent's triggers.
+#[derive(Serialize, Clone, Debug)]
+pub struct TriggerInfo {
+    /// The id of the trigger.
+    pub id: String,
+    /// The current state of the trigger.
+    pub state: TriggerState,
+    /// How many times the trigger was relaunched.
+    pub restarts: u32,
+    /// The error that ended the trigger last, if any.
+    pub last_error: Option<String>,
+    /// Whether the trigger's events are held back, see [`AgentHandle::pause_trigger`](super::AgentHandle::pause_trigger).
+    pub paused: bool,
+}
+
+/// A trigger on the board.
+struct BoardEntry {
+    info: TriggerInfo,
+    /// The trigger's pause switch.
+    paused: watch::Sender<bool>,
+    /// Stops this trigger only.
+    stop: CancellationToken,
+    /// The trigger waiting for the agent to run, or the task supervising it.
+    slot: Slot,
+}
+
+enum Slot {
+    Pending(Supervised),
+    Launched(JoinHandle<()>),
+}
+
+/// Whether the board launches the triggers it is given.
+enum Launcher {
+    /// The agent is not running yet: triggers are launched when it starts.
+    Idle,
+    /// The agent is running: triggers are launched right away.
+    Running {
+        stop: CancellationToken,
+        supervisors: TaskTracker,
+    },
+    /// The agent has stopped its triggers: no trigger can be added.
+    Stopped,
+}
+
+/// The agent's triggers, shared between the agent, the supervisors and the handles.
+///
+/// Triggers can be added and removed while the agent runs; each one has its own stop
+/// token, a child of the token stopping every trigger at shutdown.
+pub(super) struct TriggerBoard {
+    entries: Mutex<Vec<BoardEntry>>,
+    launcher: Mutex<Launcher>,
+    next_index: AtomicUsize,
+    /// The restart policy of the triggers added without their own.
+    default_policy: RestartPolicy,
+    metrics: Arc<Metrics>,
+}
+
+impl TriggerBoard {
+    pub(super) fn new(default_policy: RestartPolicy, metrics: Arc<Metrics>) -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+            launcher: Mutex::new(Launcher::Idle),
+            next_index: AtomicUsize::new(0),
+            default_policy,
+            metrics,
+        }
+    }
+
+    /// Adds a trigger, whose events get `priority`, and returns its id.
+    ///
+    /// The trigger is launched right away if the agent is running, with `handle` to
+    /// send its events, or when the agent starts otherwise.
+    pub(super) fn add(
+        self: &Arc<Self>,
+        trigger: Arc<dyn Trigger>,
+        policy: Option<RestartPolicy>,
+        priority: Priority,
+        handle: &AgentHandle,
+    ) -> Result<String, AgentError> {
+        let launcher = self.launcher.lock().unwrap();
+        // The entry is pushed before the supervisor can update it, as in `start`.
+        let mut entries = self.entries.lock().unwrap();
+        let id = format!("trigger-{}", self.next_index.fetch_add(1, Ordering::SeqCst));
+        let supervised = Supervised {
+            id: id.clone(),
+            trigger,
+            policy: policy.unwrap_or_else(|| self.default_policy.clone()),
+            priority,
+        };
+        let (paused, paused_rx) = watch::channel(false);
+        let (stop, slot) = match &*launcher {
+            Launcher::Idle => (CancellationToken::new(), Slot::Pending(supervised)),
+            Launcher::Running { stop, supervisors } => {
+                let stop = stop.child_token();
+                let task = self.launch(supervisors, supervised, stop.clone(), paused_rx, handle);
+                (stop, Slot::Launched(task))
+            }
+            Launcher::Stopped => return Err(AgentError::NotRunning),
+        };
+        let info = TriggerInfo {
+            id: id.clone(),
+            state: TriggerState::Starting,
+            restarts: 0,
+            last_error: None,
+            paused: false,
+        };
+        let entry = BoardEntry {
+            info,
+            paused,
+            stop,
+            slot,
+        };
+        entries.push(entry);
+        Ok(id)
+    }
+
+    /// Launches the triggers added so far, and the ones added later right away.
+    ///
+    /// Does nothing if the triggers were already stopped.
+    pub(super) fn start(self: &Arc<Self>, handle: &AgentHandle, stop: CancellationToken) {
+        let mut launcher = self.launcher.lock().unwrap();
+        if !matches!(*launcher, Launcher::Idle) {
+            return;
+        }
+        let supervisors = TaskTracker::new();
+        let mut entries = self.entries.lock().unwrap();
+        info!(trigger_count = entries.len(), "Launching triggers");
+        for entry in entries.iter_mut() {
+            let Slot::Pending(supervised) = &entry.slot else {
+                continue;
+            };
+            // Pending triggers get their stop token from the agent's now.
+            entry.stop = stop.child_token();
+            let paused = entry.paused.subscribe();
+            let task = self.launch(
+                &supervisors,
+                supervised.clone(),
+                entry.stop.clone(),
+                paused,
+                handle,
+            );
+            entry.slot = Slot::Launched(task);
+        }
+        *launcher = Launcher::Running { stop, supervisors };
+    }
+
+    fn launch(
+        self: &Arc<Self>,
+        supervisors: &TaskTracker,
+        supervised: Supervised,
+        stop: CancellationToken,
+        paused: watch::Receiver<bool>,
+        handle: &AgentHandle,
+    ) -> JoinHandle<()> {
+        let context = SupervisorContext {
+            events: handle.clone(),
+            stop,
+            board: self.clone(),
+            metrics: self.metrics.clone(),
+        };
+        supervisors.spawn(supervised.run(context, paused))
+    }
+
+    /// Stops a trigger, waits for it to terminate and removes it from the board.
+    ///
+    /// Returns `false` if there is no trigger with this id.
+    pub(super) async fn remove(&self, id: &str) -> bool {
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.iter().position(|entry| entry.info.id == id) {
+                Some(index) => entries.remove(index),
+                None => return false,
+            }
+        };
+        info!(trigger = %id, "Removing trigger");
+        entry.stop.cancel();
+        if let Slot::Launched(task) = entry.slot
+            && let Err(e) = task.await
+        {
+            error!(trigger = %id, error = %e, "Error waiting for trigger to terminate");
+        }
+        true
+    }
+
+    /// Stops every trigger and waits for them to terminate. No trigger can be added after.
+    pub(super) async fn stop(&self) {
+        let launcher = std::mem::replace(&mut *self.launcher.lock().unwrap(), Launcher::Stopped);
+        if let Launcher::Running { stop, supervisors } = launcher {
+            info!(
+                trigger_count = supervisors.len(),
+                "Sending shutdown signal to all triggers"
+            );
+            stop.cancel();
+            supervisors.close();
+            debug!("Waiting for triggers to terminate");
+            supervisors.wait().await;
+        }
+        info!("All triggers have been shut down");
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut TriggerInfo)) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.info.id == id) {
+            change(&mut entry.info);
+        }
+    }
+
+    /// Pauses or resumes a trigger. Returns `false` if there is no trigger with this id.
+    pub(super) fn set_paused(&self, id: &str, paused: bool) -> bool {
+        let entries = self.entries.lock().unwrap();
+        match entries.iter().find(|entry| entry.info.id == id) {
+            Some(entry) => {
+                entry.paused.send_replace(paused);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the status of every trigger, in the order they were added.
+    pub(super) fn snapshot(&self) -> Vec<TriggerInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .map(|entry| TriggerInfo {
+                paused: *entry.paused.borrow(),
+                ..entry.info.clone()
+            })
+            .collect()
+    }
+}
+
+/// How a trigger's task ended.
+enum Exit {
+    Finished,
+    Failed(String),
+}
+
+/// A trigger with the policy it is supervised with.
+#[derive(Clone)]
+struct Supervised {
+    id: String,
+    trigger: Arc<dyn Trigger>,
+    policy: RestartPolicy,
+    /// The priority of the trigger's events.
+    priority: Priority,
+}
+
+/// What a supervisor shares with the agent.
+struct SupervisorContext {
+    /// Where the trigger's events go.
+    events: AgentHandle,
+    /// Cancelled when the trigger is removed or the agent stops its triggers.
+    stop: CancellationToken,
+    board: Arc<TriggerBoard>,
+    metrics: Arc<Metrics>,
+}
+
+impl Supervised {
+    /// Launches the trigger and keeps relaunching it according to its policy, until
+    /// the context's `stop` is cancelled.
+    ///
+    /// The trigger's events are held back while `paused` is true.
+    async fn run(self, context: SupervisorContext, paused: watch::Receiver<bool>) {
+        let Supervised {
+            id,
+            trigger,
+            policy,
+            priority,
+        } = self;
+        let SupervisorContext {
+            events,
+            stop,
+            board,
+            metrics,
+        } = context;
+        // Every launch of the trigger sends through this channel, so its events are
+        // counted under the trigger's id and can be held back.
+        let (trigger_tx, trigger_rx) = mpsc::channel::<AckedEvent>(1);
+        tokio::spawn(forward(
+            id.clone(),
+            priority,
+            trigger_rx,
+            events,
+            paused,
+            stop.clone(),
+        ));
+        let mut restarts = 0;
+        // The relaunches since the trigger last ran for `stable_after`.
+        let mut in_a_row = 0;
+        loop {
+            let launched = Instant::now();
+            let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+            let exit = match trigger.launch_acked(trigger_tx.clone(), shutdown_rx).await {
+                Ok(mut handle) => {
+                    debug!(trigger = %id, "Trigger launched successfully");
+                    board.update(&id, |info| info.state = TriggerState::Running);
+                    tokio::select! {
+                        result = &mut handle => match result {
+                            Ok(()) => Exit::Finished,
+                            Err(e) => Exit::Failed(e.to_string()),
+                        },
+                        _ = stop.cancelled() => {
+                            let _ = shutdown_tx.send(());
+                            if let Err(e) = handle.await {
+                                error!(trigger = %id, error = %e, "Error waiting for trigger to terminate");
+                            } else {
+                                debug!(trigger = %id, "Trigger terminated successfully");
+                            }
+                            break;
+                        }
+                    }
+                }
+                Err(e) => Exit::Failed(e.to_string()),
+            };
+            if stop.is_cancelled() {
+                break;
+            }
+
+            match &exit {
+                Exit::Finished => warn!(trigger = %id, "Trigger task ended"),
+                Exit::Failed(e) => error!(trigger = %id, error = %e, "Trigger failed"),
+            }
+            if launched.elapsed() >= policy.stable_after {
+                in_a_row = 0;
+            }
+            if !policy.allows(&exit, in_a_row) {
+                board.update(&id, |info| match exit {
+                    Exit::Finished => info.state = TriggerState::Finished,
+                    Exit::Failed(e) => {
+                        info.state = TriggerState::Failed;
+                        info.last_error = Some(e);
+                    }
+                });
+                return;
+            }
+
+            let delay = policy.delay(in_a_row);
+            in_a_row += 1;
+            restarts += 1;
+            info!(trigger = %id, restarts, delay = ?delay, "Restarting trigger");
+            metrics.trigger_restarted(&id);
+            board.update(&id, |info| {
+                info.state = TriggerState::Restarting;
+                info.restarts = restarts;
+                if let Exit::Failed(e) = exit {
+                    info.last_error = Some(e);
+                }
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop.cancelled() => break,
+            }
+        }
+        board.update(&id, |info| info.state = TriggerState::Stopped);
+    }
+}
+
+/// Forwards a trigger's events to the agent with the trigger's priority, holding them
+/// back while the trigger is paused.
+///
+/// An event held back when the agent stops is dropped, so the trigger sees it as not processed.
+async fn forward(
+    id: String,
+    priority: Priority,
+    mut trigger_rx: mpsc::Receiver<AckedEvent>,
+    events: AgentHandle,
+    mut paused: watch::Receiver<bool>,
+    stop: CancellationToken,
+) {
+    while let Some(AckedEvent { event, ack }) = trigger_rx.recv().await {
+        events.received(&id, &event).await;
+        if *paused.borrow() {
+            debug!(trigger = %id, "Trigger paused, holding its event back");
+        }
+        tokio::select! {
+            resumed = paused.wait_for(|paused| !paused) => {
+                if resumed.is_err() {
+                    break;
+                }
+            }
+            _ = stop.cancelled() => break,
+        }
+        let envelope = Envelope {
+            ack,
+            priority,
+            ..Envelope::new(event)
+        };
+        if events.send(envelope).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_maximum() {
+        let policy = RestartPolicy::always()
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+        assert_eq!(policy.delay(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_policies_decide_which_exits_restart() {
+        let failed = Exit::Failed("boom".to_string());
+        assert!(!RestartPolicy::never().allows(&failed, 0));
+        assert!(RestartPolicy::on_failure().allows(&failed, 0));
+        assert!(!RestartPolicy::on_failure().allows(&Exit::Finished, 0));
+        assert!(RestartPolicy::always().allows(&Exit::Finished, 0));
+        assert!(
+            !RestartPolicy::always()
+                .with_max_restarts(2)
+                .allows(&failed, 2)
+        );
+    }
+}
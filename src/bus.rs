@@ -0,0 +1,417 @@
+// The `bus` module provides the `EventBus`, which lets several agents share the events
+// of the same triggers.
+//
+// Triggers are launched once, on the bus, and every event they emit is published to
+// the subscriptions whose pattern matches its name. Agents consume a subscription
+// through a `BusTrigger`.
+
+use crate::triggers::event::{Ack, AckedEvent, Outcome, TEvent};
+use crate::triggers::{BusTrigger, BusTriggerBuilder, Trigger, TriggerError};
+use crate::utils::EventPattern;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, info, warn};
+
+/// What publishing does when a subscription's queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Publishing waits until the queue has room, which holds back the triggers and
+    /// every other subscription. Nothing is lost while the subscription's trigger
+    /// runs; once it has stopped, publishing skips the subscription.
+    #[default]
+    Wait,
+    /// The event is dropped for this subscription, and counted in
+    /// [`BusTrigger::dropped`]. The other subscriptions still receive it.
+    DropNewest,
+}
+
+/// The queue of a subscription, shared by the members of its group.
+pub(crate) struct Queue {
+    pattern: EventPattern,
+    group: Option<String>,
+    tx: mpsc::Sender<AckedEvent>,
+    /// Taken in turn by the members of the group, one event at a time.
+    pub(crate) rx: tokio::sync::Mutex<mpsc::Receiver<AckedEvent>>,
+    backpressure: Backpressure,
+    pub(crate) dropped: AtomicU64,
+    /// The number of launched triggers consuming the queue, or `None` until the
+    /// first one is launched.
+    consumers: watch::Sender<Option<usize>>,
+}
+
+impl Queue {
+    /// Registers a launched trigger consuming the queue, until the returned guard is
+    /// dropped.
+    pub(crate) fn consume(self: &Arc<Self>) -> Consumer {
+        self.consumers
+            .send_modify(|consumers| *consumers = Some(consumers.unwrap_or(0) + 1));
+        Consumer(self.clone())
+    }
+
+    /// Returns `true` if the triggers consuming the queue have all stopped.
+    fn is_closed(&self) -> bool {
+        *self.consumers.borrow() == Some(0)
+    }
+
+    /// Queues an event, and returns `false` if it was dropped.
+    ///
+    /// Waiting for room gives up once `stop` is cancelled, or once the queue is closed.
+    async fn deliver(&self, event: AckedEvent, stop: &CancellationToken) -> bool {
+        match self.backpressure {
+            Backpressure::Wait => {
+                let mut consumers = self.consumers.subscribe();
+                tokio::select! {
+                    biased;
+                    // The queue holds its own receiver, so the channel is never closed.
+                    sent = self.tx.send(event) => sent.is_ok(),
+                    _ = stop.cancelled() => false,
+                    _ = consumers.wait_for(|consumers| *consumers == Some(0)) => false,
+                }
+            }
+            Backpressure::DropNewest => match self.tx.try_send(event) {
+                Ok(()) => true,
+                Err(e) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        pattern = %self.pattern,
+                        group = ?self.group,
+                        event_name = %e.into_inner().event.name,
+                        "Subscription queue is full, dropping the event"
+                    );
+                    false
+                }
+            },
+        }
+    }
+}
+
+/// Keeps a queue open while the trigger consuming it runs.
+///
+/// Once the last consumer is gone, the queue is closed: the events it holds are
+/// dropped, so their publishers see them as not processed, and it receives no more
+/// events until a trigger consumes it again.
+pub(crate) struct Consumer(Arc<Queue>);
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        let mut closed = false;
+        self.0.consumers.send_modify(|consumers| {
+            let left = consumers.unwrap_or(1) - 1;
+            *consumers = Some(left);
+            closed = left == 0;
+        });
+        if closed && let Ok(mut rx) = self.0.rx.try_lock() {
+            let mut count = 0;
+            while rx.try_recv().is_ok() {
+                count += 1;
+            }
+            if count > 0 {
+                debug!(pattern = %self.0.pattern, count, "Subscription closed, dropping its events");
+            }
+        }
+    }
+}
+
+struct Inner {
+    /// The live subscriptions; those whose triggers are all gone are pruned.
+    queues: Mutex<Vec<Weak<Queue>>>,
+    /// Stops the triggers launched on the bus.
+    shutdown: broadcast::Sender<()>,
+    /// Cancelled once the bus is shut down, so that publishing no longer waits.
+    stop: CancellationToken,
+    /// The triggers launched on the bus, and the tasks publishing their events.
+    tasks: TaskTracker,
+}
+
+/// An in-process bus between triggers and agents.
+///
+/// A trigger added to the bus is launched once, and its events are published to
+/// every subscription whose pattern matches their name:
+///
+/// * Each subscription made with [`subscribe`](Self::subscribe) receives its own copy
+///   of the events (broadcast).
+/// * The subscriptions of a group, made with [`BusTriggerBuilder::in_group`], share
+///   one queue: each event goes to only one of them (work queue).
+///
+/// Each subscription has a bounded queue, and its [`Backpressure`] decides what
+/// happens when it is full. The event emitted by a trigger is acked once every
+/// subscription that received it has processed it, and nacked if one of them failed.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let bus = EventBus::new();
+/// bus.add_trigger(Box::new(gmail)).await?;
+/// let triage = AgentBuilder::new().add_trigger(Box::new(bus.subscribe("NewEmail")));
+/// let archive = AgentBuilder::new().add_trigger(Box::new(bus.subscribe("*")));
+/// ```
+#[derive(Clone)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Creates a bus without triggers or subscriptions.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                queues: Mutex::new(Vec::new()),
+                shutdown: broadcast::channel(1).0,
+                stop: CancellationToken::new(),
+                tasks: TaskTracker::new(),
+            }),
+        }
+    }
+
+    /// Subscribes to the events whose name matches `pattern`, with the default
+    /// capacity and backpressure.
+    ///
+    /// See [`BusTriggerBuilder`] for the other options.
+    pub fn subscribe(&self, pattern: &str) -> BusTrigger {
+        BusTriggerBuilder::new(self, pattern).build()
+    }
+
+    /// Returns the queue of a new subscription, or the queue of `group` if it has
+    /// live members already.
+    ///
+    /// The first member of a group decides its pattern, capacity and backpressure.
+    pub(crate) fn queue(
+        &self,
+        pattern: EventPattern,
+        group: Option<String>,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> Arc<Queue> {
+        let mut queues = self.inner.queues.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        if let Some(group) = &group {
+            let existing = queues
+                .iter()
+                .filter_map(Weak::upgrade)
+                .find(|queue| queue.group.as_ref() == Some(group));
+            if let Some(queue) = existing {
+                return queue;
+            }
+        }
+        let (tx, rx) = mpsc::channel(capacity);
+        let queue = Arc::new(Queue {
+            pattern,
+            group,
+            tx,
+            rx: tokio::sync::Mutex::new(rx),
+            backpressure,
+            dropped: AtomicU64::new(0),
+            consumers: watch::Sender::new(None),
+        });
+        queues.push(Arc::downgrade(&queue));
+        queue
+    }
+
+    /// Launches a trigger and publishes its events, until the bus is shut down.
+    pub async fn add_trigger(&self, trigger: Box<dyn Trigger>) -> Result<(), TriggerError> {
+        let (tx, mut rx) = mpsc::channel::<AckedEvent>(1);
+        let handle = trigger
+            .launch_acked(tx, self.inner.shutdown.subscribe())
+            .await?;
+        let bus = self.clone();
+        self.inner.tasks.spawn(async move {
+            while let Some(event) = rx.recv().await {
+                bus.publish_acked(event).await;
+            }
+        });
+        self.inner.tasks.spawn(async move {
+            if let Err(e) = handle.await {
+                warn!(error = %e, "Bus trigger task failed");
+            }
+        });
+        Ok(())
+    }
+
+    /// Publishes an event, and returns the number of subscriptions it was delivered to.
+    pub async fn publish(&self, event: TEvent) -> usize {
+        self.publish_acked(AckedEvent::from(event)).await
+    }
+
+    /// Publishes an event, reporting its outcome once every subscription it was
+    /// delivered to has processed it.
+    ///
+    /// An event no subscription matches is acked; one that was dropped by every
+    /// matching subscription is reported as not processed. Subscriptions whose
+    /// triggers have all stopped are skipped.
+    pub async fn publish_acked(&self, event: AckedEvent) -> usize {
+        let AckedEvent { event, ack } = event;
+        let queues: Vec<Arc<Queue>> = self
+            .inner
+            .queues
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|queue| queue.pattern.matches(&event.name) && !queue.is_closed())
+            .collect();
+        if queues.is_empty() {
+            debug!(event_name = %event.name, "No subscription for the event");
+            ack.ack();
+            return 0;
+        }
+
+        let mut outcomes = Vec::with_capacity(queues.len());
+        for queue in queues {
+            let (copy_ack, outcome) = Ack::channel();
+            let copy = AckedEvent {
+                event: event.clone(),
+                ack: copy_ack,
+            };
+            if queue.deliver(copy, &self.inner.stop).await {
+                outcomes.push(outcome);
+            }
+        }
+        let delivered = outcomes.len();
+        if delivered > 0 {
+            tokio::spawn(async move {
+                // The event counts as processed if any subscriber processed it.
+                let mut skipped = None;
+                let mut acked = false;
+                for outcome in outcomes {
+                    match outcome.await {
+                        Ok(Outcome::Ack) => acked = true,
+                        Ok(Outcome::Nack(reason)) => return ack.nack(reason),
+                        Ok(Outcome::Skipped(reason)) => skipped = Some(reason),
+                        // Dropping the ack reports the event as not processed.
+                        Err(_) => return,
+                    }
+                }
+                match skipped {
+                    Some(reason) if !acked => ack.skip(reason),
+                    _ => ack.ack(),
+                }
+            });
+        }
+        delivered
+    }
+
+    /// Stops the triggers launched on the bus, and waits for their last events to be
+    /// published.
+    ///
+    /// Events that would wait for room in a full queue are dropped instead.
+    pub async fn shutdown(&self) {
+        let _ = self.inner.shutdown.send(());
+        self.inner.stop.cancel();
+        self.inner.tasks.close();
+        self.inner.tasks.wait().await;
+        info!("Event bus has shut down");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn event(name: &str) -> TEvent {
+        TEvent {
+            name: name.to_string(),
+            payload: None,
+        }
+    }
+
+    // Launches a subscription and returns the events it emits.
+    async fn consume(trigger: BusTrigger) -> mpsc::Receiver<AckedEvent> {
+        let (tx, rx) = mpsc::channel(16);
+        // The shutdown sender is leaked so that the trigger keeps running.
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        std::mem::forget(shutdown_tx);
+        trigger.launch_acked(tx, shutdown_rx).await.unwrap();
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_to_subscriptions_and_shares_groups() {
+        let bus = EventBus::new();
+        let mut all = consume(bus.subscribe("*")).await;
+        let mut emails = consume(bus.subscribe("New*")).await;
+        let first = BusTriggerBuilder::new(&bus, "*")
+            .in_group("workers")
+            .build();
+        let second = BusTriggerBuilder::new(&bus, "*")
+            .in_group("workers")
+            .build();
+        let mut workers = [consume(first).await, consume(second).await];
+
+        assert_eq!(bus.publish(event("NewEmail")).await, 3);
+        assert_eq!(bus.publish(event("Tick")).await, 2);
+        assert_eq!(bus.publish(event("Tick")).await, 2);
+
+        assert_eq!(all.recv().await.unwrap().event.name, "NewEmail");
+        assert_eq!(all.recv().await.unwrap().event.name, "Tick");
+        assert_eq!(emails.recv().await.unwrap().event.name, "NewEmail");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(emails.try_recv().is_err());
+        // Each event of the group went to one of its members.
+        let received: usize = workers.iter_mut().map(|rx| rx.len()).sum();
+        assert_eq!(received, 3);
+    }
+
+    #[tokio::test]
+    async fn test_drops_events_for_full_subscriptions() {
+        let bus = EventBus::new();
+        let slow = BusTriggerBuilder::new(&bus, "*")
+            .with_capacity(1)
+            .with_backpressure(Backpressure::DropNewest)
+            .build();
+        let _all = bus.subscribe("*");
+
+        assert_eq!(bus.publish(event("Tick")).await, 2);
+        assert_eq!(bus.publish(event("Tick")).await, 1);
+        assert_eq!(slow.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_acks_once_every_subscription_has_processed_the_event() {
+        let bus = EventBus::new();
+        let mut first = consume(bus.subscribe("*")).await;
+        let mut second = consume(bus.subscribe("*")).await;
+
+        let (ack, mut outcome) = Ack::channel();
+        bus.publish_acked(AckedEvent {
+            event: event("Tick"),
+            ack,
+        })
+        .await;
+        first.recv().await.unwrap().ack.ack();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(outcome.try_recv().is_err());
+        second.recv().await.unwrap().ack.nack("boom");
+        assert_eq!(outcome.await.unwrap(), Outcome::Nack("boom".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_stopped_subscriptions_no_longer_hold_back_publishing() {
+        let bus = EventBus::new();
+        let mut live = consume(bus.subscribe("*")).await;
+        let stopped = BusTriggerBuilder::new(&bus, "*").with_capacity(1).build();
+        let (tx, _rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let handle = stopped.launch_acked(tx, shutdown_rx).await.unwrap();
+        shutdown_tx.send(()).unwrap();
+        handle.await.unwrap();
+
+        for _ in 0..3 {
+            let published =
+                tokio::time::timeout(Duration::from_secs(1), bus.publish(event("Tick")));
+            assert_eq!(published.await, Ok(1));
+        }
+        for _ in 0..3 {
+            assert_eq!(live.recv().await.unwrap().event.name, "Tick");
+        }
+    }
+}
@@ -0,0 +1,940 @@
+// The `config` module builds agents from configuration files.
+//
+// A configuration names its components by type, with their parameters. The types are
+// resolved through a `ComponentRegistry`, which knows the components of this crate and
+// can be extended with those of other crates.
+
+mod registry;
+
+pub use registry::{BuildContext, ComponentRegistry, ConfiguredHandler, Pending, ready};
+
+use crate::agent::{AgentBuilder, Batch, EventPolicy, Overflow, Priority, Route};
+use crate::dead_letter::DeadLetterDir;
+use crate::dedupe::{FileDedupeStore, MemoryDedupeStore};
+use crate::handlers::JsonlFileHandler;
+use crate::llm::{RetryConfig, RetryStrategy};
+use crate::shutdown::TimeBasedShutdown;
+use crate::triggers::event::{TEvent, payload_key};
+use crate::utils::{EventPattern, TEngine, TEngineError};
+use rig::tool::ToolDyn;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value, json};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The `ConfigError` enum defines the possible errors that can occur while loading a
+/// configuration.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    /// An I/O error occurred while reading the configuration file.
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    /// The configuration could not be parsed.
+    #[error("Invalid {format} configuration: {message}")]
+    Parse {
+        /// The format of the configuration, e.g. `TOML`.
+        format: &'static str,
+        /// What is wrong, and where.
+        message: String,
+    },
+    /// The configuration file has an extension other than `.toml`, `.yaml`, `.yml` or
+    /// `.json`.
+    #[error("Unsupported configuration format: {0}")]
+    UnsupportedFormat(String),
+    /// The configuration names a component type the registry does not know.
+    #[error("Unknown {kind} type `{type_name}`")]
+    UnknownComponent {
+        /// The kind of component, e.g. `trigger`.
+        kind: &'static str,
+        /// The type named by the configuration.
+        type_name: String,
+    },
+    /// The parameters of a component are invalid.
+    #[error("Invalid parameters for {kind} `{type_name}`: {message}")]
+    InvalidParams {
+        /// The kind of component, e.g. `trigger`.
+        kind: &'static str,
+        /// The type of the component.
+        type_name: String,
+        /// What is wrong with the parameters.
+        message: String,
+    },
+    /// An environment variable named by the configuration is not set.
+    #[error("Environment variable `{0}` is not set")]
+    MissingEnv(String),
+    /// A component failed to build.
+    #[error("Failed to build {kind} `{type_name}`: {message}")]
+    Component {
+        /// The kind of component, e.g. `trigger`.
+        kind: &'static str,
+        /// The type of the component.
+        type_name: String,
+        /// Why it failed.
+        message: String,
+    },
+    /// A prompt template of the configuration is invalid, or failed to render.
+    #[error("Invalid prompt template `{template}`: {message}")]
+    Template {
+        /// The pattern of the template's route, or `default`.
+        template: String,
+        /// What is wrong with the template.
+        message: String,
+    },
+    /// The configuration is inconsistent.
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+impl ConfigError {
+    fn template(template: &str, error: TEngineError) -> Self {
+        // The engine's own messages do not say what is wrong.
+        let message = std::error::Error::source(&error)
+            .map(ToString::to_string)
+            .unwrap_or_else(|| error.to_string());
+        Self::Template {
+            template: template.to_string(),
+            message,
+        }
+    }
+
+    /// A component failed to build.
+    pub fn component(kind: &'static str, type_name: &str, error: impl ToString) -> Self {
+        Self::Component {
+            kind,
+            type_name: type_name.to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// A component of the configuration: its type, and the parameters of that type.
+///
+/// In TOML, a component is a table whose `type` key is the type and whose other keys
+/// are the parameters:
+///
+/// ```toml
+/// [[triggers]]
+/// type = "poll"
+/// event_name = "Tick"
+/// interval = "5m"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct ComponentConfig {
+    /// The type of the component, as registered in the [`ComponentRegistry`].
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// The parameters of the component.
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+/// A trigger of the configuration: a component, and the priority of its events.
+///
+/// ```toml
+/// [[triggers]]
+/// type = "telegram_bot"
+/// priority = "high"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct TriggerConfig {
+    /// The trigger.
+    #[serde(flatten)]
+    pub component: ComponentConfig,
+    /// `low`, `normal` or `high`, see [`AgentBuilder::add_prioritized_trigger`].
+    pub priority: Option<PrioritySetting>,
+}
+
+/// The retry settings of the configuration, which default to those of [`RetryConfig`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySettings {
+    /// The maximum number of retries; `0` disables retrying.
+    pub max_attempts: Option<usize>,
+    /// The delay before the first retry, e.g. `"500ms"`.
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub base_delay: Option<Duration>,
+    /// `fixed`, `exponential_backoff` or `exponential_backoff_with_jitter`.
+    pub strategy: Option<RetryStrategySetting>,
+    /// Retries every error, not only rate limits.
+    #[serde(default)]
+    pub retry_all_errors: bool,
+}
+
+/// The retry strategies a configuration can name.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryStrategySetting {
+    /// See [`RetryStrategy::Fixed`].
+    Fixed,
+    /// See [`RetryStrategy::ExponentialBackoff`].
+    ExponentialBackoff,
+    /// See [`RetryStrategy::ExponentialBackoffWithJitter`].
+    ExponentialBackoffWithJitter,
+}
+
+impl From<&RetrySettings> for RetryConfig {
+    fn from(settings: &RetrySettings) -> Self {
+        let mut config = RetryConfig::default();
+        if let Some(max_attempts) = settings.max_attempts {
+            config.max_attempts = max_attempts;
+        }
+        if let Some(base_delay) = settings.base_delay {
+            config.base_delay = base_delay;
+        }
+        if let Some(strategy) = settings.strategy {
+            config.strategy = match strategy {
+                RetryStrategySetting::Fixed => RetryStrategy::Fixed,
+                RetryStrategySetting::ExponentialBackoff => RetryStrategy::ExponentialBackoff,
+                RetryStrategySetting::ExponentialBackoffWithJitter => {
+                    RetryStrategy::ExponentialBackoffWithJitter
+                }
+            };
+        }
+        config.only_retry_rate_limits = !settings.retry_all_errors;
+        config
+    }
+}
+
+/// A route of the configuration, see [`Route`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// The pattern the names of the events must match, e.g. `Telegram*`.
+    pub pattern: String,
+    /// The prompt template of the route.
+    pub template: String,
+    /// The retry settings of the route, instead of those of the agent.
+    pub retry: Option<RetrySettings>,
+}
+
+/// A batch of the configuration, see [`Batch`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchConfig {
+    /// The name of the events collected.
+    pub event: String,
+    /// The number of events that completes a batch.
+    pub max_size: usize,
+    /// How long after its first event a batch is emitted at the latest, e.g. `"5m"`.
+    #[serde(deserialize_with = "duration")]
+    pub max_wait: Duration,
+    /// The name of the batch events, the name of the events with a `Batch` suffix by
+    /// default.
+    pub name: Option<String>,
+}
+
+/// An event policy of the configuration, see [`EventPolicy`]. Exactly one of
+/// `rate_limit`, `debounce` and `throttle` is set.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// The pattern the names of the events must match, e.g. `Telegram*`.
+    pub pattern: String,
+    /// The payload field the policy groups the events by, e.g. `"chat.id"`.
+    pub key: Option<String>,
+    /// A rate limit, e.g. `{ events = 10, per = "1m" }`.
+    pub rate_limit: Option<RateSettings>,
+    /// A debounce window, e.g. `"2s"`.
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub debounce: Option<Duration>,
+    /// A throttle, e.g. `{ events = 100, per = "1h", overflow = "drop" }`.
+    pub throttle: Option<ThrottleSettings>,
+}
+
+/// The settings of a rate limit, see [`EventPolicy::rate_limit`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateSettings {
+    /// The number of events let through every `per`.
+    pub events: u32,
+    /// The period, e.g. `"1m"`.
+    #[serde(deserialize_with = "duration")]
+    pub per: Duration,
+}
+
+/// The settings of a throttle, see [`EventPolicy::throttle`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleSettings {
+    /// The number of events let through in each window.
+    pub events: u32,
+    /// The window, e.g. `"1h"`.
+    #[serde(deserialize_with = "duration")]
+    pub per: Duration,
+    /// `queue`, by default, or `drop`.
+    #[serde(default)]
+    pub overflow: OverflowSetting,
+}
+
+/// What a throttle of the configuration does with the excess events.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowSetting {
+    /// See [`Overflow::Queue`].
+    #[default]
+    Queue,
+    /// See [`Overflow::Drop`].
+    Drop,
+}
+
+impl PolicyConfig {
+    /// Returns the policy, or `None` unless exactly one kind of policy is set.
+    pub fn policy(&self) -> Option<EventPolicy> {
+        let policy = match (&self.rate_limit, self.debounce, &self.throttle) {
+            (Some(rate), None, None) => EventPolicy::rate_limit(rate.events, rate.per),
+            (None, Some(window), None) => EventPolicy::debounce(window),
+            (None, None, Some(throttle)) => {
+                let overflow = match throttle.overflow {
+                    OverflowSetting::Queue => Overflow::Queue,
+                    OverflowSetting::Drop => Overflow::Drop,
+                };
+                EventPolicy::throttle(throttle.events, throttle.per, overflow)
+            }
+            _ => return None,
+        };
+        Some(match &self.key {
+            Some(key) => policy.with_key(payload_key(key)),
+            None => policy,
+        })
+    }
+}
+
+/// A priority rule of the configuration, see [`AgentBuilder::add_priority_rule`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityConfig {
+    /// The pattern the names of the events must match, e.g. `Telegram*`.
+    pub pattern: String,
+    /// `low`, `normal` or `high`.
+    pub priority: PrioritySetting,
+}
+
+/// The priority of the events of a trigger, or matching a rule, of the configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrioritySetting {
+    /// See [`Priority::Low`].
+    Low,
+    /// See [`Priority::Normal`].
+    Normal,
+    /// See [`Priority::High`].
+    High,
+}
+
+impl From<PrioritySetting> for Priority {
+    fn from(setting: PrioritySetting) -> Self {
+        match setting {
+            PrioritySetting::Low => Priority::Low,
+            PrioritySetting::Normal => Priority::Normal,
+            PrioritySetting::High => Priority::High,
+        }
+    }
+}
+
+/// The deduplication settings of the configuration, see
+/// [`AgentBuilder::with_deduplication`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DedupeSettings {
+    /// The payload field the key of each event is read from, e.g. `"id"`.
+    pub key: String,
+    /// The JSON Lines file the processed keys are kept in, so that they survive
+    /// restarts. Without it, they are kept in memory.
+    pub path: Option<PathBuf>,
+    /// How long each key is kept, e.g. `"7d"`. Required without a `path`.
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub ttl: Option<Duration>,
+}
+
+/// The configuration of an agent.
+///
+/// Every field is optional, so that a configuration can describe only part of an
+/// agent and leave the rest to code, e.g. a model that is not registered.
+///
+/// # Example
+///
+/// ```toml
+/// prompt_template = "Summarize this email: {{payload.body}}"
+/// drain_deadline = "30s"
+///
+/// [retry]
+/// max_attempts = 5
+/// base_delay = "500ms"
+///
+/// [model]
+/// type = "gemini"
+/// preamble = "You are an email assistant."
+///
+/// [[triggers]]
+/// type = "gmail_watch"
+/// credentials_path = "./credentials.json"
+/// token_path = "./token.json"
+///
+/// [[tools]]
+/// type = "daily_summary_writer"
+/// output_dir = "./summaries"
+///
+/// [[handlers]]
+/// type = "stdout"
+///
+/// [dedupe]
+/// key = "id"
+/// path = "./processed.jsonl"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    /// The prompt template of the events no route matches.
+    pub prompt_template: Option<String>,
+    /// The routes, tried in order.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// The model.
+    pub model: Option<ComponentConfig>,
+    /// The triggers.
+    #[serde(default)]
+    pub triggers: Vec<TriggerConfig>,
+    /// The tools given to the model.
+    #[serde(default)]
+    pub tools: Vec<ComponentConfig>,
+    /// The response and error handlers.
+    #[serde(default)]
+    pub handlers: Vec<ComponentConfig>,
+    /// The retry settings of the agent.
+    pub retry: Option<RetrySettings>,
+    /// The maximum number of events processed at the same time.
+    pub concurrency: Option<usize>,
+    /// How long the agent drains its queue on shutdown, e.g. `"30s"`.
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub drain_deadline: Option<Duration>,
+    /// The file the agent journals its events to.
+    pub journal: Option<PathBuf>,
+    /// The directory the agent keeps its dead letters in.
+    pub dead_letters: Option<PathBuf>,
+    /// The address the metrics are served on.
+    pub metrics_listener: Option<SocketAddr>,
+    /// The address the admin API is served on.
+    pub admin_listener: Option<SocketAddr>,
+    /// Shuts the agent down after this long, instead of on Ctrl-C.
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub shutdown_after: Option<Duration>,
+    /// Records the events the agent receives in this JSON Lines file.
+    pub record_events: Option<PathBuf>,
+    /// Runs the agent in dry-run mode, appending its prompts to this JSON Lines file.
+    pub dry_run: Option<PathBuf>,
+    /// Skips the events whose key was already processed.
+    pub dedupe: Option<DedupeSettings>,
+    /// The rate limits, debounces and throttles, tried in order.
+    #[serde(default)]
+    pub policies: Vec<PolicyConfig>,
+    /// The batches the events are collected in.
+    #[serde(default)]
+    pub batches: Vec<BatchConfig>,
+    /// The priority rules, tried in order.
+    #[serde(default)]
+    pub priorities: Vec<PriorityConfig>,
+    /// How many events of higher priorities are processed, at most, while an event of
+    /// a lower priority waits.
+    pub starvation_limit: Option<usize>,
+}
+
+impl AgentConfig {
+    /// Loads a configuration file, whose format is given by its extension: `.toml`,
+    /// `.yaml` (or `.yml`) or `.json`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        match extension {
+            "toml" => Self::from_toml_str(&std::fs::read_to_string(path)?),
+            "yaml" | "yml" => Self::from_yaml_str(&std::fs::read_to_string(path)?),
+            "json" => Self::from_json_str(&std::fs::read_to_string(path)?),
+            _ => Err(ConfigError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    /// Parses a TOML configuration.
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        toml::from_str(input).map_err(|e| ConfigError::Parse {
+            format: "TOML",
+            message: e.to_string(),
+        })
+    }
+
+    /// Parses a YAML configuration.
+    pub fn from_yaml_str(input: &str) -> Result<Self, ConfigError> {
+        serde_yaml::from_str(input).map_err(|e| ConfigError::Parse {
+            format: "YAML",
+            message: e.to_string(),
+        })
+    }
+
+    /// Parses a JSON configuration.
+    pub fn from_json_str(input: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(input).map_err(|e| ConfigError::Parse {
+            format: "JSON",
+            message: e.to_string(),
+        })
+    }
+
+    /// Checks the prompt templates, and that every component of the configuration can
+    /// be created, without building those that need the network, such as Gmail
+    /// authentication.
+    pub fn check(&self, registry: &ComponentRegistry) -> Result<(), ConfigError> {
+        let context = BuildContext::default();
+        self.validate()?;
+        self.templates()?;
+        // What needs the network is left in the pending futures, which are dropped.
+        for trigger in &self.triggers {
+            drop(registry.trigger(&trigger.component, &context)?);
+        }
+        for tool in &self.tools {
+            drop(registry.tool(tool, &context)?);
+        }
+        for handler in &self.handlers {
+            drop(registry.handler(handler, &context)?);
+        }
+        if let Some(model) = &self.model {
+            registry.model(model, Vec::new(), &context)?;
+        }
+        Ok(())
+    }
+
+    /// Builds the components of the configuration, and returns an [`AgentBuilder`]
+    /// set up with them.
+    ///
+    /// The builder can be customized further before the agent is built, e.g. with a
+    /// model that is not in the configuration.
+    pub async fn into_builder(
+        self,
+        registry: &ComponentRegistry,
+    ) -> Result<AgentBuilder, ConfigError> {
+        self.validate()?;
+        let context = BuildContext::default();
+        // Every component is created before any is awaited, so that those sharing a
+        // Google account authenticate once, with all their scopes.
+        let triggers = self
+            .triggers
+            .iter()
+            .map(|trigger| {
+                let priority = trigger.priority.map(Priority::from).unwrap_or_default();
+                Ok((registry.trigger(&trigger.component, &context)?, priority))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        let tools = self
+            .tools
+            .iter()
+            .map(|tool| registry.tool(tool, &context))
+            .collect::<Result<Vec<_>, _>>()?;
+        let handlers = self
+            .handlers
+            .iter()
+            .map(|handler| registry.handler(handler, &context))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut builder = AgentBuilder::new();
+        for (trigger, priority) in triggers {
+            builder = builder.add_prioritized_trigger(trigger.await?, priority);
+        }
+        let mut built_tools: Vec<Box<dyn ToolDyn>> = Vec::with_capacity(tools.len());
+        for tool in tools {
+            built_tools.push(tool.await?);
+        }
+        for handler in handlers {
+            let handler = handler.await?;
+            if let Some(response) = handler.response {
+                builder = builder.add_response_handler(response);
+            }
+            if let Some(error) = handler.error {
+                builder = builder.add_error_handler(error);
+            }
+        }
+        if let Some(model) = &self.model {
+            builder = builder.with_model(registry.model(model, built_tools, &context)?);
+        }
+
+        if let Some(template) = self.prompt_template {
+            builder = builder.with_prompt_template(template);
+        }
+        for route in self.routes {
+            let mut built = Route::new(&route.pattern, route.template);
+            if let Some(retry) = &route.retry {
+                built = built.with_retry_config(retry.into());
+            }
+            builder = builder.add_route(built);
+        }
+        if let Some(retry) = &self.retry {
+            builder = builder.with_retry_config(retry.into());
+        }
+        if let Some(concurrency) = self.concurrency {
+            builder = builder.with_concurrency(concurrency);
+        }
+        if let Some(deadline) = self.drain_deadline {
+            builder = builder.with_drain_deadline(deadline);
+        }
+        if let Some(path) = self.journal {
+            builder = builder.with_journal(path);
+        }
+        if let Some(dir) = self.dead_letters {
+            builder = builder.with_dead_letter_store(DeadLetterDir::new(dir));
+        }
+        if let Some(addr) = self.metrics_listener {
+            builder = builder.with_metrics_listener(addr);
+        }
+        if let Some(addr) = self.admin_listener {
+            builder = builder.with_admin_listener(addr);
+        }
+        if let Some(after) = self.shutdown_after {
+            builder = builder.with_shutdown_handler(TimeBasedShutdown::new(after));
+        }
+        if let Some(path) = self.record_events {
+            builder = builder.with_event_recording(path);
+        }
+        if let Some(path) = self.dry_run {
+            builder = builder.dry_run(JsonlFileHandler::new(path));
+        }
+        for batch in self.batches {
+            let mut built = Batch::new(&batch.event, batch.max_size, batch.max_wait);
+            if let Some(name) = &batch.name {
+                built = built.with_batch_name(name);
+            }
+            builder = builder.add_batch(built);
+        }
+        for policy in &self.policies {
+            let paced = policy.policy().expect("validated above");
+            builder = builder.add_event_policy(&policy.pattern, paced);
+        }
+        for rule in self.priorities {
+            let pattern = EventPattern::new(&rule.pattern);
+            builder = builder.add_priority_rule(rule.priority.into(), move |event| {
+                pattern.matches(&event.name)
+            });
+        }
+        if let Some(limit) = self.starvation_limit {
+            builder = builder.with_starvation_limit(limit);
+        }
+        if let Some(dedupe) = self.dedupe {
+            let key = payload_key(&dedupe.key);
+            builder = match (dedupe.path, dedupe.ttl) {
+                (Some(path), None) => builder.with_deduplication(key, FileDedupeStore::new(path)),
+                (Some(path), Some(ttl)) => {
+                    builder.with_deduplication(key, FileDedupeStore::new(path).with_ttl(ttl))
+                }
+                (None, Some(ttl)) => builder.with_deduplication(key, MemoryDedupeStore::new(ttl)),
+                (None, None) => unreachable!("validated above"),
+            };
+        }
+        Ok(builder)
+    }
+
+    /// Renders the prompt the agent would send to the model for an event, or returns
+    /// `None` if no route matches it.
+    ///
+    /// Routes are resolved like the agent does: the first route whose pattern matches
+    /// the name of the event, then the default prompt template.
+    pub fn render(&self, event: &TEvent) -> Result<Option<String>, ConfigError> {
+        let engine = self.templates()?;
+        let route = self
+            .routes
+            .iter()
+            .position(|route| EventPattern::new(&route.pattern).matches(&event.name));
+        let (name, label) = match (route, &self.prompt_template) {
+            (Some(index), _) => (format!("route-{index}"), &*self.routes[index].pattern),
+            (None, Some(_)) => ("default".to_string(), "default"),
+            (None, None) => return Ok(None),
+        };
+        engine
+            .render(&name, &json!(event))
+            .map(Some)
+            .map_err(|e| ConfigError::template(label, e))
+    }
+
+    /// Registers every prompt template, named `route-<index>` and `default`.
+    fn templates(&self) -> Result<TEngine, ConfigError> {
+        let mut engine = TEngine::new();
+        for (index, route) in self.routes.iter().enumerate() {
+            engine
+                .register_template_string(&format!("route-{index}"), &route.template)
+                .map_err(|e| ConfigError::template(&route.pattern, e))?;
+        }
+        if let Some(template) = &self.prompt_template {
+            engine
+                .register_template_string("default", template)
+                .map_err(|e| ConfigError::template("default", e))?;
+        }
+        Ok(engine)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.tools.is_empty() && self.model.is_none() {
+            return Err(ConfigError::Invalid(
+                "tools are given to the model, which the configuration does not name".to_string(),
+            ));
+        }
+        for policy in &self.policies {
+            if policy.policy().is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "the policy of `{}` needs exactly one of `rate_limit`, `debounce` and `throttle`",
+                    policy.pattern
+                )));
+            }
+        }
+        if let Some(DedupeSettings {
+            path: None,
+            ttl: None,
+            ..
+        }) = &self.dedupe
+        {
+            return Err(ConfigError::Invalid(
+                "dedupe keeps its keys in memory without a `path`, and needs a `ttl` then"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a duration written like `"5m"` or `"1h 30m"`.
+///
+/// For the parameters of components: `#[serde(deserialize_with = "duration")]`.
+pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an optional duration written like `"5m"` or `"1h 30m"`.
+pub fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|text| humantime::parse_duration(&text).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScriptedLLM;
+    use crate::triggers::{ChannelTrigger, ChannelTriggerBuilder, Trigger};
+    use std::sync::{Arc, Mutex};
+
+    fn echo() -> Box<ScriptedLLM> {
+        Box::new(ScriptedLLM::new().respond_with(|prompt| Ok(prompt.to_string())))
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct QueueParams {
+        capacity: usize,
+    }
+
+    #[tokio::test]
+    async fn test_builds_an_agent_with_builtin_and_registered_components() {
+        let config = AgentConfig::from_toml_str(
+            r#"
+            prompt_template = "{{payload.text}}"
+            concurrency = 2
+            drain_deadline = "2s"
+
+            [retry]
+            max_attempts = 0
+
+            [[routes]]
+            pattern = "Tick"
+            template = "It is {{payload.time}}"
+
+            [[triggers]]
+            type = "poll"
+            event_name = "Tick"
+            interval = "1h"
+
+            [[triggers]]
+            type = "queue"
+            capacity = 8
+            priority = "high"
+
+            [[handlers]]
+            type = "stdout"
+
+            [[batches]]
+            event = "Tick"
+            max_size = 10
+            max_wait = "1m"
+
+            [[policies]]
+            pattern = "Tick"
+            throttle = { events = 1, per = "1m", overflow = "drop" }
+
+            [[policies]]
+            pattern = "*"
+            key = "chat.id"
+            debounce = "2s"
+
+            [[priorities]]
+            pattern = "Tick*"
+            priority = "low"
+            "#,
+        )
+        .unwrap();
+
+        let created = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = ComponentRegistry::new();
+        let seen = created.clone();
+        registry.register_trigger("queue", move |params: QueueParams, _| {
+            seen.lock().unwrap().push(params.capacity);
+            let trigger: ChannelTrigger = ChannelTriggerBuilder::new()
+                .with_capacity(params.capacity)
+                .build();
+            Ok(ready(Box::new(trigger) as Box<dyn Trigger>))
+        });
+
+        assert_eq!(config.triggers[1].priority, Some(PrioritySetting::High));
+        config.check(&registry).unwrap();
+        let builder = config.into_builder(&registry).await.unwrap();
+        assert_eq!(*created.lock().unwrap(), [8, 8]);
+        // The model is left to code.
+        builder.with_model(echo()).build().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reads_yaml_configurations() {
+        let config = AgentConfig::from_yaml_str(
+            r#"
+            prompt_template: "{{payload.text}}"
+            drain_deadline: 2s
+            triggers:
+              - type: poll
+                event_name: Tick
+                interval: 1h
+            policies:
+              - pattern: Tick
+                throttle: { events: 1, per: 1m, overflow: drop }
+            "#,
+        )
+        .unwrap();
+        let registry = ComponentRegistry::new();
+        config.check(&registry).unwrap();
+        let builder = config.into_builder(&registry).await.unwrap();
+        builder.with_model(echo()).build().unwrap();
+
+        assert!(matches!(
+            AgentConfig::from_yaml_str("promt_template: typo"),
+            Err(ConfigError::Parse { format: "YAML", .. })
+        ));
+    }
+
+    #[test]
+    fn test_reports_unknown_types_and_invalid_parameters() {
+        let registry = ComponentRegistry::new();
+        let unknown = AgentConfig::from_toml_str("[[triggers]]\ntype = \"kafka\"").unwrap();
+        assert!(matches!(
+            unknown.check(&registry),
+            Err(ConfigError::UnknownComponent { kind: "trigger", type_name }) if type_name == "kafka"
+        ));
+
+        let invalid = AgentConfig::from_json_str(
+            r#"{"triggers": [{"type": "poll", "event_name": "Tick", "interval": "often"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            invalid.check(&registry),
+            Err(ConfigError::InvalidParams {
+                kind: "trigger",
+                ..
+            })
+        ));
+        let misspelled = AgentConfig::from_toml_str(
+            "[[triggers]]\ntype = \"gmail_watch\"\ncredentials_path = \"c.json\"\ntoken_path = \"t.json\"\nmark_as_raed = true",
+        )
+        .unwrap();
+        assert!(matches!(
+            misspelled.check(&registry),
+            Err(ConfigError::InvalidParams {
+                kind: "trigger",
+                ..
+            })
+        ));
+
+        let tools_only = AgentConfig::from_toml_str(
+            "[[tools]]\ntype = \"simple_file_writer\"\noutput_dir = \".\"",
+        )
+        .unwrap();
+        assert!(matches!(
+            tools_only.check(&registry),
+            Err(ConfigError::Invalid(_))
+        ));
+        let ambiguous = AgentConfig::from_toml_str(
+            "[[policies]]\npattern = \"*\"\ndebounce = \"1s\"\nrate_limit = { events = 1, per = \"1s\" }",
+        )
+        .unwrap();
+        assert!(matches!(
+            ambiguous.check(&registry),
+            Err(ConfigError::Invalid(_))
+        ));
+        let forgetful = AgentConfig::from_toml_str("[dedupe]\nkey = \"id\"").unwrap();
+        assert!(matches!(
+            forgetful.check(&registry),
+            Err(ConfigError::Invalid(_))
+        ));
+
+        assert!(matches!(
+            AgentConfig::from_toml_str("promt_template = \"typo\""),
+            Err(ConfigError::Parse { format: "TOML", .. })
+        ));
+        assert!(matches!(
+            AgentConfig::from_path("agent.ini"),
+            Err(ConfigError::UnsupportedFormat(_))
+        ));
+
+        let missing = AgentConfig::from_toml_str(
+            "[[triggers]]\ntype = \"gmail_watch\"\ncredentials_path = \"missing.json\"\ntoken_path = \"token.json\"\nmark_as_read = true",
+        )
+        .unwrap();
+        assert!(matches!(
+            missing.check(&registry),
+            Err(ConfigError::Component {
+                kind: "trigger",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_renders_the_prompt_of_the_matching_route() {
+        let config = AgentConfig::from_toml_str(
+            r#"
+            prompt_template = "Summarize: {{payload.body}}"
+
+            [[routes]]
+            pattern = "Telegram*"
+            template = "Answer {{payload.from}}: {{payload.text}}"
+            "#,
+        )
+        .unwrap();
+        let event = |name: &str, payload: Value| TEvent {
+            name: name.to_string(),
+            payload: Some(payload),
+        };
+
+        let telegram = event("TelegramMessage", json!({"from": "ann", "text": "hi"}));
+        assert_eq!(
+            config.render(&telegram).unwrap().as_deref(),
+            Some("Answer ann: hi")
+        );
+        let email = event("NewEmail", json!({"body": "invoice"}));
+        assert_eq!(
+            config.render(&email).unwrap().as_deref(),
+            Some("Summarize: invoice")
+        );
+
+        let broken = AgentConfig::from_toml_str("prompt_template = \"{{#if}}\"").unwrap();
+        assert!(matches!(
+            broken.check(&ComponentRegistry::empty()),
+            Err(ConfigError::Template { template, .. }) if template == "default"
+        ));
+    }
+}
@@ -0,0 +1,527 @@
+// The `registry` module maps the component types named in a configuration file to the
+// factories that build them.
+
+use super::{ComponentConfig, ConfigError, duration};
+use crate::handlers::{ErrorHandler, JsonlFileHandler, ResponseHandler, StdoutHandler};
+use crate::llm::LLM;
+use crate::tools::{DailySummaryWriterBuilder, GmailToolBuilder, SimpleFileWriterBuilder};
+use crate::triggers::replay_trigger::ReplayPace;
+use crate::triggers::{
+    GmailWatchTriggerBuilder, PollTriggerBuilder, ReplayTriggerBuilder, TelegramBotTriggerBuilder,
+    Trigger,
+};
+use crate::utils::context_hub::ContextHub;
+use crate::utils::google_auth::{GConf, GoogleAuthFlow, InnerConf};
+use rig::client::CompletionClient;
+use rig::completion::ToolDefinition;
+use rig::tool::{ToolDyn, ToolError};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A component being built.
+///
+/// Factories do the checks that need no I/O right away, and return the rest of the
+/// work, such as authenticating, as a future. The futures are awaited once every
+/// component of the configuration has been created, so that components sharing a
+/// [`ContextHub`] authenticate only once.
+pub type Pending<T> = Pin<Box<dyn Future<Output = Result<T, ConfigError>> + Send>>;
+
+/// Returns a component that needs no further work.
+pub fn ready<T: Send + 'static>(component: T) -> Pending<T> {
+    Box::pin(std::future::ready(Ok(component)))
+}
+
+/// A handler built from the configuration, receiving responses, errors or both.
+#[derive(Clone, Default)]
+pub struct ConfiguredHandler {
+    /// Receives the model's responses.
+    pub response: Option<Arc<dyn ResponseHandler>>,
+    /// Receives the model's errors.
+    pub error: Option<Arc<dyn ErrorHandler>>,
+}
+
+impl ConfiguredHandler {
+    /// A handler receiving both the responses and the errors.
+    pub fn both(handler: impl ResponseHandler + ErrorHandler + 'static) -> Self {
+        let handler = Arc::new(handler);
+        Self {
+            response: Some(handler.clone()),
+            error: Some(handler),
+        }
+    }
+}
+
+/// What factories share while a configuration is built.
+#[derive(Default)]
+pub struct BuildContext {
+    hubs: Mutex<HashMap<(PathBuf, PathBuf), Arc<ContextHub>>>,
+}
+
+impl BuildContext {
+    /// Returns the [`ContextHub`] of a Google configuration, shared by every component
+    /// using the same credential and token files.
+    pub fn context_hub(&self, gconf: GConf) -> Arc<ContextHub> {
+        let key = (gconf.0.credentials_path.clone(), gconf.0.token_path.clone());
+        self.hubs
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(ContextHub::new(gconf)))
+            .clone()
+    }
+
+    /// Reads an environment variable, such as a token the configuration names.
+    pub fn env(&self, var: &str) -> Result<String, ConfigError> {
+        std::env::var(var).map_err(|_| ConfigError::MissingEnv(var.to_string()))
+    }
+}
+
+type Factory<T> =
+    Arc<dyn Fn(&ComponentConfig, &BuildContext) -> Result<Pending<T>, ConfigError> + Send + Sync>;
+
+type ModelFactory = Arc<
+    dyn Fn(
+            &ComponentConfig,
+            Vec<Box<dyn ToolDyn>>,
+            &BuildContext,
+        ) -> Result<Box<dyn LLM>, ConfigError>
+        + Send
+        + Sync,
+>;
+
+/// The component types a configuration can name, by kind.
+///
+/// [`ComponentRegistry::new`] knows the components of this crate; other crates add
+/// theirs with the `register_*` methods. The parameters of a component are the
+/// other keys of its table, deserialized into the factory's parameter type:
+///
+/// ```rust,ignore
+/// #[derive(Deserialize)]
+/// struct KafkaParams { topic: String }
+///
+/// let mut registry = ComponentRegistry::new();
+/// registry.register_trigger("kafka", |params: KafkaParams, _| {
+///     Ok(ready(Box::new(KafkaTrigger::new(params.topic)) as Box<dyn Trigger>))
+/// });
+/// ```
+pub struct ComponentRegistry {
+    triggers: HashMap<String, Factory<Box<dyn Trigger>>>,
+    tools: HashMap<String, Factory<Box<dyn ToolDyn>>>,
+    handlers: HashMap<String, Factory<ConfiguredHandler>>,
+    models: HashMap<String, ModelFactory>,
+}
+
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ComponentRegistry {
+    /// Creates a registry with the components of this crate.
+    ///
+    /// | Kind | Types |
+    /// | --- | --- |
+    /// | triggers | `poll`, `gmail_watch`, `telegram_bot`, `replay` |
+    /// | tools | `simple_file_writer`, `daily_summary_writer`, `gmail` |
+    /// | handlers | `stdout`, `jsonl_file` |
+    /// | models | `gemini` |
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register_builtins();
+        registry
+    }
+
+    /// Creates a registry without any component.
+    pub fn empty() -> Self {
+        Self {
+            triggers: HashMap::new(),
+            tools: HashMap::new(),
+            handlers: HashMap::new(),
+            models: HashMap::new(),
+        }
+    }
+
+    /// Registers a trigger type, replacing any previous one with the same name.
+    pub fn register_trigger<P, F>(&mut self, type_name: &str, factory: F) -> &mut Self
+    where
+        P: DeserializeOwned,
+        F: Fn(P, &BuildContext) -> Result<Pending<Box<dyn Trigger>>, ConfigError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.triggers
+            .insert(type_name.to_string(), typed("trigger", factory));
+        self
+    }
+
+    /// Registers a tool type, replacing any previous one with the same name.
+    ///
+    /// Tools are given to the model of the configuration.
+    pub fn register_tool<P, F>(&mut self, type_name: &str, factory: F) -> &mut Self
+    where
+        P: DeserializeOwned,
+        F: Fn(P, &BuildContext) -> Result<Pending<Box<dyn ToolDyn>>, ConfigError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.tools
+            .insert(type_name.to_string(), typed("tool", factory));
+        self
+    }
+
+    /// Registers a handler type, replacing any previous one with the same name.
+    pub fn register_handler<P, F>(&mut self, type_name: &str, factory: F) -> &mut Self
+    where
+        P: DeserializeOwned,
+        F: Fn(P, &BuildContext) -> Result<Pending<ConfiguredHandler>, ConfigError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.handlers
+            .insert(type_name.to_string(), typed("handler", factory));
+        self
+    }
+
+    /// Registers a model type, replacing any previous one with the same name.
+    ///
+    /// The factory receives the tools of the configuration, already built.
+    pub fn register_model<P, F>(&mut self, type_name: &str, factory: F) -> &mut Self
+    where
+        P: DeserializeOwned,
+        F: Fn(P, Vec<Box<dyn ToolDyn>>, &BuildContext) -> Result<Box<dyn LLM>, ConfigError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.models.insert(
+            type_name.to_string(),
+            Arc::new(move |component, tools, context| {
+                factory(params("model", component)?, tools, context)
+            }),
+        );
+        self
+    }
+
+    pub(crate) fn trigger(
+        &self,
+        component: &ComponentConfig,
+        context: &BuildContext,
+    ) -> Result<Pending<Box<dyn Trigger>>, ConfigError> {
+        lookup(&self.triggers, "trigger", component)?(component, context)
+    }
+
+    pub(crate) fn tool(
+        &self,
+        component: &ComponentConfig,
+        context: &BuildContext,
+    ) -> Result<Pending<Box<dyn ToolDyn>>, ConfigError> {
+        lookup(&self.tools, "tool", component)?(component, context)
+    }
+
+    pub(crate) fn handler(
+        &self,
+        component: &ComponentConfig,
+        context: &BuildContext,
+    ) -> Result<Pending<ConfiguredHandler>, ConfigError> {
+        lookup(&self.handlers, "handler", component)?(component, context)
+    }
+
+    pub(crate) fn model(
+        &self,
+        component: &ComponentConfig,
+        tools: Vec<Box<dyn ToolDyn>>,
+        context: &BuildContext,
+    ) -> Result<Box<dyn LLM>, ConfigError> {
+        lookup(&self.models, "model", component)?(component, tools, context)
+    }
+
+    fn register_builtins(&mut self) {
+        self.register_trigger("poll", |params: PollParams, _| {
+            let trigger = PollTriggerBuilder::new(&params.event_name, params.interval)
+                .with_hot_start(params.hot_start)
+                .build();
+            Ok(ready(Box::new(trigger) as Box<dyn Trigger>))
+        });
+        self.register_trigger("gmail_watch", |params: GmailWatchParams, context| {
+            let (mark_as_read, max_attempts) = (params.mark_as_read, params.max_attempts);
+            let gconf = GoogleAuthParams::from(params).gconf("trigger", "gmail_watch")?;
+            let mut builder = GmailWatchTriggerBuilder::new(context.context_hub(gconf))
+                .with_mark_as_read(mark_as_read);
+            if let Some(max_attempts) = max_attempts {
+                builder = builder.with_max_attempts(max_attempts);
+            }
+            Ok(Box::pin(async move {
+                let trigger = builder
+                    .build()
+                    .await
+                    .map_err(|e| ConfigError::component("trigger", "gmail_watch", e))?;
+                Ok(Box::new(trigger) as Box<dyn Trigger>)
+            }))
+        });
+        self.register_trigger("telegram_bot", |params: TelegramBotParams, context| {
+            let token = context.env(&params.token_env)?;
+            let trigger = TelegramBotTriggerBuilder::new()
+                .with_token(&token)
+                .build()
+                .map_err(|e| ConfigError::component("trigger", "telegram_bot", e))?;
+            Ok(ready(Box::new(trigger) as Box<dyn Trigger>))
+        });
+        self.register_trigger("replay", |params: ReplayParams, _| {
+            let pace = if params.as_fast_as_possible {
+                ReplayPace::AsFastAsPossible
+            } else {
+                ReplayPace::Original
+            };
+            let trigger = ReplayTriggerBuilder::new(params.path)
+                .with_pace(pace)
+                .build()
+                .map_err(|e| ConfigError::component("trigger", "replay", e))?;
+            Ok(ready(Box::new(trigger) as Box<dyn Trigger>))
+        });
+
+        self.register_tool("simple_file_writer", |params: OutputDirParams, _| {
+            let tool = SimpleFileWriterBuilder::new(params.output_dir).build();
+            Ok(ready(Box::new(tool) as Box<dyn ToolDyn>))
+        });
+        self.register_tool("daily_summary_writer", |params: OutputDirParams, _| {
+            let tool = DailySummaryWriterBuilder::new(params.output_dir).build();
+            Ok(ready(Box::new(tool) as Box<dyn ToolDyn>))
+        });
+        self.register_tool("gmail", |params: GoogleAuthParams, context| {
+            let gconf = params.gconf("tool", "gmail")?;
+            let builder = GmailToolBuilder::new(context.context_hub(gconf));
+            Ok(Box::pin(async move {
+                let tool = builder
+                    .build()
+                    .await
+                    .map_err(|e| ConfigError::component("tool", "gmail", e))?;
+                Ok(Box::new(tool) as Box<dyn ToolDyn>)
+            }))
+        });
+
+        self.register_handler("stdout", |_: NoParams, _| {
+            Ok(ready(ConfiguredHandler::both(StdoutHandler::new())))
+        });
+        self.register_handler("jsonl_file", |params: PathParams, _| {
+            Ok(ready(ConfiguredHandler::both(JsonlFileHandler::new(
+                params.path,
+            ))))
+        });
+
+        self.register_model("gemini", |params: GeminiParams, tools, context| {
+            let api_key = context.env(&params.api_key_env)?;
+            let client = rig::providers::gemini::Client::new(&api_key);
+            // Gemini requests need a generation config, even an empty one.
+            let mut agent = client
+                .agent(&params.model)
+                .additional_params(json!({ "generationConfig": params.generation_config }));
+            if let Some(preamble) = &params.preamble {
+                agent = agent.preamble(preamble);
+            }
+            if let Some(temperature) = params.temperature {
+                agent = agent.temperature(temperature);
+            }
+            for tool in tools {
+                agent = agent.tool(DynTool(tool));
+            }
+            Ok(Box::new(agent.build()) as Box<dyn LLM>)
+        });
+    }
+}
+
+/// Wraps a factory taking its parameters deserialized.
+fn typed<P, T, F>(kind: &'static str, factory: F) -> Factory<T>
+where
+    P: DeserializeOwned,
+    F: Fn(P, &BuildContext) -> Result<Pending<T>, ConfigError> + Send + Sync + 'static,
+{
+    Arc::new(move |component, context| factory(params(kind, component)?, context))
+}
+
+/// Deserializes the parameters of a component.
+fn params<P: DeserializeOwned>(
+    kind: &'static str,
+    component: &ComponentConfig,
+) -> Result<P, ConfigError> {
+    serde_json::from_value(Value::Object(component.params.clone())).map_err(|e| {
+        ConfigError::InvalidParams {
+            kind,
+            type_name: component.type_name.clone(),
+            message: e.to_string(),
+        }
+    })
+}
+
+fn lookup<'a, F>(
+    factories: &'a HashMap<String, F>,
+    kind: &'static str,
+    component: &ComponentConfig,
+) -> Result<&'a F, ConfigError> {
+    factories
+        .get(&component.type_name)
+        .ok_or_else(|| ConfigError::UnknownComponent {
+            kind,
+            type_name: component.type_name.clone(),
+        })
+}
+
+/// Gives a boxed tool to a `rig` agent builder, which takes its tools by type.
+struct DynTool(Box<dyn ToolDyn>);
+
+impl rig::tool::Tool for DynTool {
+    const NAME: &'static str = "dyn";
+    type Error = ToolError;
+    type Args = Value;
+    type Output = Value;
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    async fn definition(&self, prompt: String) -> ToolDefinition {
+        self.0.definition(prompt).await
+    }
+
+    async fn call(&self, args: Value) -> Result<Value, ToolError> {
+        let output = self.0.call(args.to_string()).await?;
+        Ok(serde_json::from_str(&output).unwrap_or(Value::String(output)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoParams {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathParams {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputDirParams {
+    output_dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PollParams {
+    event_name: String,
+    #[serde(deserialize_with = "duration")]
+    interval: Duration,
+    #[serde(default)]
+    hot_start: bool,
+}
+
+/// The `GConf` paths of the Google components.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GoogleAuthParams {
+    credentials_path: PathBuf,
+    token_path: PathBuf,
+    #[serde(default)]
+    flow: GoogleAuthFlow,
+}
+
+impl GoogleAuthParams {
+    /// Checks that the credential file exists; the token file is created on the
+    /// first authentication.
+    fn gconf(self, kind: &'static str, type_name: &str) -> Result<GConf, ConfigError> {
+        if !self.credentials_path.is_file() {
+            return Err(ConfigError::component(
+                kind,
+                type_name,
+                format!(
+                    "credential file {} not found",
+                    self.credentials_path.display()
+                ),
+            ));
+        }
+        Ok(GConf::from(Arc::new(InnerConf {
+            credentials_path: self.credentials_path,
+            token_path: self.token_path,
+            flow: self.flow,
+        })))
+    }
+}
+
+// The authentication fields are repeated rather than flattened: `deny_unknown_fields`
+// is ignored through `flatten`, and misspelled keys would be accepted.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GmailWatchParams {
+    credentials_path: PathBuf,
+    token_path: PathBuf,
+    #[serde(default)]
+    flow: GoogleAuthFlow,
+    #[serde(default)]
+    mark_as_read: bool,
+    #[serde(default)]
+    max_attempts: Option<u32>,
+}
+
+impl From<GmailWatchParams> for GoogleAuthParams {
+    fn from(params: GmailWatchParams) -> Self {
+        Self {
+            credentials_path: params.credentials_path,
+            token_path: params.token_path,
+            flow: params.flow,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplayParams {
+    path: PathBuf,
+    #[serde(default)]
+    as_fast_as_possible: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TelegramBotParams {
+    #[serde(default = "default_telegram_token_env")]
+    token_env: String,
+}
+
+fn default_telegram_token_env() -> String {
+    "TELEGRAM_BOT_TOKEN".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeminiParams {
+    #[serde(default = "default_gemini_model")]
+    model: String,
+    preamble: Option<String>,
+    temperature: Option<f64>,
+    #[serde(default = "default_gemini_api_key_env")]
+    api_key_env: String,
+    #[serde(default = "empty_object")]
+    generation_config: Value,
+}
+
+fn default_gemini_model() -> String {
+    rig::providers::gemini::completion::GEMINI_2_0_FLASH_LITE.to_string()
+}
+
+fn default_gemini_api_key_env() -> String {
+    "GEMINI_API_KEY".to_string()
+}
+
+fn empty_object() -> Value {
+    json!({})
+}
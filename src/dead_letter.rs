@@ -0,0 +1,260 @@
+// The `dead_letter` module keeps the events the agent failed to process, so they can be
+// inspected and re-injected instead of being lost.
+
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The `DeadLetterError` enum defines the possible errors that can occur within a dead-letter store.
+#[derive(Error, Debug)]
+pub enum DeadLetterError {
+    /// An I/O error occurred.
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+    /// A dead letter could not be serialized or parsed.
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/// The processing stage at which an event failed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureStage {
+    /// The prompt template could not be rendered for the event.
+    Render,
+    /// The model returned an error, after any retries.
+    Prompt,
+    /// A response handler failed to handle the response of the model.
+    Respond,
+}
+
+impl fmt::Display for FailureStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureStage::Render => f.write_str("render"),
+            FailureStage::Prompt => f.write_str("prompt"),
+            FailureStage::Respond => f.write_str("respond"),
+        }
+    }
+}
+
+/// An event the agent failed to process, with the reason of the failure.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeadLetter {
+    /// The unique id of the dead letter.
+    pub id: String,
+    /// The event that failed.
+    pub event: TEvent,
+    /// The stage at which processing failed.
+    pub stage: FailureStage,
+    /// The error that made processing fail.
+    pub reason: String,
+    /// How many times the agent tried to process the event, re-injections included.
+    pub attempts: u32,
+    /// When the last attempt failed.
+    pub failed_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    /// Creates a new `DeadLetter` for an event that just failed.
+    pub fn new(
+        event: TEvent,
+        stage: FailureStage,
+        reason: impl Into<String>,
+        attempts: u32,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event,
+            stage,
+            reason: reason.into(),
+            attempts,
+            failed_at: Utc::now(),
+        }
+    }
+}
+
+/// The `DeadLetterStore` trait defines where failed events are kept.
+#[async_trait]
+pub trait DeadLetterStore: Send + Sync {
+    /// Stores a dead letter.
+    async fn push(&self, letter: DeadLetter) -> Result<(), DeadLetterError>;
+
+    /// Returns every stored dead letter, oldest first.
+    async fn list(&self) -> Result<Vec<DeadLetter>, DeadLetterError>;
+
+    /// Returns the dead letter with the given id, if any.
+    async fn get(&self, id: &str) -> Result<Option<DeadLetter>, DeadLetterError>;
+
+    /// Removes the dead letter with the given id and returns it, if any.
+    async fn remove(&self, id: &str) -> Result<Option<DeadLetter>, DeadLetterError>;
+}
+
+/// A dead-letter store keeping each failed event in its own JSON file.
+///
+/// Files are named after the dead letter id, so they can also be inspected or
+/// deleted by hand. Files that can't be parsed are skipped with a warning when
+/// listing, so one damaged file doesn't hide the other dead letters.
+///
+/// # Example
+/// ```rust,ignore
+/// let agent = AgentBuilder::new()
+///     .with_dead_letter_store(DeadLetterDir::new("./dead-letters"))
+///     .build()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct DeadLetterDir {
+    dir: PathBuf,
+}
+
+impl DeadLetterDir {
+    /// Creates a new `DeadLetterDir` storing dead letters in `dir`.
+    ///
+    /// The directory is created when the first dead letter is stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the file of a dead letter, or `None` if the id is not a valid id.
+    ///
+    /// Ids are checked so that they can't address files outside the directory.
+    fn path_of(&self, id: &str) -> Option<PathBuf> {
+        Uuid::parse_str(id)
+            .ok()
+            .map(|_| self.dir.join(format!("{id}.json")))
+    }
+}
+
+#[async_trait]
+impl DeadLetterStore for DeadLetterDir {
+    async fn push(&self, letter: DeadLetter) -> Result<(), DeadLetterError> {
+        let path = self.path_of(&letter.id).ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidInput, "invalid dead letter id")
+        })?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Write to a temporary file first so a crash never leaves a truncated letter.
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(&letter)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<DeadLetter>, DeadLetterError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut letters = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let content = match tokio::fs::read(&path).await {
+                Ok(content) => content,
+                // Removed since the directory was read.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match serde_json::from_slice::<DeadLetter>(&content) {
+                Ok(letter) => letters.push(letter),
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Skipping unreadable dead letter")
+                }
+            }
+        }
+        letters.sort_by_key(|letter| letter.failed_at);
+        Ok(letters)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<DeadLetter>, DeadLetterError> {
+        let Some(path) = self.path_of(id) else {
+            return Ok(None);
+        };
+        match tokio::fs::read(&path).await {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove(&self, id: &str) -> Result<Option<DeadLetter>, DeadLetterError> {
+        let letter = self.get(id).await?;
+        if let (Some(_), Some(path)) = (&letter, self.path_of(id)) {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(letter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn letter(name: &str) -> DeadLetter {
+        let event = TEvent {
+            name: name.to_string(),
+            payload: Some(json!({"text": "hi"})),
+        };
+        DeadLetter::new(event, FailureStage::Prompt, "refused", 1)
+    }
+
+    #[tokio::test]
+    async fn test_stores_lists_and_removes_letters() {
+        let dir = tempdir().unwrap();
+        let store = DeadLetterDir::new(dir.path().join("dead"));
+        assert!(store.list().await.unwrap().is_empty());
+
+        let first = letter("First");
+        let second = letter("Second");
+        store.push(first.clone()).await.unwrap();
+        store.push(second.clone()).await.unwrap();
+
+        let names: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.event.name)
+            .collect();
+        assert_eq!(names, vec!["First", "Second"]);
+
+        let found = store.get(&first.id).await.unwrap().unwrap();
+        assert_eq!(found.stage, FailureStage::Prompt);
+        assert_eq!(found.reason, "refused");
+        assert_eq!(found.event.payload, Some(json!({"text": "hi"})));
+
+        assert!(store.remove(&first.id).await.unwrap().is_some());
+        assert!(store.remove(&first.id).await.unwrap().is_none());
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_skips_unreadable_files() {
+        let dir = tempdir().unwrap();
+        let store = DeadLetterDir::new(dir.path());
+        store.push(letter("First")).await.unwrap();
+        std::fs::write(dir.path().join("broken.json"), b"{\"id\": ").unwrap();
+
+        let letters = store.list().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].event.name, "First");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_ids_outside_the_directory() {
+        let dir = tempdir().unwrap();
+        let store = DeadLetterDir::new(dir.path());
+        assert!(store.get("../secret").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,303 @@
+// The `dedupe` module keeps the keys of the events an agent has processed, so that an
+// event seen again, such as an unread email polled twice, is skipped instead of being
+// processed a second time.
+
+use crate::utils::jsonl::append_line;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::warn;
+
+/// The `DedupeError` enum defines the possible errors that can occur within a dedupe store.
+#[derive(Error, Debug)]
+pub enum DedupeError {
+    /// An I/O error occurred.
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+    /// A stored key could not be serialized or parsed.
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/// The `DedupeStore` trait defines where the keys of the processed events are kept.
+#[async_trait]
+pub trait DedupeStore: Send + Sync {
+    /// Returns `true` if an event with this key was processed, and not forgotten since.
+    async fn contains(&self, key: &str) -> Result<bool, DedupeError>;
+
+    /// Remembers that an event with this key was processed.
+    async fn insert(&self, key: &str) -> Result<(), DedupeError>;
+}
+
+/// A dedupe store keeping the keys in memory, each for a limited time.
+///
+/// The keys are lost when the process stops; see [`FileDedupeStore`] for keys that
+/// survive restarts.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::triggers::event::payload_key;
+///
+/// let agent = AgentBuilder::new()
+///     .with_deduplication(payload_key("id"), MemoryDedupeStore::new(Duration::from_secs(3600)))
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct MemoryDedupeStore {
+    ttl: Duration,
+    /// When each key was inserted.
+    keys: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryDedupeStore {
+    /// Creates a new `MemoryDedupeStore` forgetting each key `ttl` after it is inserted.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of keys remembered.
+    pub fn len(&self) -> usize {
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, inserted| inserted.elapsed() < self.ttl);
+        keys.len()
+    }
+
+    /// Returns `true` if no key is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl DedupeStore for MemoryDedupeStore {
+    async fn contains(&self, key: &str) -> Result<bool, DedupeError> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .get(key)
+            .is_some_and(|inserted| inserted.elapsed() < self.ttl))
+    }
+
+    async fn insert(&self, key: &str) -> Result<(), DedupeError> {
+        let mut keys = self.keys.lock().unwrap();
+        // Expired keys are dropped as new ones come, so the map does not grow forever.
+        keys.retain(|_, inserted| inserted.elapsed() < self.ttl);
+        keys.insert(key.to_string(), Instant::now());
+        Ok(())
+    }
+}
+
+/// A key of a [`FileDedupeStore`], with the time it was inserted.
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    key: String,
+    processed_at: DateTime<Utc>,
+}
+
+/// A dedupe store keeping the keys in a JSON Lines file, so that they survive restarts.
+///
+/// Each key is appended to the file as `{"key": "...", "processed_at": "..."}`. The file
+/// is read when the store is first used; with a TTL, the expired keys are dropped from
+/// it then. A line that cannot be read, such as the last line of a write cut short by a
+/// crash, is dropped too, and logged unless it is the last one.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::triggers::event::payload_key;
+///
+/// // Gmail message ids are unique, so the `id` of the message is enough.
+/// let agent = AgentBuilder::new()
+///     .add_trigger(Box::new(gmail))
+///     .with_deduplication(payload_key("id"), FileDedupeStore::new("./processed.jsonl"))
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct FileDedupeStore {
+    path: PathBuf,
+    ttl: Option<Duration>,
+    /// The keys and when they were inserted, read from the file on first use.
+    keys: tokio::sync::Mutex<Option<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl FileDedupeStore {
+    /// Creates a new `FileDedupeStore` keeping the keys in the file at `path`.
+    ///
+    /// The file is created when the first key is inserted. Keys are kept forever,
+    /// unless a TTL is set with [`with_ttl`](Self::with_ttl).
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ttl: None,
+            keys: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Forgets each key `ttl` after it is inserted.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn is_live(&self, processed_at: &DateTime<Utc>) -> bool {
+        match self.ttl {
+            Some(ttl) => (Utc::now() - *processed_at)
+                .to_std()
+                .map_or(true, |age| age < ttl),
+            None => true,
+        }
+    }
+
+    /// Reads the keys from the file, rewriting it without the expired ones and the lines
+    /// that cannot be read.
+    async fn load(&self) -> Result<HashMap<String, DateTime<Utc>>, DedupeError> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys = HashMap::new();
+        let mut dropped = false;
+        let lines: Vec<_> = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .collect();
+        for (i, &(number, line)) in lines.iter().enumerate() {
+            let stored: StoredKey = match serde_json::from_str(line) {
+                Ok(stored) => stored,
+                Err(e) => {
+                    // The last line is expected to be cut short when the process
+                    // stopped in the middle of a write.
+                    if i + 1 < lines.len() {
+                        warn!(path = %self.path.display(), line = number + 1, error = %e, "Dropping an unreadable dedupe key");
+                    }
+                    dropped = true;
+                    continue;
+                }
+            };
+            if self.is_live(&stored.processed_at) {
+                keys.insert(stored.key, stored.processed_at);
+            } else {
+                dropped = true;
+            }
+        }
+        if dropped {
+            let mut content = String::new();
+            for (key, processed_at) in &keys {
+                let stored = StoredKey {
+                    key: key.clone(),
+                    processed_at: *processed_at,
+                };
+                content.push_str(&serde_json::to_string(&stored)?);
+                content.push('\n');
+            }
+            tokio::fs::write(&self.path, content).await?;
+        }
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl DedupeStore for FileDedupeStore {
+    async fn contains(&self, key: &str) -> Result<bool, DedupeError> {
+        let mut keys = self.keys.lock().await;
+        if keys.is_none() {
+            *keys = Some(self.load().await?);
+        }
+        let keys = keys.as_ref().expect("the keys are loaded");
+        Ok(keys.get(key).is_some_and(|at| self.is_live(at)))
+    }
+
+    async fn insert(&self, key: &str) -> Result<(), DedupeError> {
+        let mut keys = self.keys.lock().await;
+        if keys.is_none() {
+            *keys = Some(self.load().await?);
+        }
+        let stored = StoredKey {
+            key: key.to_string(),
+            processed_at: Utc::now(),
+        };
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // The lock on the keys serializes the appends.
+        append_line(&self.path, &stored).await?;
+        keys.as_mut()
+            .expect("the keys are loaded")
+            .insert(stored.key, stored.processed_at);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_memory_store_forgets_keys_after_their_ttl() {
+        let store = MemoryDedupeStore::new(Duration::from_millis(50));
+        store.insert("a").await.unwrap();
+        assert!(store.contains("a").await.unwrap());
+        assert!(!store.contains("b").await.unwrap());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_store_keeps_keys_across_restarts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state").join("processed.jsonl");
+        let store = FileDedupeStore::new(&path);
+        assert!(!store.contains("a").await.unwrap());
+        store.insert("a").await.unwrap();
+        store.insert("b").await.unwrap();
+
+        let reopened = FileDedupeStore::new(&path);
+        assert!(reopened.contains("a").await.unwrap());
+        assert!(reopened.contains("b").await.unwrap());
+        assert!(!reopened.contains("c").await.unwrap());
+
+        // An old key is dropped from the file once the store is opened with a TTL.
+        let old = StoredKey {
+            key: "old".to_string(),
+            processed_at: Utc::now() - chrono::TimeDelta::hours(2),
+        };
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str(&serde_json::to_string(&old).unwrap());
+        std::fs::write(&path, content).unwrap();
+        let expiring = FileDedupeStore::new(&path).with_ttl(Duration::from_secs(3600));
+        assert!(!expiring.contains("old").await.unwrap());
+        assert!(expiring.contains("a").await.unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_file_store_drops_a_truncated_last_line() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("processed.jsonl");
+        let store = FileDedupeStore::new(&path);
+        store.insert("a").await.unwrap();
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str(r#"{"key": "b", "process"#);
+        std::fs::write(&path, content).unwrap();
+
+        let reopened = FileDedupeStore::new(&path);
+        assert!(reopened.contains("a").await.unwrap());
+        assert!(!reopened.contains("b").await.unwrap());
+        // The next key is written on a line of its own.
+        reopened.insert("c").await.unwrap();
+        let reopened = FileDedupeStore::new(&path);
+        assert!(reopened.contains("a").await.unwrap());
+        assert!(reopened.contains("c").await.unwrap());
+    }
+}
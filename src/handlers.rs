@@ -0,0 +1,45 @@
+//! # ForgeFlow Handlers Module
+//!
+//! Response handlers decide where the model's answer to an event goes. Without a
+//! handler the agent only logs the response; with one it can be printed, stored or
+//! sent back to where the event came from. Error handlers are their counterpart for
+//! events whose LLM call failed. Prompt sinks receive the prompts of an agent in
+//! dry-run mode, in place of the model.
+//!
+//! - [`StdoutHandler`] prints responses to stdout and errors to stderr
+//! - [`JsonlFileHandler`] appends one JSON record per response or error to a file
+//! - [`ReplyToOrigin`] sends the response back to the source of the event
+//! - [`TelegramReplyHandler`] answers Telegram messages in the chat they came from
+//! - [`ForwardHandler`] feeds responses to another agent through a [`ChannelTrigger`]
+//!
+//! [`ChannelTrigger`]: crate::triggers::ChannelTrigger
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use forgeflow::agent::AgentBuilder;
+//! use forgeflow::handlers::{JsonlFileHandler, StdoutHandler};
+//!
+//! let log = JsonlFileHandler::new("./responses.jsonl");
+//! let agent = AgentBuilder::new()
+//!     .with_model(llm)
+//!     .with_prompt_template("{{payload.text}}".to_string())
+//!     .add_response_handler(StdoutHandler::new())
+//!     .add_response_handler(log.clone())
+//!     .add_error_handler(log)
+//!     .build()?;
+//! ```
+
+pub mod forward;
+pub mod jsonl_file;
+pub mod reply;
+pub mod stdout;
+pub mod telegram_reply;
+pub mod traits;
+
+pub use forward::ForwardHandler;
+pub use jsonl_file::JsonlFileHandler;
+pub use reply::ReplyToOrigin;
+pub use stdout::StdoutHandler;
+pub use telegram_reply::{ReplyFormat, TelegramReplyHandler};
+pub use traits::{ErrorHandler, HandlerError, PromptSink, ResponseHandler};
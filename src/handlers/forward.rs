@@ -0,0 +1,79 @@
+// The `forward` module provides a handler that feeds the model's responses to another
+// agent, as events.
+
+use crate::handlers::{HandlerError, ResponseHandler};
+use crate::triggers::ChannelTrigger;
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// A handler that sends each response into a [`ChannelTrigger`], as a new event.
+///
+/// The event is named after the handler, and its payload holds the response and the
+/// event it answers:
+///
+/// ```json
+/// { "response": "...", "event": { "name": "NewEmail", "payload": { ... } } }
+/// ```
+///
+/// so the agent consuming the channel can use `{{payload.response}}` and
+/// `{{payload.event.payload.subject}}` in its prompt template. Sending waits while the
+/// channel is full.
+#[derive(Clone)]
+pub struct ForwardHandler {
+    event_name: String,
+    channel: ChannelTrigger,
+}
+
+impl ForwardHandler {
+    /// Creates a handler sending the responses into `channel` as events named `event_name`.
+    pub fn new(event_name: impl Into<String>, channel: ChannelTrigger) -> Self {
+        Self {
+            event_name: event_name.into(),
+            channel,
+        }
+    }
+}
+
+#[async_trait]
+impl ResponseHandler for ForwardHandler {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        let forwarded = TEvent {
+            name: self.event_name.clone(),
+            payload: Some(json!({ "response": response, "event": event })),
+        };
+        self.channel.send(forwarded).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::triggers::{ChannelTriggerBuilder, Trigger};
+    use tokio::sync::{broadcast, mpsc};
+
+    #[tokio::test]
+    async fn test_forwards_the_response_with_the_original_event() {
+        let channel = ChannelTriggerBuilder::new().build();
+        let handler = channel.forward("Triaged");
+        let event = TEvent {
+            name: "NewEmail".to_string(),
+            payload: Some(json!({ "subject": "Invoice" })),
+        };
+        handler.handle_response(&event, "urgent").await.unwrap();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        channel.launch(tx, shutdown_rx).await.unwrap();
+        let forwarded = rx.recv().await.unwrap();
+        assert_eq!(forwarded.name, "Triaged");
+        assert_eq!(
+            forwarded.payload,
+            Some(json!({
+                "response": "urgent",
+                "event": { "name": "NewEmail", "payload": { "subject": "Invoice" } }
+            }))
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// The `jsonl_file` module provides a handler that appends responses to a JSON Lines file.
+
+use crate::handlers::{ErrorHandler, HandlerError, PromptSink, ResponseHandler};
+use crate::llm::LLMError;
+use crate::triggers::event::TEvent;
+use crate::utils::jsonl::append_line;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// A handler that appends one JSON record per line to a file.
+///
+/// Responses are written as `{"timestamp", "event", "response"}`, errors as
+/// `{"timestamp", "event", "error"}` and dry-run prompts as
+/// `{"timestamp", "event", "prompt"}`. Clones share the same file, so a single handler
+/// can be registered both as response and as error handler.
+#[derive(Clone, Debug)]
+pub struct JsonlFileHandler {
+    path: PathBuf,
+    /// Serializes writes from concurrently processed events.
+    lock: Arc<Mutex<()>>,
+}
+
+impl JsonlFileHandler {
+    /// Creates a new `JsonlFileHandler` appending to the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    async fn append(&self, record: Value) -> Result<(), HandlerError> {
+        let _guard = self.lock.lock().await;
+        append_line(&self.path, &record).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ResponseHandler for JsonlFileHandler {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        self.append(json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "event": event,
+            "response": response,
+        }))
+        .await
+    }
+}
+
+#[async_trait]
+impl ErrorHandler for JsonlFileHandler {
+    async fn handle_error(&self, event: &TEvent, error: &LLMError) -> Result<(), HandlerError> {
+        self.append(json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "event": event,
+            "error": error.to_string(),
+        }))
+        .await
+    }
+}
+
+#[async_trait]
+impl PromptSink for JsonlFileHandler {
+    async fn record_prompt(&self, event: &TEvent, prompt: &str) -> Result<(), HandlerError> {
+        self.append(json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "event": event,
+            "prompt": prompt,
+        }))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_appends_responses_and_errors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("responses.jsonl");
+        let handler = JsonlFileHandler::new(&path);
+        let event = TEvent {
+            name: "TelegramMessage".to_string(),
+            payload: Some(json!({"text": "hi"})),
+        };
+
+        handler.handle_response(&event, "hello").await.unwrap();
+        handler
+            .handle_error(&event, &LLMError::PromptError("boom".to_string()))
+            .await
+            .unwrap();
+
+        let content = tokio::fs::read_to_string(&path).await.unwrap();
+        let records: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["event"]["name"], "TelegramMessage");
+        assert_eq!(records[0]["response"], "hello");
+        assert_eq!(records[1]["error"], "Failed to prompt the model: boom");
+    }
+}
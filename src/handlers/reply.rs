@@ -0,0 +1,152 @@
+// The `reply` module provides a handler that sends responses back to the source of the event.
+
+use crate::handlers::{ErrorHandler, HandlerError, ResponseHandler};
+use crate::llm::LLMError;
+use crate::triggers::event::TEvent;
+use crate::utils::EventPattern;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::debug;
+
+/// A handler that replies to the origin of each event.
+///
+/// Each event source that can be answered provides a replier, a [`ResponseHandler`]
+/// that knows how to deliver a message back to it (for example a Telegram chat).
+/// `ReplyToOrigin` picks the replier by event name, so one agent can answer events
+/// from several sources. Events without a matching replier are left unanswered.
+///
+/// When used as an [`ErrorHandler`], it can send a fixed apology through the same
+/// replier, see [`ReplyToOrigin::with_error_reply`].
+#[derive(Clone, Default)]
+pub struct ReplyToOrigin {
+    repliers: Vec<(EventPattern, Arc<dyn ResponseHandler>)>,
+    error_reply: Option<String>,
+}
+
+impl ReplyToOrigin {
+    /// Creates a new `ReplyToOrigin` handler without repliers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replies to the events whose name matches `pattern` through `replier`.
+    ///
+    /// Repliers are tried in the order they are added.
+    pub fn route(mut self, pattern: &str, replier: impl ResponseHandler + 'static) -> Self {
+        self.repliers
+            .push((EventPattern::new(pattern), Arc::new(replier)));
+        self
+    }
+
+    /// Sends `message` to the origin of an event whose LLM call failed.
+    ///
+    /// Without it, failures are not reported to the origin.
+    pub fn with_error_reply(mut self, message: impl Into<String>) -> Self {
+        self.error_reply = Some(message.into());
+        self
+    }
+
+    fn replier_for(&self, event: &TEvent) -> Option<&Arc<dyn ResponseHandler>> {
+        let replier = self
+            .repliers
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&event.name))
+            .map(|(_, replier)| replier);
+        if replier.is_none() {
+            debug!(event_name = %event.name, "No replier for the event, not replying");
+        }
+        replier
+    }
+}
+
+#[async_trait]
+impl ResponseHandler for ReplyToOrigin {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        match self.replier_for(event) {
+            Some(replier) => replier.handle_response(event, response).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl ErrorHandler for ReplyToOrigin {
+    async fn handle_error(&self, event: &TEvent, _error: &LLMError) -> Result<(), HandlerError> {
+        match (&self.error_reply, self.replier_for(event)) {
+            (Some(message), Some(replier)) => replier.handle_response(event, message).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Remembers the replies it was asked to send.
+    #[derive(Clone, Default)]
+    struct RecordingReplier(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl ResponseHandler for RecordingReplier {
+        async fn handle_response(
+            &self,
+            event: &TEvent,
+            response: &str,
+        ) -> Result<(), HandlerError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", event.name, response));
+            Ok(())
+        }
+    }
+
+    fn event(name: &str) -> TEvent {
+        TEvent {
+            name: name.to_string(),
+            payload: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_through_matching_replier() {
+        let replier = RecordingReplier::default();
+        let handler = ReplyToOrigin::new().route("Telegram*", replier.clone());
+
+        handler
+            .handle_response(&event("TelegramMessage"), "hello")
+            .await
+            .unwrap();
+        handler
+            .handle_response(&event("NewEmail"), "ignored")
+            .await
+            .unwrap();
+
+        assert_eq!(*replier.0.lock().unwrap(), vec!["TelegramMessage: hello"]);
+    }
+
+    #[tokio::test]
+    async fn test_error_reply_is_opt_in() {
+        let replier = RecordingReplier::default();
+        let error = LLMError::PromptError("boom".to_string());
+
+        let silent = ReplyToOrigin::new().route("Telegram*", replier.clone());
+        silent
+            .handle_error(&event("TelegramMessage"), &error)
+            .await
+            .unwrap();
+        assert!(replier.0.lock().unwrap().is_empty());
+
+        let apologetic = silent.with_error_reply("Sorry, something went wrong.");
+        apologetic
+            .handle_error(&event("TelegramMessage"), &error)
+            .await
+            .unwrap();
+        assert_eq!(
+            *replier.0.lock().unwrap(),
+            vec!["TelegramMessage: Sorry, something went wrong."]
+        );
+    }
+}
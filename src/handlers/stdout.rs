@@ -0,0 +1,43 @@
+// The `stdout` module provides a handler that prints responses to the console.
+
+use crate::handlers::{ErrorHandler, HandlerError, PromptSink, ResponseHandler};
+use crate::llm::LLMError;
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+
+/// A handler that prints responses to stdout and errors to stderr.
+///
+/// As a [`PromptSink`], it prints dry-run prompts to stdout.
+#[derive(Clone, Debug, Default)]
+pub struct StdoutHandler;
+
+impl StdoutHandler {
+    /// Creates a new `StdoutHandler`.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl ResponseHandler for StdoutHandler {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        println!("[{}] {}", event.name, response);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ErrorHandler for StdoutHandler {
+    async fn handle_error(&self, event: &TEvent, error: &LLMError) -> Result<(), HandlerError> {
+        eprintln!("[{}] {}", event.name, error);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PromptSink for StdoutHandler {
+    async fn record_prompt(&self, event: &TEvent, prompt: &str) -> Result<(), HandlerError> {
+        println!("[{}] {}", event.name, prompt);
+        Ok(())
+    }
+}
@@ -0,0 +1,494 @@
+// The `telegram_reply` module provides a handler that answers Telegram messages in their chat.
+
+use crate::handlers::{HandlerError, ResponseHandler};
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+use teloxide::{
+    Bot, RequestError,
+    prelude::*,
+    types::{MessageId, ParseMode, ReplyParameters},
+};
+use tracing::debug;
+
+/// The maximum length of a Telegram message, in characters.
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+/// How the text of a reply is interpreted by Telegram.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplyFormat {
+    /// The response is sent as plain text, so no character needs escaping.
+    #[default]
+    Plain,
+    /// The response is read as Markdown and sent as MarkdownV2, see
+    /// [`split_markdown_v2`].
+    ///
+    /// Models rarely escape every reserved character, so the handler escapes them
+    /// and keeps the code, bold text and links.
+    MarkdownV2,
+}
+
+/// A handler that answers `TelegramMessage` events with a threaded reply.
+///
+/// The reply goes to the chat in `payload.chat_id` and quotes the message in
+/// `payload.message_id`, as emitted by [`TelegramBotTrigger`](crate::TelegramBotTrigger).
+/// Responses longer than [`TELEGRAM_MESSAGE_LIMIT`] are split into several messages.
+///
+/// # Example
+/// ```rust,ignore
+/// let trigger = TelegramBotTriggerBuilder::new().build()?;
+/// let agent = AgentBuilder::new()
+///     .add_response_handler(ReplyToOrigin::new().route("TelegramMessage", trigger.reply_handler()))
+///     .add_trigger(Box::new(trigger))
+///     .build()?;
+/// ```
+#[derive(Clone)]
+pub struct TelegramReplyHandler {
+    bot: Bot,
+    format: ReplyFormat,
+}
+
+impl TelegramReplyHandler {
+    /// Creates a new `TelegramReplyHandler` sending replies through `bot`.
+    pub fn new(bot: Bot) -> Self {
+        Self {
+            bot,
+            format: ReplyFormat::default(),
+        }
+    }
+
+    /// Sets how the text of the replies is interpreted by Telegram.
+    pub fn with_format(mut self, format: ReplyFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    async fn send(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let reply = ReplyParameters {
+            allow_sending_without_reply: Some(true),
+            ..ReplyParameters::new(reply_to)
+        };
+        let request = self.bot.send_message(chat_id, text).reply_parameters(reply);
+        let result = match self.format {
+            ReplyFormat::Plain => request.await,
+            ReplyFormat::MarkdownV2 => request.parse_mode(ParseMode::MarkdownV2).await,
+        };
+        result.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl ResponseHandler for TelegramReplyHandler {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        let payload = event.payload.as_ref();
+        let chat_id = payload.and_then(|p| p["chat_id"].as_i64()).ok_or_else(|| {
+            HandlerError::DeliveryError("event has no payload.chat_id".to_string())
+        })?;
+        let message_id = payload
+            .and_then(|p| p["message_id"].as_i64())
+            .and_then(|id| i32::try_from(id).ok())
+            .ok_or_else(|| {
+                HandlerError::DeliveryError("event has no payload.message_id".to_string())
+            })?;
+
+        let parts = match self.format {
+            ReplyFormat::Plain => split_message(response, TELEGRAM_MESSAGE_LIMIT),
+            ReplyFormat::MarkdownV2 => split_markdown_v2(response, TELEGRAM_MESSAGE_LIMIT),
+        };
+        debug!(chat_id, parts = parts.len(), "Replying to Telegram message");
+        for part in parts {
+            self.send(ChatId(chat_id), MessageId(message_id), &part)
+                .await
+                .map_err(|e| HandlerError::DeliveryError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a text into parts of at most `limit` characters.
+///
+/// Parts end at the last line break before the limit when there is one, otherwise at
+/// the last whitespace, so words are only cut when a single word exceeds the limit.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut packer = Packer::new(limit);
+    packer.push_text(text.trim(), &str::to_string);
+    packer.finish()
+}
+
+/// Converts a Markdown text to MarkdownV2 and splits it into parts of at most `limit`
+/// characters.
+///
+/// Code blocks, inline code, `**bold**` and `[links](url)` become MarkdownV2 entities;
+/// every other character that MarkdownV2 reserves is escaped, so Telegram always
+/// accepts the parts. Parts are only split between entities, except for the entities
+/// longer than a part: text is split like [`split_message`] does, a code block is
+/// split into several blocks, and a longer entity is sent as escaped text.
+pub fn split_markdown_v2(text: &str, limit: usize) -> Vec<String> {
+    let mut packer = Packer::new(limit);
+    for piece in parse_markdown(text.trim()) {
+        match piece {
+            Piece::Text(text) => packer.push_text(text, &escape_markdown_v2),
+            Piece::Entity { raw, rendered } => {
+                let len = rendered.chars().count();
+                if len <= limit {
+                    packer.push_atom(&rendered, len);
+                } else {
+                    packer.push_text(raw, &escape_markdown_v2);
+                }
+            }
+            Piece::Code { language, code } => packer.push_code(language, code),
+        }
+    }
+    packer.finish()
+}
+
+/// Escapes the characters that MarkdownV2 reserves outside of entities.
+pub fn escape_markdown_v2(text: &str) -> String {
+    escape(text, "_*[]()~`>#+-=|{}.!\\")
+}
+
+fn escape(text: &str, reserved: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if reserved.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A part of a Markdown text.
+enum Piece<'a> {
+    /// Text without formatting.
+    Text(&'a str),
+    /// Inline code, bold text or a link, written in MarkdownV2 in `rendered`.
+    Entity { raw: &'a str, rendered: String },
+    /// A fenced code block.
+    Code { language: &'a str, code: &'a str },
+}
+
+/// Finds the occurrences of a pattern at increasing positions, scanning each part of
+/// the text once.
+struct Finder {
+    pattern: &'static str,
+    /// The position the last search started from, and what it found.
+    last: Option<(usize, Option<usize>)>,
+}
+
+impl Finder {
+    fn new(pattern: &'static str) -> Self {
+        Self {
+            pattern,
+            last: None,
+        }
+    }
+
+    /// Returns the first occurrence of the pattern at or after `from`.
+    fn find(&mut self, text: &str, from: usize) -> Option<usize> {
+        match self.last {
+            Some((start, found)) if start <= from && found.is_none_or(|found| found >= from) => {
+                found
+            }
+            _ => {
+                let found = text[from..].find(self.pattern).map(|at| from + at);
+                self.last = Some((from, found));
+                found
+            }
+        }
+    }
+}
+
+/// Splits a Markdown text into text, entities and code blocks. Unclosed markers are
+/// text.
+fn parse_markdown(text: &str) -> Vec<Piece<'_>> {
+    let mut fences = Finder::new("```");
+    let mut ticks = Finder::new("`");
+    let mut stars = Finder::new("**");
+    let mut brackets = Finder::new("]");
+    let mut parens = Finder::new(")");
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut at = 0;
+    while let Some(c) = text[at..].chars().next() {
+        let rest = &text[at..];
+        let found = if rest.starts_with("```") {
+            fences.find(text, at + 3).map(|end| {
+                let inner = &text[at + 3..end];
+                let (language, code) = match inner.split_once('\n') {
+                    Some((first, code)) if !first.contains(char::is_whitespace) => (first, code),
+                    _ => ("", inner),
+                };
+                let code = code.trim_end_matches('\n');
+                (Piece::Code { language, code }, end + 3)
+            })
+        } else if c == '`' {
+            ticks
+                .find(text, at + 1)
+                .filter(|end| *end > at + 1)
+                .map(|end| {
+                    let rendered = format!("`{}`", escape(&text[at + 1..end], "`\\"));
+                    let raw = &text[at..=end];
+                    (Piece::Entity { raw, rendered }, end + 1)
+                })
+        } else if rest.starts_with("**") {
+            stars
+                .find(text, at + 2)
+                .filter(|end| *end > at + 2)
+                .map(|end| {
+                    let rendered = format!("*{}*", escape_markdown_v2(&text[at + 2..end]));
+                    let raw = &text[at..end + 2];
+                    (Piece::Entity { raw, rendered }, end + 2)
+                })
+        } else if c == '[' {
+            brackets
+                .find(text, at + 1)
+                .filter(|close| text[close + 1..].starts_with('('))
+                .and_then(|close| {
+                    let end = parens.find(text, close + 2)?;
+                    let rendered = format!(
+                        "[{}]({})",
+                        escape_markdown_v2(&text[at + 1..close]),
+                        escape(&text[close + 2..end], ")\\")
+                    );
+                    let raw = &text[at..=end];
+                    Some((Piece::Entity { raw, rendered }, end + 1))
+                })
+        } else {
+            None
+        };
+        match found {
+            Some((piece, end)) => {
+                if text_start < at {
+                    pieces.push(Piece::Text(&text[text_start..at]));
+                }
+                pieces.push(piece);
+                at = end;
+                text_start = end;
+            }
+            None => at += c.len_utf8(),
+        }
+    }
+    if text_start < text.len() {
+        pieces.push(Piece::Text(&text[text_start..]));
+    }
+    pieces
+}
+
+/// Gathers the pieces of a text into parts of at most `limit` characters.
+struct Packer {
+    limit: usize,
+    parts: Vec<String>,
+    current: String,
+    /// The number of characters in `current`.
+    len: usize,
+}
+
+impl Packer {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            parts: Vec::new(),
+            current: String::new(),
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        let part = self.current.trim_end();
+        if !part.is_empty() {
+            self.parts.push(part.to_string());
+        }
+        self.current.clear();
+        self.len = 0;
+    }
+
+    /// Appends a text to the current part; a part never starts with whitespace.
+    fn append(&mut self, text: &str) {
+        let text = if self.current.is_empty() {
+            text.trim_start()
+        } else {
+            text
+        };
+        self.current.push_str(text);
+        self.len += text.chars().count();
+    }
+
+    /// Appends a text that is not split, starting a new part if it does not fit.
+    fn push_atom(&mut self, atom: &str, len: usize) {
+        if self.len + len > self.limit {
+            self.flush();
+        }
+        self.append(atom);
+    }
+
+    /// Appends a text line by line, and the lines longer than a part word by word.
+    fn push_text(&mut self, text: &str, render: &dyn Fn(&str) -> String) {
+        for line in text.split_inclusive('\n') {
+            let rendered = render(line);
+            let len = rendered.chars().count();
+            if self.len + len <= self.limit {
+                self.append(&rendered);
+                continue;
+            }
+            self.flush();
+            if len <= self.limit {
+                self.append(&rendered);
+                continue;
+            }
+            for word in words(line) {
+                let rendered = render(word);
+                let len = rendered.chars().count();
+                if len <= self.limit {
+                    self.push_atom(&rendered, len);
+                } else {
+                    // Only a word longer than a part is cut.
+                    for (at, c) in word.char_indices() {
+                        let rendered = render(&word[at..at + c.len_utf8()]);
+                        self.push_atom(&rendered, rendered.chars().count());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Appends a code block, split into several blocks if it is longer than a part.
+    fn push_code(&mut self, language: &str, code: &str) {
+        let open = format!("```{language}\n");
+        let overhead = open.chars().count() + 4;
+        let block = |body: &str| format!("{open}{body}\n```");
+        let body = escape(code, "`\\");
+        let len = overhead + body.chars().count();
+        if len <= self.limit {
+            self.push_atom(&block(&body), len);
+            return;
+        }
+        let room = self.limit.saturating_sub(overhead).max(1);
+        let mut chunks = Vec::new();
+        let mut chunk = String::new();
+        let mut chunk_len = 0;
+        for line in code.split_inclusive('\n') {
+            let escaped = escape(line, "`\\");
+            // Only a line longer than a block is cut.
+            let units = if escaped.chars().count() <= room {
+                vec![escaped]
+            } else {
+                line.chars()
+                    .map(|c| escape(c.encode_utf8(&mut [0; 4]), "`\\"))
+                    .collect()
+            };
+            for unit in units {
+                let unit_len = unit.chars().count();
+                if chunk_len + unit_len > room && !chunk.is_empty() {
+                    chunks.push(std::mem::take(&mut chunk));
+                    chunk_len = 0;
+                }
+                chunk.push_str(&unit);
+                chunk_len += unit_len;
+            }
+        }
+        chunks.push(chunk);
+        for chunk in chunks {
+            let body = chunk.trim_end_matches('\n');
+            self.push_atom(&block(body), overhead + body.chars().count());
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.flush();
+        if self.parts.is_empty() {
+            self.parts.push(String::new());
+        }
+        self.parts
+    }
+}
+
+/// Splits a line into words and runs of whitespace.
+fn words(line: &str) -> impl Iterator<Item = &str> {
+    let mut rest = line;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let end = rest
+            .find(|c: char| c.is_whitespace() != first.is_whitespace())
+            .unwrap_or(rest.len());
+        let (word, tail) = rest.split_at(end);
+        rest = tail;
+        Some(word)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_short_messages_are_not_split() {
+        assert_eq!(split_message("hello", 10), vec!["hello"]);
+    }
+
+    #[test]
+    fn test_split_prefers_line_breaks_then_spaces() {
+        assert_eq!(
+            split_message("first line\nsecond line", 15),
+            vec!["first line", "second line"]
+        );
+        assert_eq!(
+            split_message("one two three four", 9),
+            vec!["one two", "three", "four"]
+        );
+    }
+
+    #[test]
+    fn test_split_cuts_long_words() {
+        assert_eq!(split_message("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn test_split_counts_characters_not_bytes() {
+        let text = "é".repeat(10);
+        let parts = split_message(&text, 4);
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|p| p.chars().count() <= 4));
+    }
+
+    #[test]
+    fn test_markdown_is_converted_to_markdown_v2() {
+        assert_eq!(
+            split_markdown_v2("**Done!** See `a_b` at [docs](https://x.y/a_b) - 2.5", 100),
+            vec!["*Done\\!* See `a_b` at [docs](https://x.y/a_b) \\- 2\\.5"]
+        );
+        assert_eq!(
+            split_markdown_v2("```rust\nlet a = `b`;\n```\n*not bold", 100),
+            vec!["```rust\nlet a = \\`b\\`;\n```\n\\*not bold"]
+        );
+    }
+
+    #[test]
+    fn test_markdown_v2_is_split_between_entities() {
+        assert_eq!(
+            split_markdown_v2("one **two three** four", 12),
+            vec!["one", "*two three*", "four"]
+        );
+        // A code block longer than a part is split into several blocks.
+        assert_eq!(
+            split_markdown_v2("```\n0123\n4567\n```", 14),
+            vec!["```\n0123\n```", "```\n4567\n```"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_without_chat_are_rejected() {
+        let handler = TelegramReplyHandler::new(Bot::new("test_token"));
+        let event = TEvent {
+            name: "TelegramMessage".to_string(),
+            payload: Some(json!({"text": "hi"})),
+        };
+        let result = handler.handle_response(&event, "hello").await;
+        assert!(matches!(result, Err(HandlerError::DeliveryError(_))));
+    }
+}
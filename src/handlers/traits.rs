@@ -0,0 +1,87 @@
+use crate::llm::LLMError;
+use crate::triggers::event::TEvent;
+use async_trait::async_trait;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// The `HandlerError` enum defines the possible errors that can occur within a handler.
+#[derive(Error, Debug)]
+pub enum HandlerError {
+    /// An I/O error occurred.
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+    /// An error occurred while serializing a record.
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+    /// The response could not be delivered to its destination.
+    #[error("Failed to deliver the response: {0}")]
+    DeliveryError(String),
+}
+
+/// The `ResponseHandler` trait defines what happens to the model's response to an event.
+#[async_trait]
+pub trait ResponseHandler: Send + Sync {
+    /// Handles the response the model gave for an event.
+    ///
+    /// # Arguments
+    /// * `event` - The event the response was generated for.
+    /// * `response` - The response of the model.
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError>;
+}
+
+/// The `ErrorHandler` trait defines what happens when the model fails to answer an event.
+#[async_trait]
+pub trait ErrorHandler: Send + Sync {
+    /// Handles the error the model returned for an event.
+    ///
+    /// # Arguments
+    /// * `event` - The event the model was prompted for.
+    /// * `error` - The error returned by the model, after any retries.
+    async fn handle_error(&self, event: &TEvent, error: &LLMError) -> Result<(), HandlerError>;
+}
+
+/// The `PromptSink` trait receives the prompts an agent renders in dry-run mode,
+/// instead of the model.
+///
+/// See [`AgentBuilder::dry_run`](crate::agent::AgentBuilder::dry_run).
+#[async_trait]
+pub trait PromptSink: Send + Sync {
+    /// Records the prompt rendered for an event.
+    ///
+    /// # Arguments
+    /// * `event` - The event the prompt was rendered for.
+    /// * `prompt` - The prompt, as it would have been sent to the model.
+    async fn record_prompt(&self, event: &TEvent, prompt: &str) -> Result<(), HandlerError>;
+}
+
+/// Sends each prompt with its event on a channel, for the prompts to be inspected.
+#[async_trait]
+impl PromptSink for mpsc::UnboundedSender<(TEvent, String)> {
+    async fn record_prompt(&self, event: &TEvent, prompt: &str) -> Result<(), HandlerError> {
+        self.send((event.clone(), prompt.to_string()))
+            .map_err(|_| HandlerError::DeliveryError("the receiver is gone".to_string()))
+    }
+}
+
+/// Shared handlers are handlers too, so one instance can receive both responses and errors.
+#[async_trait]
+impl<T: ResponseHandler + ?Sized> ResponseHandler for Arc<T> {
+    async fn handle_response(&self, event: &TEvent, response: &str) -> Result<(), HandlerError> {
+        (**self).handle_response(event, response).await
+    }
+}
+
+#[async_trait]
+impl<T: ErrorHandler + ?Sized> ErrorHandler for Arc<T> {
+    async fn handle_error(&self, event: &TEvent, error: &LLMError) -> Result<(), HandlerError> {
+        (**self).handle_error(event, error).await
+    }
+}
+
+#[async_trait]
+impl<T: PromptSink + ?Sized> PromptSink for Arc<T> {
+    async fn record_prompt(&self, event: &TEvent, prompt: &str) -> Result<(), HandlerError> {
+        (**self).record_prompt(event, prompt).await
+    }
+}
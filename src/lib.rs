@@ -2,10 +2,31 @@
 
 /// The `agent` module provides the core functionality for the Forgeflow framework.
 pub mod agent;
+/// The `bus` module lets several agents share the events of the same triggers.
+pub mod bus;
+/// The `config` module builds agents from configuration files.
+pub mod config;
+/// The `dead_letter` module keeps the events the agent failed to process.
+pub mod dead_letter;
+/// The `dedupe` module keeps the keys of the processed events, to skip the events seen again.
+pub mod dedupe;
+/// The `handlers` module provides the destinations of the model's responses.
+pub mod handlers;
 /// The `llm` module provides a trait for interacting with language models.
 pub mod llm;
+/// The `metrics` module records what the agent does, in the Prometheus format.
+pub mod metrics;
+/// The `observer` module provides hooks into the processing of each event.
+pub mod observer;
+/// The `pipeline` module runs agents that feed each other, with a shared shutdown.
+pub mod pipeline;
+/// The `recording` module records the events an agent receives, for them to be replayed.
+pub mod recording;
 /// The `shutdown` module provides a trait for gracefully shutting down the agent.
 pub mod shutdown;
+/// The `testing` module provides test doubles and a harness for agents, behind the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 /// The `tools` module provides a collection of tools that can be used by the agent.
 pub mod tools;
 /// The `triggers` module provides a collection of triggers that can be used to initiate agent actions.
@@ -13,12 +34,19 @@ pub mod triggers;
 /// The `utils` module provides utility functions for the framework.
 pub mod utils;
 
+pub use bus::EventBus;
+pub use dead_letter::DeadLetterDir;
+pub use dedupe::{FileDedupeStore, MemoryDedupeStore};
+pub use handlers::{
+    ForwardHandler, JsonlFileHandler, ReplyToOrigin, StdoutHandler, TelegramReplyHandler,
+};
 pub use tools::{
     DailySummaryWriter, DailySummaryWriterBuilder, GmailTool, GmailToolBuilder, SimpleFileWriter,
     SimpleFileWriterBuilder,
 };
 pub use triggers::{
-    GmailWatchTrigger, GmailWatchTriggerBuilder, PollTrigger, PollTriggerBuilder,
-    TelegramBotTrigger, TelegramBotTriggerBuilder,
+    BusTrigger, BusTriggerBuilder, ChannelTrigger, ChannelTriggerBuilder, GmailWatchTrigger,
+    GmailWatchTriggerBuilder, PollTrigger, PollTriggerBuilder, ReplayTrigger,
+    ReplayTriggerBuilder, TelegramBotTrigger, TelegramBotTriggerBuilder,
 };
 pub use utils::context_hub::ContextHub;
@@ -29,7 +29,7 @@ use tracing::debug;
 ///     .build();
 /// 
 /// // Use it as an LLM in ForgeFlow
-/// let mut llm: Box<dyn LLM> = Box::new(agent);
+/// let llm: Box<dyn LLM> = Box::new(agent);
 /// ```
 /// 
 /// # Thread Safety
@@ -41,7 +41,7 @@ impl<M> LLM for RigAgent<M>
 where
     M: CompletionModel,
 {
-    async fn prompt(&mut self, text: String) -> Result<String, LLMError> {
+    async fn prompt(&self, text: String) -> Result<String, LLMError> {
         rig::completion::Prompt::prompt(self, text)
             .await
             .map(|response| response.to_string())
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use std::sync::Arc;
 use thiserror::Error;
 
 /// A custom error type for LLM operations.
@@ -33,7 +34,7 @@ pub enum LLMError {
 ///
 /// #[async_trait]
 /// impl LLM for MockLLM {
-///     async fn prompt(&mut self, text: String) -> Result<String, LLMError> {
+///     async fn prompt(&self, text: String) -> Result<String, LLMError> {
 ///         Ok(format!("Mock response to: {}", text))
 ///     }
 /// }
@@ -42,7 +43,10 @@ pub enum LLMError {
 /// # Thread Safety
 ///
 /// The trait requires `Send + Sync` to ensure LLM implementations can be safely
-/// used across thread boundaries in async contexts.
+/// used across thread boundaries in async contexts. `prompt` takes `&self` so a
+/// single model can serve several events at once when the agent runs with
+/// [`AgentBuilder::with_concurrency`](crate::agent::AgentBuilder::with_concurrency);
+/// implementations that keep mutable state should use interior mutability.
 #[async_trait]
 pub trait LLM: Send + Sync {
     /// Sends a text prompt to the language model and gets a response.
@@ -72,5 +76,13 @@ pub trait LLM: Send + Sync {
     /// * Invalid responses
     /// * Authentication failures
     /// * Service unavailability
-    async fn prompt(&mut self, text: String) -> Result<String, LLMError>;
+    async fn prompt(&self, text: String) -> Result<String, LLMError>;
+}
+
+/// Shared models are models too, so one instance can back several routes or decorators.
+#[async_trait]
+impl<T: LLM + ?Sized> LLM for Arc<T> {
+    async fn prompt(&self, text: String) -> Result<String, LLMError> {
+        (**self).prompt(text).await
+    }
 }
@@ -24,7 +24,7 @@
 pub mod retry;
 
 // Re-export the main retry decorators for convenience
-pub use retry::{BoxedRetryLLM, ManualRetryLLM, RetryableLLM};
+pub use retry::{BoxedRetryLLM, ManualRetryLLM, RetryHook, RetryableLLM};
 
 // Note: BoxedRetryLLM is re-exported for completeness but is typically
 // used internally by the LLM factory rather than directly by users.
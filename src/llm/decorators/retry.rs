@@ -21,7 +21,7 @@
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
 //! // Wrap any LLM implementation with retry logic
 //! let base_llm = /* your LLM implementation */;
-//! let mut retryable_llm = RetryableLLM::new(base_llm, 3); // 3 retries
+//! let retryable_llm = RetryableLLM::new(base_llm, 3); // 3 retries
 //!
 //! // This will automatically retry on 429 errors
 //! let response = retryable_llm.prompt("Hello, world!".to_string()).await?;
@@ -38,7 +38,7 @@
 //!
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
 //! let base_llm = /* your LLM implementation */;
-//! let mut manual_retry_llm = ManualRetryLLM::new(
+//! let manual_retry_llm = ManualRetryLLM::new(
 //!     base_llm,
 //!     5,                              // max retries
 //!     Duration::from_millis(500)      // base delay
@@ -60,8 +60,12 @@
 use crate::llm::core::{LLM, LLMError};
 use async_trait::async_trait;
 use serde_json::Value;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// A callback invoked with the error each time an LLM call is about to be retried.
+pub type RetryHook = Arc<dyn Fn(&LLMError) + Send + Sync>;
+
 /// A wrapper for an LLM that adds retry logic using exponential backoff.
 ///
 /// This implementation provides automatic retry functionality for LLM operations,
@@ -78,14 +82,14 @@ use std::time::Duration;
 /// # struct MockLLM;
 /// # #[async_trait]
 /// # impl LLM for MockLLM {
-/// #     async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
+/// #     async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
 /// #         Ok("response".to_string())
 /// #     }
 /// # }
 ///
 /// # async fn example() -> Result<(), LLMError> {
 /// let base_llm = MockLLM;
-/// let mut retryable_llm = RetryableLLM::new(base_llm, 3);
+/// let retryable_llm = RetryableLLM::new(base_llm, 3);
 ///
 /// let response = retryable_llm.prompt("Hello!".to_string()).await?;
 /// # Ok(())
@@ -94,6 +98,7 @@ use std::time::Duration;
 pub struct RetryableLLM<L: LLM> {
     llm: L,
     retries: usize,
+    on_retry: Option<RetryHook>,
 }
 
 impl<L: LLM> RetryableLLM<L> {
@@ -104,7 +109,17 @@ impl<L: LLM> RetryableLLM<L> {
     /// * `llm` - The underlying LLM implementation to wrap
     /// * `retries` - Maximum number of retry attempts (0 means no retries)
     pub fn new(llm: L, retries: usize) -> Self {
-        Self { llm, retries }
+        Self {
+            llm,
+            retries,
+            on_retry: None,
+        }
+    }
+
+    /// Sets a callback invoked before each retry, for example to count retries.
+    pub fn with_on_retry(mut self, on_retry: RetryHook) -> Self {
+        self.on_retry = Some(on_retry);
+        self
     }
 
     /// Determines if an error should be retried based on the error content.
@@ -171,7 +186,7 @@ impl<L: LLM> RetryableLLM<L> {
 
 #[async_trait]
 impl<L: LLM + Send + Sync> LLM for RetryableLLM<L> {
-    async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
+    async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
         let mut last_error = None;
         let base_delay = Duration::from_millis(1000);
 
@@ -187,6 +202,10 @@ impl<L: LLM + Send + Sync> LLM for RetryableLLM<L> {
                         break;
                     }
 
+                    if let Some(on_retry) = &self.on_retry {
+                        on_retry(error);
+                    }
+
                     // Handle retry delay from API response, or use exponential backoff
                     Self::handle_retry_delay(error).await;
 
@@ -219,14 +238,14 @@ impl<L: LLM + Send + Sync> LLM for RetryableLLM<L> {
 /// # struct MockLLM;
 /// # #[async_trait]
 /// # impl LLM for MockLLM {
-/// #     async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
+/// #     async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
 /// #         Ok("response".to_string())
 /// #     }
 /// # }
 ///
 /// # async fn example() -> Result<(), LLMError> {
 /// let base_llm = MockLLM;
-/// let mut manual_retry_llm = ManualRetryLLM::new(
+/// let manual_retry_llm = ManualRetryLLM::new(
 ///     base_llm,
 ///     5,                              // max retries
 ///     Duration::from_millis(1000)     // base delay
@@ -240,6 +259,7 @@ pub struct ManualRetryLLM<L: LLM> {
     llm: L,
     max_retries: usize,
     base_delay: Duration,
+    on_retry: Option<RetryHook>,
 }
 
 impl<L: LLM> ManualRetryLLM<L> {
@@ -255,9 +275,16 @@ impl<L: LLM> ManualRetryLLM<L> {
             llm,
             max_retries,
             base_delay,
+            on_retry: None,
         }
     }
 
+    /// Sets a callback invoked before each retry, for example to count retries.
+    pub fn with_on_retry(mut self, on_retry: RetryHook) -> Self {
+        self.on_retry = Some(on_retry);
+        self
+    }
+
     /// Determines if an error should be retried.
     ///
     /// # Arguments
@@ -322,7 +349,7 @@ impl<L: LLM> ManualRetryLLM<L> {
 
 #[async_trait]
 impl<L: LLM + Send + Sync> LLM for ManualRetryLLM<L> {
-    async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
+    async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
         let mut last_error = None;
 
         for attempt in 0..=self.max_retries {
@@ -337,6 +364,10 @@ impl<L: LLM + Send + Sync> LLM for ManualRetryLLM<L> {
                         break;
                     }
 
+                    if let Some(on_retry) = &self.on_retry {
+                        on_retry(error);
+                    }
+
                     // Calculate exponential backoff delay
                     let delay = self.base_delay * (2_u32.pow(attempt as u32));
                     Self::wait_for_retry_delay(error, delay).await;
@@ -356,6 +387,7 @@ impl<L: LLM + Send + Sync> LLM for ManualRetryLLM<L> {
 pub struct BoxedRetryLLM {
     inner: Box<dyn LLM>,
     max_attempts: usize,
+    on_retry: Option<RetryHook>,
 }
 
 impl BoxedRetryLLM {
@@ -364,9 +396,16 @@ impl BoxedRetryLLM {
         Self {
             inner,
             max_attempts,
+            on_retry: None,
         }
     }
 
+    /// Sets a callback invoked before each retry, for example to count retries.
+    pub fn with_on_retry(mut self, on_retry: RetryHook) -> Self {
+        self.on_retry = Some(on_retry);
+        self
+    }
+
     /// Determines if an error should be retried based on the error content.
     fn should_retry(error: &LLMError) -> bool {
         let error_str = error.to_string();
@@ -411,7 +450,7 @@ impl BoxedRetryLLM {
 
 #[async_trait]
 impl LLM for BoxedRetryLLM {
-    async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
+    async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
         let mut last_error = None;
         let base_delay = Duration::from_millis(1000);
 
@@ -427,6 +466,10 @@ impl LLM for BoxedRetryLLM {
                         break;
                     }
 
+                    if let Some(on_retry) = &self.on_retry {
+                        on_retry(error);
+                    }
+
                     // Handle retry delay from API response, or use exponential backoff
                     Self::handle_retry_delay(error).await;
 
@@ -446,158 +489,135 @@ impl LLM for BoxedRetryLLM {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::ScriptedLLM;
     use std::sync::Arc;
     use std::sync::atomic::{AtomicUsize, Ordering};
 
-    struct MockLLM {
-        call_count: Arc<AtomicUsize>,
-        error_on_call: Option<i64>,
-        fail_first_n: Option<usize>,
-    }
-
-    impl MockLLM {
-        fn new(call_count: Arc<AtomicUsize>) -> Self {
-            Self {
-                call_count,
-                error_on_call: None,
-                fail_first_n: None,
-            }
-        }
-
-        fn with_error(mut self, error_code: i64) -> Self {
-            self.error_on_call = Some(error_code);
-            self
-        }
-
-        fn fail_first_n_calls(mut self, n: usize) -> Self {
-            self.fail_first_n = Some(n);
-            self
-        }
+    // A model rate-limited `n` times before it answers.
+    fn rate_limited(n: usize) -> ScriptedLLM {
+        (0..n).fold(ScriptedLLM::new(), |llm, _| {
+            llm.rate_limited(Duration::from_millis(100))
+        })
     }
 
-    #[async_trait]
-    impl LLM for MockLLM {
-        async fn prompt(&mut self, _prompt: String) -> Result<String, LLMError> {
-            let count = self.call_count.fetch_add(1, Ordering::SeqCst) + 1;
-
-            // Handle fail_first_n scenario
-            if let Some(fail_count) = self.fail_first_n {
-                if count <= fail_count {
-                    let error_json = serde_json::json!({
-                        "error": {
-                            "code": 429,
-                            "message": "Rate limit exceeded",
-                            "status": "RESOURCE_EXHAUSTED",
-                            "details": [{
-                                "@type": "type.googleapis.com/google.rpc.RetryInfo",
-                                "retryDelay": "100ms"
-                            }]
-                        }
-                    });
-                    return Err(LLMError::PromptError(error_json.to_string()));
-                }
-                return Ok("Success after retries".to_string());
-            }
-
-            // Handle error_on_call scenario
-            if let Some(error_code) = self.error_on_call {
-                let error_json = serde_json::json!({
-                    "error": {
-                        "code": error_code,
-                        "message": "An error occurred.",
-                        "status": if error_code == 429 { "RESOURCE_EXHAUSTED" } else { "INTERNAL" }
-                    }
-                });
-                Err(LLMError::PromptError(error_json.to_string()))
-            } else {
-                Ok("Success".to_string())
-            }
-        }
+    // A model that fails with an internal server error.
+    fn server_error() -> ScriptedLLM {
+        let error = serde_json::json!({
+            "error": { "code": 500, "message": "An error occurred.", "status": "INTERNAL" }
+        });
+        ScriptedLLM::new().fail(error.to_string())
     }
 
     #[tokio::test]
     async fn test_no_retry_on_success() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone());
-        let mut retryable_llm = RetryableLLM::new(mock_llm, 3);
+        let model = ScriptedLLM::new().respond("Success");
+        let retryable_llm = RetryableLLM::new(model.clone(), 3);
 
         let result = retryable_llm.prompt("test".to_string()).await;
 
         assert!(result.is_ok());
-        assert_eq!(call_count.load(Ordering::SeqCst), 1);
+        assert_eq!(model.prompts().len(), 1);
     }
 
     #[tokio::test]
     async fn test_retry_on_429_error() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone()).with_error(429);
-        let mut retryable_llm = RetryableLLM::new(mock_llm, 3);
+        let model = rate_limited(4);
+        let retryable_llm = RetryableLLM::new(model.clone(), 3);
 
         let result = retryable_llm.prompt("test".to_string()).await;
 
         assert!(result.is_err());
-        assert_eq!(call_count.load(Ordering::SeqCst), 4); // 1 initial call + 3 retries
+        assert_eq!(model.prompts().len(), 4); // 1 initial call + 3 retries
+    }
+
+    #[tokio::test]
+    async fn test_boxed_retry_calls_the_retry_hook() {
+        let retries = Arc::new(AtomicUsize::new(0));
+        let model = rate_limited(1).respond("Success after retries");
+        let counter = retries.clone();
+        let llm =
+            BoxedRetryLLM::new(Box::new(model.clone()), 3).with_on_retry(Arc::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }));
+
+        let result = llm.prompt("test".to_string()).await;
+
+        assert!(result.is_ok());
+        assert_eq!(model.prompts().len(), 2);
+        assert_eq!(retries.load(Ordering::SeqCst), 1);
     }
 
     #[tokio::test]
     async fn test_no_retry_on_other_error() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone()).with_error(500);
-        let mut retryable_llm = RetryableLLM::new(mock_llm, 3);
+        let model = server_error();
+        let retryable_llm = RetryableLLM::new(model.clone(), 3);
 
         let result = retryable_llm.prompt("test".to_string()).await;
 
         assert!(result.is_err());
-        assert_eq!(call_count.load(Ordering::SeqCst), 1); // No retries for non-429 errors
+        assert_eq!(model.prompts().len(), 1); // No retries for non-429 errors
     }
 
     #[tokio::test]
     async fn test_success_after_retries() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone()).fail_first_n_calls(2);
-        let mut retryable_llm = RetryableLLM::new(mock_llm, 3);
+        let model = rate_limited(2).respond("Success after retries");
+        let retryable_llm = RetryableLLM::new(model.clone(), 3);
 
         let result = retryable_llm.prompt("test".to_string()).await;
 
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "Success after retries");
-        assert_eq!(call_count.load(Ordering::SeqCst), 3); // 2 failed + 1 success
+        assert_eq!(model.prompts().len(), 3); // 2 failed + 1 success
     }
 
     #[tokio::test]
     async fn test_manual_retry_success() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone());
-        let mut manual_retry_llm = ManualRetryLLM::new(mock_llm, 3, Duration::from_millis(10));
+        let model = ScriptedLLM::new().respond("Success");
+        let manual_retry_llm = ManualRetryLLM::new(model.clone(), 3, Duration::from_millis(10));
 
         let result = manual_retry_llm.prompt("test".to_string()).await;
 
         assert!(result.is_ok());
-        assert_eq!(call_count.load(Ordering::SeqCst), 1);
+        assert_eq!(model.prompts().len(), 1);
     }
 
     #[tokio::test]
     async fn test_manual_retry_on_429() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone()).fail_first_n_calls(2);
-        let mut manual_retry_llm = ManualRetryLLM::new(mock_llm, 3, Duration::from_millis(10));
+        let model = rate_limited(2).respond("Success after retries");
+        let manual_retry_llm = ManualRetryLLM::new(model.clone(), 3, Duration::from_millis(10));
 
         let result = manual_retry_llm.prompt("test".to_string()).await;
 
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "Success after retries");
-        assert_eq!(call_count.load(Ordering::SeqCst), 3);
+        assert_eq!(model.prompts().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_manual_retry_calls_the_retry_hook() {
+        let retries = Arc::new(AtomicUsize::new(0));
+        let model = rate_limited(2).respond("Success after retries");
+        let counter = retries.clone();
+        let llm = ManualRetryLLM::new(model.clone(), 3, Duration::from_millis(10)).with_on_retry(
+            Arc::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+
+        let result = llm.prompt("test".to_string()).await;
+
+        assert!(result.is_ok());
+        assert_eq!(retries.load(Ordering::SeqCst), 2);
     }
 
     #[tokio::test]
     async fn test_manual_retry_no_retry_on_500() {
-        let call_count = Arc::new(AtomicUsize::new(0));
-        let mock_llm = MockLLM::new(call_count.clone()).with_error(500);
-        let mut manual_retry_llm = ManualRetryLLM::new(mock_llm, 3, Duration::from_millis(10));
+        let model = server_error();
+        let manual_retry_llm = ManualRetryLLM::new(model.clone(), 3, Duration::from_millis(10));
 
         let result = manual_retry_llm.prompt("test".to_string()).await;
 
         assert!(result.is_err());
-        assert_eq!(call_count.load(Ordering::SeqCst), 1); // No retries for non-429 errors
+        assert_eq!(model.prompts().len(), 1); // No retries for non-429 errors
     }
 }
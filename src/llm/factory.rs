@@ -1,6 +1,6 @@
 use crate::llm::config::RetryConfig;
 use crate::llm::core::LLM;
-use crate::llm::decorators::BoxedRetryLLM;
+use crate::llm::decorators::{BoxedRetryLLM, RetryHook};
 
 /// Factory for creating LLM instances with optional decorators.
 ///
@@ -44,6 +44,18 @@ impl LLMFactory {
     /// let llm = LLMFactory::create(base_llm, Some(RetryConfig::disabled()));
     /// ```
     pub fn create(base_llm: Box<dyn LLM>, retry_config: Option<RetryConfig>) -> Box<dyn LLM> {
+        Self::create_with_retry_hook(base_llm, retry_config, None)
+    }
+
+    /// Create an LLM instance with optional retry decoration, calling `on_retry`
+    /// before each retry.
+    ///
+    /// This behaves like [`LLMFactory::create`]; the hook is ignored when no retry is applied.
+    pub fn create_with_retry_hook(
+        base_llm: Box<dyn LLM>,
+        retry_config: Option<RetryConfig>,
+        on_retry: Option<RetryHook>,
+    ) -> Box<dyn LLM> {
         match retry_config {
             Some(config) if config.max_attempts > 0 => {
                 tracing::debug!(
@@ -53,7 +65,11 @@ impl LLMFactory {
                     only_rate_limits = config.only_retry_rate_limits,
                     "Wrapping LLM with retry decorator"
                 );
-                Box::new(BoxedRetryLLM::new(base_llm, config.max_attempts))
+                let llm = BoxedRetryLLM::new(base_llm, config.max_attempts);
+                match on_retry {
+                    Some(on_retry) => Box::new(llm.with_on_retry(on_retry)),
+                    None => Box::new(llm),
+                }
             }
             Some(_) => {
                 tracing::debug!(
@@ -103,84 +119,68 @@ impl LLMFactory {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::llm::LLMError;
-    use async_trait::async_trait;
-
-    // Mock LLM for testing
-    struct MockLLM {
-        name: String,
-    }
+    use crate::testing::ScriptedLLM;
 
-    impl MockLLM {
-        fn new(name: &str) -> Self {
-            Self {
-                name: name.to_string(),
-            }
-        }
-    }
-
-    #[async_trait]
-    impl LLM for MockLLM {
-        async fn prompt(&mut self, prompt: String) -> Result<String, LLMError> {
-            Ok(format!("{}: {}", self.name, prompt))
-        }
+    // Answers every prompt with the prompt itself.
+    fn echo() -> Box<ScriptedLLM> {
+        Box::new(ScriptedLLM::new().respond_with(|prompt| Ok(prompt.to_string())))
     }
 
     #[tokio::test]
     async fn test_create_without_retry_config() {
-        let base_llm = Box::new(MockLLM::new("base"));
-        let mut llm = LLMFactory::create(base_llm, None);
+        let base_llm = echo();
+        let llm = LLMFactory::create(base_llm, None);
 
         // Test that the LLM works by calling prompt
         let result = llm.prompt("test".to_string()).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "base: test");
+        assert_eq!(result.unwrap(), "test");
     }
 
     #[tokio::test]
     async fn test_create_with_retry_config() {
-        let base_llm = Box::new(MockLLM::new("base"));
+        let base_llm = echo();
         let config = RetryConfig::default();
-        let mut llm = LLMFactory::create(base_llm, Some(config));
+        let llm = LLMFactory::create(base_llm, Some(config));
 
         // The returned LLM should be wrapped with retry logic and still work
         let result = llm.prompt("test".to_string()).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "base: test");
+        assert_eq!(result.unwrap(), "test");
     }
 
     #[tokio::test]
     async fn test_create_with_disabled_retry() {
-        let base_llm = Box::new(MockLLM::new("base"));
+        let base_llm = echo();
         let config = RetryConfig::disabled();
-        let mut llm = LLMFactory::create(base_llm, Some(config));
+        let llm = LLMFactory::create(base_llm, Some(config));
 
         // Should return the base LLM without retry wrapping since max_attempts = 0
         let result = llm.prompt("test".to_string()).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "base: test");
+        assert_eq!(result.unwrap(), "test");
     }
 
     #[tokio::test]
     async fn test_create_with_default_retry() {
-        let base_llm = Box::new(MockLLM::new("base"));
-        let mut llm = LLMFactory::create_with_default_retry(base_llm);
+        let base_llm = echo();
+        let llm = LLMFactory::create_with_default_retry(base_llm);
 
         // Should create LLM with default retry configuration and still work
         let result = llm.prompt("test".to_string()).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "base: test");
+        assert_eq!(result.unwrap(), "test");
     }
 
     #[tokio::test]
     async fn test_create_without_retry() {
-        let base_llm = Box::new(MockLLM::new("base"));
-        let mut llm = LLMFactory::create_without_retry(base_llm);
+        let base_llm = echo();
+        let llm = LLMFactory::create_without_retry(base_llm);
 
         // Should return the base LLM unchanged
         let result = llm.prompt("test".to_string()).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "base: test");
+        assert_eq!(result.unwrap(), "test");
     }
 
     #[test]
@@ -0,0 +1,337 @@
+// The `metrics` module records what the agent does, and renders it in the Prometheus
+// text exposition format.
+//
+// Every agent has its own `Metrics`, reachable from its `AgentHandle`. The agent can
+// also serve them over HTTP on a local listener, see `AgentBuilder::with_metrics_listener`.
+
+use crate::utils::http;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// The upper bounds of the LLM latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+/// A counter for each combination of label values.
+struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn inc(&self, values: &[&str]) {
+        let key = values.iter().map(|v| v.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    fn get(&self, values: &[&str]) -> u64 {
+        let key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        self.values.lock().unwrap().get(&key).copied().unwrap_or(0)
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        for (values, count) in self.values.lock().unwrap().iter() {
+            let labels = label_set(self.labels, values, None);
+            let _ = writeln!(out, "{}{labels} {count}", self.name);
+        }
+    }
+}
+
+/// The observations of a histogram for one combination of label values.
+#[derive(Default)]
+struct Histogram {
+    /// The number of observations in each bucket, not cumulated.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// A histogram for each combination of label values.
+struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, Histogram>>,
+}
+
+impl HistogramVec {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn observe(&self, values: &[&str], seconds: f64) {
+        let key = values.iter().map(|v| v.to_string()).collect();
+        let mut histograms = self.values.lock().unwrap();
+        let histogram = histograms.entry(key).or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        for (values, histogram) in self.values.lock().unwrap().iter() {
+            let mut cumulated = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulated += count;
+                let labels = label_set(self.labels, values, Some(&le.to_string()));
+                let _ = writeln!(out, "{}_bucket{labels} {cumulated}", self.name);
+            }
+            let labels = label_set(self.labels, values, Some("+Inf"));
+            let _ = writeln!(out, "{}_bucket{labels} {}", self.name, histogram.count);
+            let labels = label_set(self.labels, values, None);
+            let _ = writeln!(out, "{}_sum{labels} {}", self.name, histogram.sum);
+            let _ = writeln!(out, "{}_count{labels} {}", self.name, histogram.count);
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Formats a label set, with the `le` label of histogram buckets if given.
+fn label_set(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Escapes a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The metrics of an agent.
+///
+/// The metrics are recorded by the agent itself; [`Metrics::render`] returns them in
+/// the Prometheus text format.
+pub struct Metrics {
+    events_received: CounterVec,
+    render_failures: CounterVec,
+    llm_latency: HistogramVec,
+    llm_retries: CounterVec,
+    trigger_restarts: CounterVec,
+    queue_depth: AtomicUsize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates a new set of metrics, all empty.
+    pub fn new() -> Self {
+        Self {
+            events_received: CounterVec::new(
+                "forgeflow_events_received_total",
+                "Events received by the agent, by trigger and event name.",
+                &["trigger", "event"],
+            ),
+            render_failures: CounterVec::new(
+                "forgeflow_render_failures_total",
+                "Events whose prompt template failed to render, by route.",
+                &["route"],
+            ),
+            llm_latency: HistogramVec::new(
+                "forgeflow_llm_request_duration_seconds",
+                "Time spent waiting for the model, retries included, by route and outcome.",
+                &["route", "outcome"],
+            ),
+            llm_retries: CounterVec::new(
+                "forgeflow_llm_retries_total",
+                "LLM calls retried by the retry decorator, by route.",
+                &["route"],
+            ),
+            trigger_restarts: CounterVec::new(
+                "forgeflow_trigger_restarts_total",
+                "Triggers relaunched by their supervisor, by trigger.",
+                &["trigger"],
+            ),
+            queue_depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records an event emitted by a trigger, or injected under the `trigger` name.
+    pub fn event_received(&self, trigger: &str, event_name: &str) {
+        self.events_received.inc(&[trigger, event_name]);
+    }
+
+    /// Records a prompt template that failed to render.
+    pub fn render_failed(&self, route: &str) {
+        self.render_failures.inc(&[route]);
+    }
+
+    /// Records the duration of an LLM call and whether it succeeded.
+    pub fn llm_call(&self, route: &str, duration: Duration, success: bool) {
+        let outcome = if success { "success" } else { "error" };
+        self.llm_latency
+            .observe(&[route, outcome], duration.as_secs_f64());
+    }
+
+    /// Records an LLM call retried by the retry decorator.
+    pub fn llm_retried(&self, route: &str) {
+        self.llm_retries.inc(&[route]);
+    }
+
+    /// Records a trigger relaunched by its supervisor.
+    pub fn trigger_restarted(&self, trigger: &str) {
+        self.trigger_restarts.inc(&[trigger]);
+    }
+
+    /// Sets the number of events waiting in the agent's queue.
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Returns the number of events waiting in the agent's queue.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many events named `event_name` were received from `trigger`.
+    pub fn events_received(&self, trigger: &str, event_name: &str) -> u64 {
+        self.events_received.get(&[trigger, event_name])
+    }
+
+    /// Returns how many LLM calls were retried on `route`.
+    pub fn llm_retries(&self, route: &str) -> u64 {
+        self.llm_retries.get(&[route])
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.events_received.render(&mut out);
+        self.render_failures.render(&mut out);
+        self.llm_latency.render(&mut out);
+        self.llm_retries.render(&mut out);
+        self.trigger_restarts.render(&mut out);
+        header(
+            &mut out,
+            "forgeflow_queue_depth",
+            "Events waiting in the agent's queue, collected in a batch, or held back by an event policy.",
+            "gauge",
+        );
+        let _ = writeln!(out, "forgeflow_queue_depth {}", self.queue_depth());
+        out
+    }
+}
+
+/// Serves the metrics on `GET /metrics` until `stop` is cancelled.
+pub(crate) async fn serve(listener: TcpListener, metrics: Arc<Metrics>, stop: CancellationToken) {
+    http::serve("metrics", listener, stop, move |request| {
+        let metrics = metrics.clone();
+        async move { respond(&request, &metrics) }
+    })
+    .await
+}
+
+fn respond(request: &Request<Incoming>, metrics: &Metrics) -> Response<Full<Bytes>> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => http::response(
+            StatusCode::OK,
+            "text/plain; version=0.0.4",
+            metrics.render(),
+        ),
+        _ => http::text(StatusCode::NOT_FOUND, "Not found\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_renders_counters_and_histograms() {
+        let metrics = Metrics::new();
+        metrics.event_received("trigger-0", "Tick");
+        metrics.event_received("trigger-0", "Tick");
+        metrics.llm_call("default", Duration::from_millis(300), true);
+        metrics.set_queue_depth(3);
+
+        let text = metrics.render();
+        assert!(
+            text.contains(
+                "forgeflow_events_received_total{trigger=\"trigger-0\",event=\"Tick\"} 2"
+            )
+        );
+        assert!(text.contains(
+            "forgeflow_llm_request_duration_seconds_bucket{route=\"default\",outcome=\"success\",le=\"0.25\"} 0"
+        ));
+        assert!(text.contains(
+            "forgeflow_llm_request_duration_seconds_bucket{route=\"default\",outcome=\"success\",le=\"0.5\"} 1"
+        ));
+        assert!(text.contains(
+            "forgeflow_llm_request_duration_seconds_count{route=\"default\",outcome=\"success\"} 1"
+        ));
+        assert!(text.contains("forgeflow_queue_depth 3"));
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics_over_http() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.llm_retried("Telegram*");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = CancellationToken::new();
+        let server = tokio::spawn(serve(listener, metrics, stop.clone()));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("forgeflow_llm_retries_total{route=\"Telegram*\"} 1"));
+
+        stop.cancel();
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+// The `observer` module provides the `AgentObserver` trait, whose hooks are called by
+// the agent at each step of the processing of an event.
+//
+// Observers run in the order they were added. Each one sees the event and the prompt
+// as modified by the observers before it, and the first one to veto ends the processing.
+
+use crate::dead_letter::FailureStage;
+use crate::llm::LLMError;
+use crate::triggers::event::TEvent;
+use crate::utils::TEngineError;
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+/// Whether the processing of an event goes on after a hook.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The processing goes on.
+    Continue,
+    /// The processing stops here, for the given reason.
+    ///
+    /// A vetoed event is not dead-lettered: it is reported to its trigger as skipped.
+    Veto(String),
+}
+
+/// How long the steps of the processing of an event took.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// The time spent rendering the prompt.
+    pub render: Duration,
+    /// The time spent waiting for the model, retries included.
+    pub llm: Duration,
+    /// The time since the agent started processing the event.
+    pub total: Duration,
+}
+
+/// Why the processing of an event failed.
+#[derive(Debug)]
+pub enum ProcessingError<'a> {
+    /// The prompt template could not be rendered for the event.
+    Render(&'a TEngineError),
+    /// The model failed, after any retries.
+    Model(&'a LLMError),
+}
+
+impl ProcessingError<'_> {
+    /// Returns the stage at which the processing failed.
+    pub fn stage(&self) -> FailureStage {
+        match self {
+            Self::Render(_) => FailureStage::Render,
+            Self::Model(_) => FailureStage::Prompt,
+        }
+    }
+}
+
+impl fmt::Display for ProcessingError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Render(e) => write!(f, "{e}"),
+            Self::Model(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// The `AgentObserver` trait lets code outside the agent follow, and alter, the
+/// processing of each event.
+///
+/// Every hook has a default implementation that does nothing, so observers only
+/// implement the ones they need.
+///
+/// # Example
+/// ```rust,ignore
+/// use forgeflow::observer::{AgentObserver, Verdict};
+///
+/// struct DropSpam;
+///
+/// #[async_trait::async_trait]
+/// impl AgentObserver for DropSpam {
+///     async fn on_event(&self, event: &mut TEvent) -> Verdict {
+///         if event.name == "Spam" {
+///             return Verdict::Veto("spam".to_string());
+///         }
+///         Verdict::Continue
+///     }
+/// }
+///
+/// let agent = AgentBuilder::new().add_observer(DropSpam).build()?;
+/// ```
+#[async_trait]
+pub trait AgentObserver: Send + Sync {
+    /// Called when the agent starts processing an event, before it is routed.
+    ///
+    /// The event can be modified: the modified event is the one routed, rendered,
+    /// passed to the handlers and, if it fails, dead-lettered.
+    async fn on_event(&self, _event: &mut TEvent) -> Verdict {
+        Verdict::Continue
+    }
+
+    /// Called with the rendered prompt, before the model is prompted.
+    ///
+    /// The prompt can be modified before it is sent.
+    async fn on_prompt_rendered(&self, _event: &TEvent, _prompt: &mut String) -> Verdict {
+        Verdict::Continue
+    }
+
+    /// Called with the model's response, before the response handlers.
+    async fn on_response(&self, _event: &TEvent, _response: &str, _timing: &Timing) {}
+
+    /// Called when the processing of an event fails: with the template error if the
+    /// prompt can't be rendered, or with the model's error, after any retries and
+    /// before the error handlers.
+    async fn on_error(&self, _event: &TEvent, _error: &ProcessingError<'_>, _timing: &Timing) {}
+}
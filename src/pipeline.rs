@@ -0,0 +1,216 @@
+// The `pipeline` module runs several agents that feed each other through
+// `ChannelTrigger`s, and shuts them down together.
+
+use crate::agent::{Agent, AgentError, ShutdownReport};
+use crate::shutdown::{CtrlCShutdown, Never, Shutdown};
+use crate::triggers::ChannelTrigger;
+use tokio_util::task::TaskTracker;
+use tracing::info;
+
+/// Runs agents connected by channels, from the upstream ones to the downstream ones.
+///
+/// Each stage is an [`Agent`] whose responses can be forwarded into the
+/// [`ChannelTrigger`] of the next ones with [`ChannelTrigger::forward`]. An agent may
+/// forward into several channels (fan-out), and several agents may forward into the
+/// same channel (fan-in).
+///
+/// The pipeline has a single shutdown handler, which replaces those of its stages.
+/// Once it fires, the stages are shut down one after the other in the order they
+/// were added, each one after the events forwarded into the channels have been
+/// processed. Stages must therefore be added upstream first.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let triaged = ChannelTriggerBuilder::new().build();
+/// let triage = AgentBuilder::new()
+///     .add_trigger(Box::new(gmail))
+///     .add_response_handler(triaged.forward("Triaged"))
+///     // ...
+///     .build()?;
+/// let drafts = AgentBuilder::new()
+///     .add_trigger(Box::new(triaged.clone()))
+///     .with_prompt_template("Draft a reply to: {{payload.event.payload.body}}".to_string())
+///     // ...
+///     .build()?;
+/// let reports = Pipeline::new()
+///     .add_stage(triage)
+///     .add_stage(drafts)
+///     .add_channel(triaged)
+///     .run()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Agent>,
+    channels: Vec<ChannelTrigger>,
+    shutdown_handler: Option<Box<dyn Shutdown>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an agent after the stages already added.
+    pub fn add_stage(mut self, agent: Agent) -> Self {
+        self.stages.push(agent);
+        self
+    }
+
+    /// Adds a channel between stages, whose events are processed before the stages
+    /// after the current one are shut down.
+    pub fn add_channel(mut self, channel: ChannelTrigger) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Sets the shutdown handler of the pipeline, `CtrlCShutdown` by default.
+    pub fn with_shutdown_handler(mut self, handler: impl Shutdown + 'static) -> Self {
+        self.shutdown_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Runs every stage until the pipeline is shut down, or until every stage has
+    /// stopped on its own.
+    ///
+    /// Returns the shutdown report of each stage, in the order they were added, or
+    /// the first error a stage stopped with.
+    pub async fn run(self) -> Result<Vec<ShutdownReport>, AgentError> {
+        let mut shutdown_handler = self
+            .shutdown_handler
+            .unwrap_or_else(|| Box::new(CtrlCShutdown::new()));
+        let tasks = TaskTracker::new();
+        let stages: Vec<_> = self
+            .stages
+            .into_iter()
+            .map(|mut agent| {
+                // The pipeline shuts its stages down itself.
+                agent.set_shutdown_handler(Box::new(Never));
+                let handle = agent.handle();
+                (handle, tasks.spawn(agent.run()))
+            })
+            .collect();
+        tasks.close();
+        info!(stages = stages.len(), "Pipeline started");
+
+        tokio::select! {
+            _ = shutdown_handler.wait_for_signal() => {
+                info!("External shutdown signal triggered the pipeline termination");
+            }
+            _ = tasks.wait() => info!("Every stage of the pipeline has stopped"),
+        }
+
+        let mut results = Vec::with_capacity(stages.len());
+        for (index, (handle, task)) in stages.into_iter().enumerate() {
+            if index > 0 {
+                // What the upstream stages forwarded is processed before moving on.
+                for channel in &self.channels {
+                    channel.wait_processed().await;
+                }
+            }
+            handle.shutdown();
+            let result = match task.await {
+                Ok(result) => result,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            };
+            info!(stage = index, "Pipeline stage has shut down");
+            results.push(result);
+        }
+        results.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentBuilder;
+    use crate::handlers::{HandlerError, ResponseHandler};
+    use crate::testing::ScriptedLLM;
+    use crate::triggers::{ChannelTriggerBuilder, event::TEvent};
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // Keeps the responses it handles.
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl ResponseHandler for Collect {
+        async fn handle_response(&self, _: &TEvent, response: &str) -> Result<(), HandlerError> {
+            self.0.lock().unwrap().push(response.to_string());
+            Ok(())
+        }
+    }
+
+    fn stage(
+        delay: Duration,
+        template: &str,
+        trigger: &ChannelTrigger,
+        handler: impl ResponseHandler + 'static,
+    ) -> Agent {
+        AgentBuilder::new()
+            .with_model(Box::new(
+                ScriptedLLM::new()
+                    .respond_with(|prompt| Ok(prompt.to_string()))
+                    .with_latency(delay),
+            ))
+            .with_prompt_template(template.to_string())
+            .add_trigger(Box::new(trigger.clone()))
+            .add_response_handler(handler)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_feeds_responses_downstream_and_drains_on_shutdown() {
+        let inbox = ChannelTriggerBuilder::new().build();
+        let triaged = ChannelTriggerBuilder::new().build();
+        let drafts = Collect::default();
+        // The shutdown comes while the first stage is still busy.
+        let triage = stage(
+            Duration::from_millis(100),
+            "triaged {{payload.subject}}",
+            &inbox,
+            triaged.forward("Triaged"),
+        );
+        let draft = stage(
+            Duration::ZERO,
+            "{{payload.event.name}}: {{payload.response}}",
+            &triaged,
+            drafts.clone(),
+        );
+
+        for subject in ["invoice", "meeting"] {
+            inbox
+                .send(TEvent {
+                    name: "NewEmail".to_string(),
+                    payload: Some(json!({ "subject": subject })),
+                })
+                .await;
+        }
+        let reports = Pipeline::new()
+            .add_stage(triage)
+            .add_stage(draft)
+            .add_channel(inbox)
+            .add_channel(triaged)
+            .with_shutdown_handler(crate::shutdown::TimeBasedShutdown::new(
+                Duration::from_millis(50),
+            ))
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(reports[0].processed, 2);
+        assert_eq!(reports[1].processed, 2);
+        let mut drafts = drafts.0.lock().unwrap().clone();
+        drafts.sort();
+        assert_eq!(
+            drafts,
+            ["NewEmail: triaged invoice", "NewEmail: triaged meeting"]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+// The `recording` module records the events an agent receives from its triggers, so
+// that they can be replayed later with a `ReplayTrigger`.
+//
+// A recording is a JSON Lines file with one event per line, with the time it was
+// received: `{"received_at": "2025-06-01T08:00:00Z", "name": "NewEmail", "payload": {...}}`.
+
+use crate::triggers::event::TEvent;
+use crate::utils::jsonl::append_line;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// An event of a recording, with the time it was received.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedEvent {
+    /// When the agent received the event.
+    pub received_at: DateTime<Utc>,
+    /// The event.
+    #[serde(flatten)]
+    pub event: TEvent,
+}
+
+/// Appends the events it is given to a recording file.
+///
+/// Clones share the same file.
+#[derive(Clone, Debug)]
+pub struct EventRecorder {
+    path: PathBuf,
+    /// Serializes writes from concurrent triggers.
+    lock: Arc<Mutex<()>>,
+}
+
+impl EventRecorder {
+    /// Creates a new `EventRecorder` appending to the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Appends an event, received now, to the recording.
+    pub async fn record(&self, event: &TEvent) -> std::io::Result<()> {
+        let record = RecordedEvent {
+            received_at: Utc::now(),
+            event: event.clone(),
+        };
+        let _guard = self.lock.lock().await;
+        append_line(&self.path, &record).await
+    }
+}
+
+/// Reads the events of a recording, in the order they were recorded.
+///
+/// Blank lines are skipped; any other line that is not a recorded event is an error.
+pub fn read_recording(path: impl AsRef<Path>) -> std::io::Result<Vec<RecordedEvent>> {
+    let file = std::fs::File::open(path)?;
+    let mut events = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: {e}", number + 1),
+            )
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_recorded_events_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let recorder = EventRecorder::new(&path);
+        let events = [
+            TEvent {
+                name: "NewEmail".to_string(),
+                payload: Some(json!({"subject": "Invoice", "labels": ["INBOX"]})),
+            },
+            TEvent {
+                name: "Tick".to_string(),
+                payload: None,
+            },
+            TEvent {
+                name: "Null".to_string(),
+                payload: Some(Value::Null),
+            },
+        ];
+        for event in &events {
+            recorder.record(event).await.unwrap();
+        }
+
+        let recorded = read_recording(&path).unwrap();
+        assert_eq!(recorded.len(), 3);
+        for (recorded, event) in recorded.iter().zip(&events) {
+            assert_eq!(recorded.event.name, event.name);
+            assert_eq!(recorded.event.payload, event.payload);
+        }
+        assert!(recorded[0].received_at <= recorded[2].received_at);
+    }
+}
@@ -87,3 +87,14 @@ impl Shutdown for TimeBasedShutdown {
         );
     }
 }
+
+/// A shutdown handler that never fires, for agents shut down through their handle.
+#[derive(Clone)]
+pub(crate) struct Never;
+
+#[async_trait]
+impl Shutdown for Never {
+    async fn wait_for_signal(&mut self) {
+        std::future::pending::<()>().await
+    }
+}
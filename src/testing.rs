@@ -0,0 +1,472 @@
+// The `testing` module provides test doubles for the parts of an agent that talk to
+// the outside world, and a harness to run an agent against them.
+//
+// It is compiled with the `testing` feature:
+//
+//     [dev-dependencies]
+//     forgeflow = { version = "...", features = ["testing"] }
+
+use crate::agent::{AgentBuilder, AgentError, AgentHandle, ShutdownReport};
+use crate::llm::{LLM, LLMError};
+use crate::observer::{AgentObserver, ProcessingError, Timing, Verdict};
+use crate::shutdown::Never;
+use crate::triggers::event::{Ack, AckedEvent, Outcome, TEvent};
+use crate::triggers::{Trigger, TriggerError};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::{self, JoinHandle};
+
+/// How long [`AgentHarness::wait_for`] waits, by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers the prompts left once the script is exhausted.
+type Fallback = Arc<dyn Fn(&str) -> Result<String, String> + Send + Sync>;
+
+#[derive(Default)]
+struct Script {
+    replies: VecDeque<Result<String, String>>,
+    fallback: Option<Fallback>,
+    prompts: Vec<String>,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+/// A model that answers with a script of responses and errors, in order.
+///
+/// Clones share the same script, so a clone can be kept to inspect the prompts once
+/// the model is given to an agent. Once the script is exhausted, every prompt fails,
+/// unless [`respond_with`](Self::respond_with) answers it.
+///
+/// # Example
+/// ```rust,ignore
+/// let llm = ScriptedLLM::new()
+///     .rate_limited(Duration::from_millis(10))
+///     .respond("a haiku")
+///     .fail("model overloaded");
+/// ```
+#[derive(Clone, Default)]
+pub struct ScriptedLLM {
+    script: Arc<Mutex<Script>>,
+    latency: Duration,
+}
+
+impl ScriptedLLM {
+    /// Creates a model with an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a response to the script.
+    pub fn respond(self, response: impl Into<String>) -> Self {
+        self.push(Ok(response.into()))
+    }
+
+    /// Adds an error to the script, which is not retried.
+    pub fn fail(self, message: impl Into<String>) -> Self {
+        self.push(Err(message.into()))
+    }
+
+    /// Adds a rate-limit error to the script, formatted like a Gemini 429 response,
+    /// which the retry decorators retry after `retry_delay`.
+    pub fn rate_limited(self, retry_delay: Duration) -> Self {
+        let error = json!({
+            "error": {
+                "code": 429,
+                "message": "Resource has been exhausted (e.g. check quota).",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [{
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": humantime::format_duration(retry_delay).to_string(),
+                }]
+            }
+        });
+        self.push(Err(error.to_string()))
+    }
+
+    /// Answers every prompt left once the script is exhausted with `reply`, which
+    /// is given the prompt.
+    ///
+    /// ```rust,ignore
+    /// let echo = ScriptedLLM::new().respond_with(|prompt| Ok(prompt.to_string()));
+    /// ```
+    pub fn respond_with(
+        self,
+        reply: impl Fn(&str) -> Result<String, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.script.lock().unwrap().fallback = Some(Arc::new(reply));
+        self
+    }
+
+    /// Makes every prompt take `latency` before it is answered.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Returns the prompts the model received, in order, retries included.
+    pub fn prompts(&self) -> Vec<String> {
+        self.script.lock().unwrap().prompts.clone()
+    }
+
+    /// Returns the most prompts the model was answering at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.script.lock().unwrap().max_in_flight
+    }
+
+    /// Returns the number of replies left in the script.
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().replies.len()
+    }
+
+    fn push(self, reply: Result<String, String>) -> Self {
+        self.script.lock().unwrap().replies.push_back(reply);
+        self
+    }
+}
+
+#[async_trait]
+impl LLM for ScriptedLLM {
+    async fn prompt(&self, prompt: String) -> Result<String, LLMError> {
+        let reply = {
+            let mut script = self.script.lock().unwrap();
+            let reply = match (script.replies.pop_front(), &script.fallback) {
+                (Some(reply), _) => reply,
+                (None, Some(fallback)) => fallback(&prompt),
+                (None, None) => Err("the script has no reply left".to_string()),
+            };
+            script.prompts.push(prompt);
+            script.in_flight += 1;
+            script.max_in_flight = script.max_in_flight.max(script.in_flight);
+            reply
+        };
+        tokio::time::sleep(self.latency).await;
+        self.script.lock().unwrap().in_flight -= 1;
+        reply.map_err(LLMError::PromptError)
+    }
+}
+
+struct Channel {
+    tx: mpsc::UnboundedSender<AckedEvent>,
+    rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<AckedEvent>>,
+}
+
+/// A trigger that emits the events a test gives it.
+///
+/// Clones share the same events, so a clone can be kept to drive the trigger once it
+/// is given to an agent. Events fired before the trigger is launched are kept until it
+/// is.
+#[derive(Clone)]
+pub struct ManualTrigger {
+    channel: Arc<Channel>,
+}
+
+impl Default for ManualTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualTrigger {
+    /// Creates a trigger without events.
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            channel: Arc::new(Channel {
+                tx,
+                rx: tokio::sync::Mutex::new(rx),
+            }),
+        }
+    }
+
+    /// Emits an event with a name and a payload.
+    pub fn fire(&self, name: &str, payload: Value) {
+        self.send(TEvent {
+            name: name.to_string(),
+            payload: Some(payload),
+        });
+    }
+
+    /// Emits an event.
+    pub fn send(&self, event: TEvent) {
+        // The trigger holds its own receiver, so the channel is never closed.
+        let _ = self.channel.tx.send(AckedEvent::from(event));
+    }
+
+    /// Emits an event and waits for the agent to report its outcome.
+    ///
+    /// Returns `None` if the agent dropped the event without processing it.
+    pub async fn process(&self, event: TEvent) -> Option<Outcome> {
+        let (ack, outcome) = Ack::channel();
+        let _ = self.channel.tx.send(AckedEvent { event, ack });
+        outcome.await.ok()
+    }
+}
+
+#[async_trait]
+impl Trigger for ManualTrigger {
+    /// Launches the trigger's long-running task.
+    async fn launch(
+        &self,
+        tx: mpsc::Sender<TEvent>,
+        shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let (acked_tx, mut acked_rx) = mpsc::channel::<AckedEvent>(1);
+        let handle = self.launch_acked(acked_tx, shutdown_rx).await?;
+        tokio::spawn(async move {
+            while let Some(AckedEvent { event, ack }) = acked_rx.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+                ack.ack();
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Launches the trigger's long-running task, emitting the events with their ack.
+    async fn launch_acked(
+        &self,
+        tx: mpsc::Sender<AckedEvent>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let channel = self.channel.clone();
+        Ok(tokio::spawn(async move {
+            let mut rx = channel.rx.lock().await;
+            loop {
+                let event = tokio::select! {
+                    _ = shutdown_rx.recv() => break,
+                    event = rx.recv() => event,
+                };
+                let Some(event) = event else { break };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        }))
+    }
+}
+
+/// An event that reached the model, with what the model answered.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    /// The event, as the observers left it.
+    pub event: TEvent,
+    /// The prompt sent to the model.
+    pub prompt: String,
+    /// The model's response, or its error after any retries.
+    pub response: Result<String, String>,
+}
+
+#[derive(Default)]
+struct Log {
+    /// The prompts rendered for the events in flight, by the task processing them.
+    ///
+    /// Each event is processed on its own task, from its rendered prompt to the
+    /// model's answer, so identical events in flight at once are told apart.
+    pending: Mutex<HashMap<task::Id, String>>,
+    exchanges: watch::Sender<Vec<Exchange>>,
+}
+
+impl Log {
+    fn start(&self, prompt: &str) {
+        if let Some(id) = task::try_id() {
+            self.pending.lock().unwrap().insert(id, prompt.to_string());
+        }
+    }
+
+    fn complete(&self, event: &TEvent, response: Result<String, String>) {
+        let prompt = task::try_id().and_then(|id| self.pending.lock().unwrap().remove(&id));
+        self.exchanges.send_modify(|exchanges| {
+            exchanges.push(Exchange {
+                event: event.clone(),
+                prompt: prompt.unwrap_or_default(),
+                response,
+            })
+        });
+    }
+}
+
+/// Records each exchange, as the last observer of the agent.
+struct Recorder(Arc<Log>);
+
+#[async_trait]
+impl AgentObserver for Recorder {
+    async fn on_prompt_rendered(&self, _event: &TEvent, prompt: &mut String) -> Verdict {
+        self.0.start(prompt);
+        Verdict::Continue
+    }
+
+    async fn on_response(&self, event: &TEvent, response: &str, _timing: &Timing) {
+        self.0.complete(event, Ok(response.to_string()));
+    }
+
+    async fn on_error(&self, event: &TEvent, error: &ProcessingError<'_>, _timing: &Timing) {
+        // A prompt that could not be rendered was never sent.
+        if let ProcessingError::Model(e) = error {
+            self.0.complete(event, Err(e.to_string()));
+        }
+    }
+}
+
+/// Runs an agent in the background and collects what it sends to its model.
+///
+/// The agent runs until [`shutdown`](Self::shutdown): the harness replaces its
+/// shutdown handler.
+///
+/// # Example
+/// ```rust,ignore
+/// let llm = ScriptedLLM::new().respond("hello ann");
+/// let trigger = ManualTrigger::new();
+/// let harness = AgentHarness::start(
+///     AgentBuilder::new()
+///         .with_model(Box::new(llm))
+///         .with_prompt_template("Greet {{payload.name}}".to_string())
+///         .add_trigger(Box::new(trigger.clone())),
+/// )?;
+///
+/// trigger.fire("Greet", json!({ "name": "ann" }));
+/// let exchanges = harness.wait_for(1).await;
+/// assert_eq!(exchanges[0].prompt, "Greet ann");
+/// assert_eq!(exchanges[0].response.as_deref(), Ok("hello ann"));
+/// harness.shutdown().await?;
+/// ```
+pub struct AgentHarness {
+    handle: AgentHandle,
+    task: JoinHandle<Result<ShutdownReport, AgentError>>,
+    log: Arc<Log>,
+    timeout: Duration,
+}
+
+impl AgentHarness {
+    /// Builds the agent and runs it on a new task.
+    pub fn start(builder: AgentBuilder) -> Result<Self, AgentError> {
+        let log = Arc::new(Log::default());
+        let agent = builder
+            .add_observer(Recorder(log.clone()))
+            .with_shutdown_handler(Never)
+            .build()?;
+        let (handle, task) = agent.spawn();
+        Ok(Self {
+            handle,
+            task,
+            log,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets how long [`wait_for`](Self::wait_for) waits, 5 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns a handle to the agent.
+    pub fn handle(&self) -> &AgentHandle {
+        &self.handle
+    }
+
+    /// Waits until `count` events have reached the model and been answered, and
+    /// returns every exchange so far, in the order they completed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer exchanges complete within the timeout.
+    pub async fn wait_for(&self, count: usize) -> Vec<Exchange> {
+        let mut exchanges = self.log.exchanges.subscribe();
+        let waited = tokio::time::timeout(
+            self.timeout,
+            exchanges.wait_for(|exchanges| exchanges.len() >= count),
+        )
+        .await;
+        match waited {
+            Ok(Ok(exchanges)) => exchanges.clone(),
+            _ => panic!(
+                "{} of {count} exchanges completed within {:?}",
+                self.exchanges().len(),
+                self.timeout
+            ),
+        }
+    }
+
+    /// Returns the exchanges completed so far.
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.log.exchanges.borrow().clone()
+    }
+
+    /// Shuts the agent down and returns its report.
+    pub async fn shutdown(self) -> Result<ShutdownReport, AgentError> {
+        self.handle.shutdown();
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::RetryConfig;
+    use crate::llm::RetryStrategy;
+
+    #[tokio::test]
+    async fn test_harness_collects_prompts_and_responses_across_retries() {
+        let llm = ScriptedLLM::new()
+            .respond("hello ann")
+            .rate_limited(Duration::from_millis(10))
+            .respond("hello bob")
+            .fail("overloaded");
+        let trigger = ManualTrigger::new();
+        let harness = AgentHarness::start(
+            AgentBuilder::new()
+                .with_model(Box::new(llm.clone()))
+                .with_prompt_template("Greet {{payload.name}}".to_string())
+                .with_retry_config(RetryConfig::new(
+                    2,
+                    Duration::from_millis(10),
+                    RetryStrategy::Fixed,
+                ))
+                .add_trigger(Box::new(trigger.clone())),
+        )
+        .unwrap();
+
+        for name in ["ann", "bob", "eve"] {
+            trigger.fire("Greet", json!({ "name": name }));
+        }
+        let exchanges = harness.wait_for(3).await;
+        let summary: Vec<_> = exchanges
+            .iter()
+            .map(|exchange| (exchange.prompt.as_str(), exchange.response.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Greet ann", Ok("hello ann".to_string())),
+                ("Greet bob", Ok("hello bob".to_string())),
+                (
+                    "Greet eve",
+                    Err("Failed to prompt the model: overloaded".to_string())
+                ),
+            ]
+        );
+        // The rate-limited prompt was retried.
+        assert_eq!(llm.prompts().len(), 4);
+        assert_eq!(llm.remaining(), 0);
+
+        let outcome = trigger
+            .process(TEvent {
+                name: "Greet".to_string(),
+                payload: Some(json!({ "name": "zoe" })),
+            })
+            .await;
+        assert!(matches!(outcome, Some(Outcome::Nack(_))));
+
+        let report = harness.shutdown().await.unwrap();
+        assert_eq!(report.processed, 4);
+        assert_eq!(report.failed, 2);
+    }
+}
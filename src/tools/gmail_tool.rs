@@ -118,6 +118,7 @@ mod tests {
     use std::path::Path;
 
     #[tokio::test]
+    #[ignore = "needs Gmail credentials in ./tmp"]
     async fn gmail_tool_call_succeeds() {
         // --- 1. Arrange ---
         // This test requires a valid message ID to run successfully.
@@ -135,14 +136,7 @@ mod tests {
 
         let hub = Arc::new(ContextHub::new(conf));
         let builder = GmailToolBuilder::new(hub);
-        let tool_result = builder.build().await;
-
-        if tool_result.is_err() {
-            println!("Skipping test because of missing credentials or auth error.");
-            return;
-        }
-
-        let tool = tool_result.unwrap();
+        let tool = builder.build().await.unwrap();
         let args = GTArgs { message_id };
 
         // --- 2. Act ---
@@ -0,0 +1,152 @@
+// The `bus_trigger` module provides a trigger that emits the events of an `EventBus`
+// subscription.
+
+use crate::bus::{Backpressure, EventBus, Queue};
+use crate::triggers::{
+    Trigger, TriggerError,
+    event::{AckedEvent, TEvent},
+};
+use crate::utils::EventPattern;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// The number of events a subscription holds before its backpressure applies, by default.
+const DEFAULT_CAPACITY: usize = 100;
+
+/// A builder for [`BusTrigger`], which subscribes to an [`EventBus`].
+pub struct BusTriggerBuilder {
+    bus: EventBus,
+    pattern: EventPattern,
+    group: Option<String>,
+    capacity: usize,
+    backpressure: Backpressure,
+}
+
+impl BusTriggerBuilder {
+    /// Creates a new `BusTriggerBuilder`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bus` - The bus to subscribe to.
+    /// * `pattern` - The pattern the names of the events must match, e.g. `Telegram*`.
+    pub fn new(bus: &EventBus, pattern: &str) -> Self {
+        Self {
+            bus: bus.clone(),
+            pattern: EventPattern::new(pattern),
+            group: None,
+            capacity: DEFAULT_CAPACITY,
+            backpressure: Backpressure::default(),
+        }
+    }
+
+    /// Joins a group: the members of a group share one queue, and each event goes to
+    /// only one of them.
+    ///
+    /// The first member of a group decides its pattern, capacity and backpressure.
+    pub fn in_group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// Sets how many events the subscription holds before its backpressure applies.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets what publishing does when the subscription's queue is full.
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Subscribes to the bus and builds a `BusTrigger`.
+    ///
+    /// The subscription receives events from now on, and lasts as long as the
+    /// trigger. Once the trigger has stopped, it receives none until it is launched
+    /// again.
+    pub fn build(self) -> BusTrigger {
+        BusTrigger {
+            queue: self
+                .bus
+                .queue(self.pattern, self.group, self.capacity, self.backpressure),
+        }
+    }
+}
+
+/// A trigger that emits the events of an [`EventBus`] subscription.
+///
+/// The outcome the agent reports for each event is passed back to the bus.
+pub struct BusTrigger {
+    queue: Arc<Queue>,
+}
+
+impl BusTrigger {
+    /// Returns the number of events dropped because the subscription's queue was full.
+    ///
+    /// The members of a group share the count.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl Trigger for BusTrigger {
+    /// Launches the trigger's long-running task.
+    ///
+    /// Without acks, an event counts as processed once the agent has received it.
+    async fn launch(
+        &self,
+        tx: mpsc::Sender<TEvent>,
+        shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let (acked_tx, mut acked_rx) = mpsc::channel::<AckedEvent>(1);
+        let handle = self.launch_acked(acked_tx, shutdown_rx).await?;
+        tokio::spawn(async move {
+            while let Some(AckedEvent { event, ack }) = acked_rx.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+                ack.ack();
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Launches the trigger's long-running task, emitting the events of the
+    /// subscription with the ack of their bus delivery.
+    async fn launch_acked(
+        &self,
+        tx: mpsc::Sender<AckedEvent>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let queue = self.queue.clone();
+        let consumer = queue.consume();
+        Ok(tokio::spawn(async move {
+            // Closes the queue if this was its last consumer, so publishing no longer
+            // waits for it.
+            let _consumer = consumer;
+            info!("BusTrigger started");
+            loop {
+                // The members of a group take the queue in turn, one event at a time.
+                let event = tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        info!("BusTrigger received shutdown signal, terminating");
+                        break;
+                    }
+                    event = async { queue.rx.lock().await.recv().await } => event,
+                };
+                // The queue holds its own sender, so it is never closed.
+                let Some(event) = event else { break };
+                debug!(event_name = %event.event.name, "Emitting event from the bus");
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,229 @@
+// The `channel_trigger` module provides a trigger that emits the events sent into its
+// channel, which lets one agent feed another.
+
+use crate::handlers::ForwardHandler;
+use crate::triggers::{
+    Trigger, TriggerError,
+    event::{Ack, AckedEvent, TEvent},
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// The number of events the channel holds before senders wait, by default.
+const DEFAULT_CAPACITY: usize = 100;
+
+/// A builder for [`ChannelTrigger`].
+pub struct ChannelTriggerBuilder {
+    capacity: usize,
+}
+
+impl Default for ChannelTriggerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelTriggerBuilder {
+    /// Creates a new `ChannelTriggerBuilder`.
+    pub fn new() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Sets how many events the channel holds before senders wait.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Builds a `ChannelTrigger`.
+    pub fn build(&self) -> ChannelTrigger {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        ChannelTrigger {
+            channel: Arc::new(Channel {
+                tx,
+                rx: Mutex::new(rx),
+                state: watch::Sender::new(State::default()),
+            }),
+        }
+    }
+}
+
+/// The channel shared by the clones of a `ChannelTrigger`.
+struct Channel {
+    tx: mpsc::Sender<TEvent>,
+    /// Held by the running trigger, and kept across restarts.
+    rx: Mutex<mpsc::Receiver<TEvent>>,
+    state: watch::Sender<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The number of events sent and not yet processed by the agent.
+    pending: usize,
+    /// Whether the trigger is running.
+    running: bool,
+}
+
+impl Channel {
+    /// Counts an event as processed.
+    fn done(&self) {
+        self.state.send_modify(|state| state.pending -= 1);
+    }
+
+    fn set_running(&self, running: bool) {
+        self.state.send_modify(|state| state.running = running);
+    }
+}
+
+/// A trigger that emits the events sent into its channel.
+///
+/// The trigger is what an agent consumes, while [`forward`](Self::forward) returns
+/// the response handler other agents send their responses into it with. Clones
+/// share the same channel, so a channel can be fed by several agents.
+#[derive(Clone)]
+pub struct ChannelTrigger {
+    channel: Arc<Channel>,
+}
+
+impl ChannelTrigger {
+    /// Sends an event into the channel, waiting while the channel is full.
+    pub async fn send(&self, event: TEvent) {
+        self.channel.state.send_modify(|state| state.pending += 1);
+        // The channel holds its own receiver, so it is never closed.
+        let _ = self.channel.tx.send(event).await;
+    }
+
+    /// Returns a response handler that sends the responses into the channel, as
+    /// events named `event_name`.
+    pub fn forward(&self, event_name: impl Into<String>) -> ForwardHandler {
+        ForwardHandler::new(event_name, self.clone())
+    }
+
+    /// Returns the number of events sent into the channel and not yet processed by
+    /// the agent consuming it.
+    ///
+    /// Events the agent refused or dropped while stopping count as processed.
+    pub fn pending(&self) -> usize {
+        self.channel.state.borrow().pending
+    }
+
+    /// Waits until every event sent into the channel has been processed, or until
+    /// the trigger is no longer running to process them.
+    pub async fn wait_processed(&self) {
+        let mut state = self.channel.state.subscribe();
+        let _ = state
+            .wait_for(|state| state.pending == 0 || !state.running)
+            .await;
+    }
+}
+
+#[async_trait]
+impl Trigger for ChannelTrigger {
+    /// Launches the trigger's long-running task.
+    ///
+    /// Without acks, an event counts as processed once the agent has received it.
+    async fn launch(
+        &self,
+        tx: mpsc::Sender<TEvent>,
+        shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let (acked_tx, mut acked_rx) = mpsc::channel::<AckedEvent>(1);
+        let handle = self.launch_acked(acked_tx, shutdown_rx).await?;
+        tokio::spawn(async move {
+            while let Some(AckedEvent { event, ack }) = acked_rx.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+                ack.ack();
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Launches the trigger's long-running task, counting each event as processed
+    /// once the agent reports its outcome.
+    async fn launch_acked(
+        &self,
+        tx: mpsc::Sender<AckedEvent>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let channel = self.channel.clone();
+        Ok(tokio::spawn(async move {
+            let mut rx = channel.rx.lock().await;
+            channel.set_running(true);
+            info!("ChannelTrigger started");
+            loop {
+                let event = tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        info!("ChannelTrigger received shutdown signal, terminating");
+                        break;
+                    }
+                    // The channel holds its own sender, so it is never closed.
+                    Some(event) = rx.recv() => event,
+                };
+                debug!(event_name = %event.name, "Emitting event from the channel");
+                let (ack, outcome) = Ack::channel();
+                if tx.send(AckedEvent { event, ack }).await.is_err() {
+                    channel.done();
+                    break;
+                }
+                let channel = channel.clone();
+                tokio::spawn(async move {
+                    let _ = outcome.await;
+                    channel.done();
+                });
+            }
+            channel.set_running(false);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_channel_trigger_tracks_events_until_processed() {
+        let trigger = ChannelTriggerBuilder::new().with_capacity(4).build();
+        let (tx, mut rx) = mpsc::channel(4);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let handle = trigger.launch_acked(tx, shutdown_rx).await.unwrap();
+
+        let event = TEvent {
+            name: "Triaged".to_string(),
+            payload: Some(json!({ "response": "urgent" })),
+        };
+        trigger.send(event).await;
+        assert_eq!(trigger.pending(), 1);
+
+        let AckedEvent { event, ack } = rx.recv().await.unwrap();
+        assert_eq!(event.name, "Triaged");
+        assert_eq!(trigger.pending(), 1);
+        ack.ack();
+        tokio::time::timeout(Duration::from_secs(1), trigger.wait_processed())
+            .await
+            .unwrap();
+
+        // Dropped events count as processed too.
+        trigger
+            .send(TEvent {
+                name: "Triaged".to_string(),
+                payload: None,
+            })
+            .await;
+        drop(rx.recv().await.unwrap());
+        tokio::time::timeout(Duration::from_secs(1), trigger.wait_processed())
+            .await
+            .unwrap();
+
+        shutdown_tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+}
@@ -1,13 +1,164 @@
 // The `event` module defines the `TEvent` struct, which represents an event that can be processed by the agent.
 
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::oneshot;
 
 /// The `TEvent` struct represents an event that can be processed by the agent.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TEvent {
     /// The name of the event.
     pub name: String,
     /// The payload of the event, which can be any JSON value.
+    ///
+    /// An event without payload is written without the field, so that a `null`
+    /// payload reads back as `Some(Value::Null)`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "present"
+    )]
     pub payload: Option<Value>,
 }
+
+/// Reads a field that is present, even if `null`, as `Some`.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// A function that derives a key from an event, such as a chat or thread id.
+///
+/// Returning `None` means the event has no key.
+pub type KeyExtractor = Arc<dyn Fn(&TEvent) -> Option<String> + Send + Sync>;
+
+/// Returns a key extractor that reads a field of the event payload.
+///
+/// Nested fields are addressed with a dotted path, e.g. `"message.chat_id"`.
+/// String values are used as-is, other values are converted to their JSON text.
+pub fn payload_key(path: &str) -> impl Fn(&TEvent) -> Option<String> + Send + Sync + 'static {
+    let path: Vec<String> = path.split('.').map(str::to_string).collect();
+    move |event: &TEvent| {
+        let mut value = event.payload.as_ref()?;
+        for field in &path {
+            value = value.get(field)?;
+        }
+        match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// What became of an event, as reported back to the trigger that emitted it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The event was processed and its response handled.
+    Ack,
+    /// The event could not be processed, for the given reason.
+    Nack(String),
+    /// The event was left unprocessed on purpose, for the given reason, because no
+    /// route matched it or an observer vetoed it.
+    Skipped(String),
+}
+
+/// Reports the outcome of an event to the trigger that emitted it.
+///
+/// An `Ack` dropped without an outcome, as happens to the events still queued when
+/// the agent stops, closes the receiver without a value: the trigger should treat
+/// the event as not processed.
+pub struct Ack(Option<oneshot::Sender<Outcome>>);
+
+impl Ack {
+    /// Creates an `Ack` with the receiver its outcome will be delivered to.
+    pub fn channel() -> (Self, oneshot::Receiver<Outcome>) {
+        let (tx, rx) = oneshot::channel();
+        (Self(Some(tx)), rx)
+    }
+
+    /// Creates an `Ack` whose outcome nobody waits for.
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    /// Reports that the event was processed.
+    pub fn ack(self) {
+        self.report(Outcome::Ack);
+    }
+
+    /// Reports that the event could not be processed.
+    pub fn nack(self, reason: impl Into<String>) {
+        self.report(Outcome::Nack(reason.into()));
+    }
+
+    /// Reports that the event was left unprocessed on purpose.
+    pub fn skip(self, reason: impl Into<String>) {
+        self.report(Outcome::Skipped(reason.into()));
+    }
+
+    fn report(mut self, outcome: Outcome) {
+        if let Some(tx) = self.0.take() {
+            // The trigger may have stopped waiting, which is fine.
+            let _ = tx.send(outcome);
+        }
+    }
+}
+
+/// An event sent by a trigger that wants to know its outcome.
+pub struct AckedEvent {
+    /// The event.
+    pub event: TEvent,
+    /// Where the outcome of the event is reported.
+    pub ack: Ack,
+}
+
+impl From<TEvent> for AckedEvent {
+    fn from(event: TEvent) -> Self {
+        Self {
+            event,
+            ack: Ack::none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payload_key_reads_nested_fields() {
+        let event = TEvent {
+            name: "TelegramMessage".to_string(),
+            payload: Some(json!({"chat_id": 42, "thread": {"id": "t-1"}})),
+        };
+        assert_eq!(payload_key("chat_id")(&event), Some("42".to_string()));
+        assert_eq!(payload_key("thread.id")(&event), Some("t-1".to_string()));
+        assert_eq!(payload_key("missing")(&event), None);
+    }
+
+    #[tokio::test]
+    async fn ack_reports_outcomes() {
+        let (ack, outcome) = Ack::channel();
+        ack.nack("refused");
+        assert_eq!(outcome.await.unwrap(), Outcome::Nack("refused".to_string()));
+
+        let (ack, outcome) = Ack::channel();
+        drop(ack);
+        assert!(outcome.await.is_err());
+    }
+
+    #[test]
+    fn null_payloads_read_back_as_null() {
+        for payload in [None, Some(Value::Null), Some(json!({"n": 1}))] {
+            let event = TEvent {
+                name: "Tick".to_string(),
+                payload,
+            };
+            let read: TEvent =
+                serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+            assert_eq!(read.payload, event.payload);
+        }
+    }
+}
@@ -1,19 +1,33 @@
 // The `gmail_watch_trigger` module provides a trigger that watches for new unread emails in a Gmail account.
 
 use crate::{
-    triggers::{event::TEvent, Trigger, TriggerError},
+    triggers::{
+        event::{Ack, AckedEvent, Outcome, TEvent},
+        Trigger, TriggerError,
+    },
     utils::{context_hub::ContextHub, google_auth::GmailHubType},
 };
 use async_trait::async_trait;
-use google_gmail1::api::Scope;
+use google_gmail1::api::{ModifyMessageRequest, Scope};
 use serde_json::json;
-use std::{error::Error, sync::Arc, time::Duration};
-use tokio::sync::{broadcast, mpsc};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{broadcast, mpsc, oneshot, oneshot::error::RecvError};
 use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// The number of times a message whose processing fails is emitted, by default.
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
 
 /// A builder for [`GmailWatchTrigger`].
 pub struct GmailWatchTriggerBuilder {
     hub: Arc<ContextHub>,
+    mark_as_read: bool,
+    max_attempts: u32,
 }
 
 impl GmailWatchTriggerBuilder {
@@ -26,7 +40,32 @@ impl GmailWatchTriggerBuilder {
     /// * `hub` - A shared [`ContextHub`] for managing authentication.
     pub fn new(hub: Arc<ContextHub>) -> Self {
         hub.add_scope(Scope::Readonly);
-        Self { hub }
+        Self {
+            hub,
+            mark_as_read: false,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Sets whether messages are marked as read once the agent has processed them.
+    ///
+    /// A message is only marked as read when its event is acknowledged. A message
+    /// skipped by the agent, because no route matched it or an observer vetoed it,
+    /// stays unread, and so does a message whose processing failed.
+    /// Marking messages requires the `Modify` scope, which this method registers.
+    pub fn with_mark_as_read(mut self, mark_as_read: bool) -> Self {
+        if mark_as_read {
+            self.hub.add_scope(Scope::Modify);
+        }
+        self.mark_as_read = mark_as_read;
+        self
+    }
+
+    /// Sets how many times a message whose processing fails is emitted before the
+    /// trigger gives up on it. Defaults to 3.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
     }
 
     /// Builds a [`GmailWatchTrigger`].
@@ -35,48 +74,188 @@ impl GmailWatchTriggerBuilder {
     /// using the scopes collected in the [`ContextHub`] and creates a [`GmailWatchTrigger`].
     pub async fn build(&self) -> Result<GmailWatchTrigger, Box<dyn Error>> {
         let hub = self.hub.get_hub().await?;
-        Ok(GmailWatchTrigger { hub })
+        Ok(GmailWatchTrigger {
+            hub,
+            mark_as_read: self.mark_as_read,
+            max_attempts: self.max_attempts,
+        })
     }
 }
 
 /// A trigger that watches for new unread emails in a Gmail account.
+///
+/// Each unread message is emitted once while the trigger runs, unless its processing
+/// fails: it is then emitted again at the next polls, up to the maximum number of
+/// attempts. A message is not emitted again while its previous event is still being
+/// processed. Unless messages are marked as read, they are emitted again when the
+/// agent restarts: deduplicate the events on their `id` with
+/// [`AgentBuilder::with_deduplication`](crate::agent::AgentBuilder::with_deduplication).
 pub struct GmailWatchTrigger {
     hub: GmailHubType,
+    /// Whether messages are marked as read once their event is acknowledged.
+    mark_as_read: bool,
+    /// How many times a message whose processing fails is emitted.
+    max_attempts: u32,
+}
+
+/// What the trigger knows of an unread message it emitted.
+#[derive(Default)]
+struct Tracked {
+    /// The number of times the message was emitted.
+    attempts: u32,
+    /// Whether the outcome of its last event is not known yet.
+    in_flight: bool,
+    /// Whether the agent processed or skipped it, so it is not emitted again.
+    settled: bool,
+}
+
+/// Tracks the unread messages the trigger emitted, by id.
+#[derive(Default)]
+struct Messages(HashMap<String, Tracked>);
+
+impl Messages {
+    /// Forgets the messages that are no longer unread, unless they are in flight.
+    fn retain_listed(&mut self, listed: &HashSet<&str>) {
+        self.0
+            .retain(|id, tracked| tracked.in_flight || listed.contains(id.as_str()));
+    }
+
+    /// Returns `true` if the message is to be emitted, counting the attempt.
+    fn begin(&mut self, id: &str, max_attempts: u32) -> bool {
+        let tracked = self.0.entry(id.to_string()).or_default();
+        if tracked.in_flight || tracked.settled || tracked.attempts >= max_attempts {
+            return false;
+        }
+        tracked.attempts += 1;
+        tracked.in_flight = true;
+        true
+    }
+
+    /// Takes back an attempt for a message that did not reach the agent.
+    fn undo(&mut self, id: &str) {
+        if let Some(tracked) = self.0.get_mut(id) {
+            tracked.attempts -= 1;
+            tracked.in_flight = false;
+        }
+    }
+
+    /// Records the outcome of a message's event.
+    ///
+    /// An event dropped without an outcome, as happens when the agent stops, does
+    /// not count as an attempt.
+    fn finish(&mut self, id: &str, outcome: Result<Outcome, RecvError>, max_attempts: u32) {
+        let Some(tracked) = self.0.get_mut(id) else {
+            return;
+        };
+        match outcome {
+            Ok(Outcome::Ack) => tracked.settled = true,
+            Ok(Outcome::Skipped(reason)) => {
+                debug!(message_id = %id, reason = %reason, "Message skipped, leaving it unread");
+                tracked.settled = true;
+            }
+            Ok(Outcome::Nack(reason)) if tracked.attempts >= max_attempts => {
+                warn!(message_id = %id, attempts = tracked.attempts, reason = %reason, "Message failed too many times, giving up on it");
+            }
+            Ok(Outcome::Nack(reason)) => {
+                debug!(message_id = %id, reason = %reason, "Message not processed, leaving it unread");
+            }
+            Err(_) => tracked.attempts -= 1,
+        }
+        tracked.in_flight = false;
+    }
+}
+
+/// Waits for the outcome of a message's event, and marks the message as read if it
+/// was processed and `mark_as_read` is set.
+async fn settle(
+    hub: GmailHubType,
+    id: String,
+    outcome: oneshot::Receiver<Outcome>,
+    messages: Arc<Mutex<Messages>>,
+    mark_as_read: bool,
+    max_attempts: u32,
+) {
+    let outcome = outcome.await;
+    if mark_as_read && outcome == Ok(Outcome::Ack) {
+        let request = ModifyMessageRequest {
+            add_label_ids: None,
+            remove_label_ids: Some(vec!["UNREAD".to_string()]),
+        };
+        let modify = hub
+            .users()
+            .messages_modify(request, "me", &id)
+            .add_scope(Scope::Modify);
+        match modify.doit().await {
+            Ok(_) => debug!(message_id = %id, "Marked message as read"),
+            Err(e) => warn!(message_id = %id, error = %e, "Failed to mark message as read"),
+        }
+    }
+    messages.lock().unwrap().finish(&id, outcome, max_attempts);
 }
 
 #[async_trait]
 impl Trigger for GmailWatchTrigger {
-    /// Launches the trigger's long-running task.
+    /// Launches the trigger's long-running task, without waiting for the outcome of its events.
     async fn launch(
         &self,
         tx: mpsc::Sender<TEvent>,
+        shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let (acked_tx, mut acked_rx) = mpsc::channel::<AckedEvent>(1);
+        let handle = self.launch_acked(acked_tx, shutdown_rx).await?;
+        tokio::spawn(async move {
+            // Nobody reports the outcome, so the acks are dropped and no message is
+            // marked as read.
+            while let Some(AckedEvent { event, .. }) = acked_rx.recv().await {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Launches the trigger's long-running task.
+    async fn launch_acked(
+        &self,
+        tx: mpsc::Sender<AckedEvent>,
         mut shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<JoinHandle<()>, TriggerError> {
         let hub = self.hub.clone();
+        let mark_as_read = self.mark_as_read;
+        let max_attempts = self.max_attempts;
+        let messages = Arc::new(Mutex::new(Messages::default()));
         let task_handle = tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(120));
             loop {
                 tokio::select! {
                     _ = interval.tick() => {
                         let res_result = hub.users().messages_list("me").q("is:unread").doit().await;
-                        if let Ok((_result, msg_list)) = res_result {
-                            if let Some(msgl) = msg_list.messages {
-                                for i in msgl {
-                                    if let Some(id) = i.id {
-                                        let msg_result = hub.users().messages_get("me", &id).add_scope(Scope::Readonly).doit().await;
-                                        if let Ok(msg) = msg_result {
-                                            let event = TEvent {
-                                                name: "NewEmail".to_string(),
-                                                payload: Some(json!(msg.1)),
-                                            };
-                                            if tx.send(event).await.is_err() {
-                                                // Agent's main channel closed, so we can also stop.
-                                                break;
-                                            }
-                                        }
-                                    }
-                                }
+                        let Ok((_result, msg_list)) = res_result else {
+                            continue;
+                        };
+                        let ids: Vec<String> = msg_list.messages.unwrap_or_default().into_iter().filter_map(|m| m.id).collect();
+                        messages.lock().unwrap().retain_listed(&ids.iter().map(String::as_str).collect());
+                        for id in ids {
+                            if !messages.lock().unwrap().begin(&id, max_attempts) {
+                                continue;
                             }
+                            let msg_result = hub.users().messages_get("me", &id).add_scope(Scope::Readonly).doit().await;
+                            let Ok(msg) = msg_result else {
+                                messages.lock().unwrap().undo(&id);
+                                continue;
+                            };
+                            let (ack, outcome) = Ack::channel();
+                            let event = TEvent {
+                                name: "NewEmail".to_string(),
+                                payload: Some(json!(msg.1)),
+                            };
+                            if tx.send(AckedEvent { event, ack }).await.is_err() {
+                                // Agent's main channel closed, so we can also stop.
+                                messages.lock().unwrap().undo(&id);
+                                return;
+                            }
+                            tokio::spawn(settle(hub.clone(), id, outcome, messages.clone(), mark_as_read, max_attempts));
                         }
                     }
                     _ = shutdown_rx.recv() => {
@@ -97,8 +276,39 @@ mod tests {
     use crate::utils::google_auth::{GConf, InnerConf, GoogleAuthFlow};
     use std::path::Path;
 
+    #[test]
+    fn messages_are_emitted_until_settled_or_out_of_attempts() {
+        let mut messages = Messages::default();
+        let nack = || Ok(Outcome::Nack("boom".to_string()));
+
+        // A failing message is emitted up to the maximum number of attempts.
+        assert!(messages.begin("a", 2));
+        assert!(!messages.begin("a", 2), "the message is in flight");
+        messages.finish("a", nack(), 2);
+        assert!(messages.begin("a", 2));
+        messages.finish("a", nack(), 2);
+        assert!(!messages.begin("a", 2));
+
+        // A skipped message is not emitted again.
+        assert!(messages.begin("b", 2));
+        messages.finish("b", Ok(Outcome::Skipped("vetoed".to_string())), 2);
+        assert!(!messages.begin("b", 2));
+
+        // An event dropped without an outcome doesn't count as an attempt.
+        assert!(messages.begin("c", 1));
+        let (ack, outcome) = Ack::channel();
+        drop(ack);
+        messages.finish("c", outcome.blocking_recv(), 1);
+        assert!(messages.begin("c", 1));
+
+        // Messages no longer unread are forgotten, unless in flight.
+        messages.retain_listed(&HashSet::new());
+        assert_eq!(messages.0.len(), 1);
+    }
+
     // This is the test function
     #[tokio::test]
+    #[ignore = "needs Gmail credentials in ./tmp"]
     async fn gmail_trigger_launches_and_shuts_down() {
         // --- 1. Arrange ---
         // Create the channels that the agent would normally create.
@@ -116,17 +326,7 @@ mod tests {
         let builder = GmailWatchTriggerBuilder::new(hub);
 
         // Build the trigger.
-        let trigger_result = builder.build().await;
-
-        if trigger_result.is_err() {
-            // This test requires valid credentials. If they are not available, we skip the test.
-            // This is not ideal, but it's better than having a failing test.
-            // In a real-world scenario, we would use a mock API.
-            println!("Skipping test because of missing credentials or auth error.");
-            return;
-        }
-
-        let trigger = trigger_result.unwrap();
+        let trigger = builder.build().await.unwrap();
 
         // --- 2. Act ---
         // Launch the trigger.
@@ -1,13 +1,19 @@
 // This file is automatically generated by build.rs
 
+pub mod bus_trigger;
+pub mod channel_trigger;
 pub mod poll_trigger;
 pub mod gmail_watch_trigger;
 pub mod event;
+pub mod replay_trigger;
 pub mod traits;
 pub mod telegram_bot_trigger;
 
+pub use bus_trigger::{BusTrigger, BusTriggerBuilder};
+pub use channel_trigger::{ChannelTrigger, ChannelTriggerBuilder};
 pub use poll_trigger::{PollTrigger, PollTriggerBuilder};
 pub use gmail_watch_trigger::{GmailWatchTrigger, GmailWatchTriggerBuilder};
+pub use replay_trigger::{ReplayTrigger, ReplayTriggerBuilder};
 pub use telegram_bot_trigger::{TelegramBotTrigger, TelegramBotTriggerBuilder};
 pub use crate::triggers::event::TEvent;
 pub use crate::triggers::traits::{Trigger, TriggerError};
\ No newline at end of file
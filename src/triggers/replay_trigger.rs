@@ -0,0 +1,182 @@
+// The `replay_trigger` module provides a trigger that replays the events of a
+// recording.
+
+use crate::recording::{RecordedEvent, read_recording};
+use crate::triggers::{Trigger, TriggerError, event::TEvent};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+/// How fast a [`ReplayTrigger`] emits its events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayPace {
+    /// The events are spaced as they were received, from the first one.
+    #[default]
+    Original,
+    /// The events are emitted one after the other, as fast as the agent takes them.
+    AsFastAsPossible,
+}
+
+/// A builder for [`ReplayTrigger`].
+pub struct ReplayTriggerBuilder {
+    path: PathBuf,
+    pace: ReplayPace,
+}
+
+impl ReplayTriggerBuilder {
+    /// Creates a new `ReplayTriggerBuilder`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The recording to replay, as written by an
+    ///   [`EventRecorder`](crate::recording::EventRecorder).
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            pace: ReplayPace::default(),
+        }
+    }
+
+    /// Sets how fast the events are emitted, at their original pace by default.
+    pub fn with_pace(mut self, pace: ReplayPace) -> Self {
+        self.pace = pace;
+        self
+    }
+
+    /// Reads the recording and builds a `ReplayTrigger`.
+    pub fn build(&self) -> std::io::Result<ReplayTrigger> {
+        Ok(ReplayTrigger {
+            events: read_recording(&self.path)?.into(),
+            pace: self.pace,
+        })
+    }
+}
+
+/// A trigger that emits the events of a recording once, then stops.
+///
+/// Relaunching the trigger, e.g. under a restart policy, replays the recording again.
+pub struct ReplayTrigger {
+    events: Arc<[RecordedEvent]>,
+    pace: ReplayPace,
+}
+
+impl ReplayTrigger {
+    /// Returns the number of events in the recording.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if the recording has no event.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[async_trait]
+impl Trigger for ReplayTrigger {
+    /// Launches the trigger's long-running task.
+    async fn launch(
+        &self,
+        tx: mpsc::Sender<TEvent>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<JoinHandle<()>, TriggerError> {
+        let events = self.events.clone();
+        let pace = self.pace;
+        Ok(tokio::spawn(async move {
+            info!(events = events.len(), ?pace, "ReplayTrigger started");
+            let started = Instant::now();
+            let first = events.first().map(|recorded| recorded.received_at);
+            for recorded in events.iter() {
+                if let (ReplayPace::Original, Some(first)) = (pace, first) {
+                    let offset = (recorded.received_at - first)
+                        .to_std()
+                        .unwrap_or(Duration::ZERO);
+                    tokio::select! {
+                        _ = shutdown_rx.recv() => {
+                            info!("ReplayTrigger received shutdown signal, terminating");
+                            return;
+                        }
+                        _ = tokio::time::sleep_until(started + offset) => {}
+                    }
+                }
+                debug!(event_name = %recorded.event.name, "Replaying event");
+                tokio::select! {
+                    _ = shutdown_rx.recv() => {
+                        info!("ReplayTrigger received shutdown signal, terminating");
+                        return;
+                    }
+                    sent = tx.send(recorded.event.clone()) => {
+                        if sent.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            info!("ReplayTrigger has replayed its recording");
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, Utc};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replays_events_at_their_original_pace_or_at_once() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let start = Utc::now();
+        let lines: Vec<String> = [(0, "First"), (100, "Second"), (250, "Third")]
+            .iter()
+            .map(|(offset, name)| {
+                let recorded = RecordedEvent {
+                    received_at: start + TimeDelta::milliseconds(*offset),
+                    event: TEvent {
+                        name: name.to_string(),
+                        payload: Some(json!({ "offset": offset })),
+                    },
+                };
+                serde_json::to_string(&recorded).unwrap()
+            })
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        for (pace, expected) in [
+            (ReplayPace::Original, [0, 100, 250]),
+            (ReplayPace::AsFastAsPossible, [0, 0, 0]),
+        ] {
+            let trigger = ReplayTriggerBuilder::new(&path)
+                .with_pace(pace)
+                .build()
+                .unwrap();
+            assert_eq!(trigger.len(), 3);
+            let (tx, mut rx) = mpsc::channel(10);
+            let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+            // The clock is paused: it only moves when advanced, or when every task
+            // waits for a timer.
+            let launched = Instant::now();
+            let handle = trigger.launch(tx, shutdown_rx).await.unwrap();
+            for (offset, name) in expected.iter().zip(["First", "Second", "Third"]) {
+                let offset = Duration::from_millis(*offset);
+                let until = offset.saturating_sub(launched.elapsed());
+                if !until.is_zero() {
+                    tokio::time::advance(until - Duration::from_millis(1)).await;
+                    assert!(rx.try_recv().is_err(), "{name} replayed early");
+                }
+                let event = rx.recv().await.unwrap();
+                assert_eq!(event.name, name);
+                assert_eq!(launched.elapsed(), offset, "{name} replayed late");
+            }
+            handle.await.unwrap();
+            assert!(rx.recv().await.is_none());
+        }
+    }
+}
@@ -1,5 +1,6 @@
 // The `telegram_bot_trigger` module provides a trigger that listens for Telegram messages.
 
+use crate::handlers::TelegramReplyHandler;
 use crate::triggers::{event::TEvent, Trigger, TriggerError};
 use async_trait::async_trait;
 use serde_json::json;
@@ -53,6 +54,16 @@ pub struct TelegramBotTrigger {
     bot: Bot,
 }
 
+impl TelegramBotTrigger {
+    /// Returns a handler that answers this trigger's messages in the chat they came from.
+    ///
+    /// The handler shares the trigger's bot, so replies are sent by the same bot
+    /// that received the messages.
+    pub fn reply_handler(&self) -> TelegramReplyHandler {
+        TelegramReplyHandler::new(self.bot.clone())
+    }
+}
+
 #[async_trait]
 impl Trigger for TelegramBotTrigger {
     /// Launches the trigger's long-running task to listen for Telegram updates.
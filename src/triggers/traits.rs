@@ -1,4 +1,4 @@
-use crate::triggers::event::TEvent;
+use crate::triggers::event::{AckedEvent, TEvent};
 use crate::utils::google_auth::AuthError;
 use async_trait::async_trait;
 use thiserror::Error;
@@ -28,4 +28,31 @@ pub trait Trigger: Send + Sync {
         tx: mpsc::Sender<TEvent>,
         shutdown_rx: broadcast::Receiver<()>,
     ) -> Result<tokio::task::JoinHandle<()>, TriggerError>;
+
+    /// Launches the trigger's long-running task, sending events that carry an [`Ack`].
+    ///
+    /// This is how the agent launches triggers. Triggers that need to know whether
+    /// their events were processed, for example to commit an offset only after
+    /// success, override it and wait on the receiver of each event's
+    /// [`Ack::channel`]. The default implementation calls [`launch`](Self::launch)
+    /// and forwards its events without waiting for their outcome.
+    ///
+    /// [`Ack`]: crate::triggers::event::Ack
+    /// [`Ack::channel`]: crate::triggers::event::Ack::channel
+    async fn launch_acked(
+        &self,
+        tx: mpsc::Sender<AckedEvent>,
+        shutdown_rx: broadcast::Receiver<()>,
+    ) -> Result<tokio::task::JoinHandle<()>, TriggerError> {
+        let (event_tx, mut event_rx) = mpsc::channel::<TEvent>(1);
+        let handle = self.launch(event_tx, shutdown_rx).await?;
+        tokio::spawn(async move {
+            while let Some(event) = event_rx.recv().await {
+                if tx.send(AckedEvent::from(event)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(handle)
+    }
 }
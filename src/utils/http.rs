@@ -0,0 +1,75 @@
+// The `http` module provides the small HTTP/1 server the agent's local listeners,
+// the metrics and the admin API, are built on.
+
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::future::Future;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Serves the connections accepted on `listener` with `handler` until `stop` is cancelled.
+///
+/// `name` identifies the listener in the logs.
+pub(crate) async fn serve<H, F>(
+    name: &'static str,
+    listener: TcpListener,
+    stop: CancellationToken,
+    handler: H,
+) where
+    H: Fn(Request<Incoming>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response<Full<Bytes>>> + Send + 'static,
+{
+    if let Ok(addr) = listener.local_addr() {
+        info!(listener = name, %addr, "HTTP listener started");
+    }
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!(listener = name, error = %e, "Failed to accept a connection");
+                    continue;
+                }
+            },
+            _ = stop.cancelled() => break,
+        };
+        let handler = handler.clone();
+        let service = service_fn(move |request| {
+            let response = handler(request);
+            async move { Ok::<_, Infallible>(response.await) }
+        });
+        tokio::spawn(async move {
+            let connection = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = connection.await {
+                debug!(listener = name, error = %e, "HTTP connection failed");
+            }
+        });
+    }
+    debug!(listener = name, "HTTP listener stopped");
+}
+
+/// Builds a response with the given status, content type and body.
+pub(crate) fn response(
+    status: StatusCode,
+    content_type: &'static str,
+    body: impl Into<Bytes>,
+) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static(content_type),
+    );
+    response
+}
+
+/// Builds a plain-text response.
+pub(crate) fn text(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    response(status, "text/plain; charset=utf-8", body)
+}
@@ -0,0 +1,25 @@
+// The `jsonl` module appends records to JSON Lines files, for the handlers, stores
+// and recorders that keep one record per line.
+
+use serde::Serialize;
+use std::path::Path;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Appends a record to the JSON Lines file at `path`, as a single line, creating the
+/// file if it does not exist.
+///
+/// Callers appending to the same file concurrently serialize their appends, so that
+/// lines are not interleaved.
+pub(crate) async fn append_line(path: &Path, record: &impl Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    // Tokio hands writes to a background task; flushing waits for it to finish.
+    file.flush().await
+}
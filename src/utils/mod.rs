@@ -2,6 +2,10 @@
 
 pub mod context_hub;
 pub mod google_auth;
+pub(crate) mod http;
+pub(crate) mod jsonl;
+pub mod pattern;
 pub mod template;
 
+pub use crate::utils::pattern::EventPattern;
 pub use crate::utils::template::{TEngine, TEngineError};
@@ -0,0 +1,100 @@
+// The `pattern` module provides glob patterns for matching event names.
+
+use std::fmt;
+
+/// A glob pattern matched against event names.
+///
+/// `*` matches any sequence of characters (including none) and `?` matches exactly
+/// one character; every other character matches itself. For example `Telegram*`
+/// matches `TelegramMessage`, and `*` matches every event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventPattern {
+    pattern: String,
+}
+
+impl EventPattern {
+    /// Creates a new `EventPattern`.
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    /// Returns the pattern as written.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns `true` if the name matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+
+        let (mut p, mut n) = (0, 0);
+        // The position of the last `*` seen and the name position it was tried at.
+        let mut backtrack: Option<(usize, usize)> = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                }
+                Some('?') => {
+                    p += 1;
+                    n += 1;
+                }
+                Some(c) if *c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    // Let the last `*` swallow one more character and try again.
+                    Some((star, from)) => {
+                        p = star + 1;
+                        n = from + 1;
+                        backtrack = Some((star, from + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|c| *c == '*')
+    }
+}
+
+impl From<&str> for EventPattern {
+    fn from(pattern: &str) -> Self {
+        Self::new(pattern)
+    }
+}
+
+impl fmt::Display for EventPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literal_names() {
+        let pattern = EventPattern::new("NewEmail");
+        assert!(pattern.matches("NewEmail"));
+        assert!(!pattern.matches("NewEmails"));
+        assert!(!pattern.matches("New"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(EventPattern::new("*").matches(""));
+        assert!(EventPattern::new("*").matches("TelegramMessage"));
+        assert!(EventPattern::new("Telegram*").matches("TelegramMessage"));
+        assert!(EventPattern::new("*Email").matches("NewEmail"));
+        assert!(EventPattern::new("*a*b*").matches("xxaxxbxx"));
+        assert!(!EventPattern::new("*a*b").matches("xxaxxbxx"));
+        assert!(EventPattern::new("Poll?").matches("Poll1"));
+        assert!(!EventPattern::new("Poll?").matches("Poll"));
+    }
+}
@@ -49,6 +49,14 @@ impl TEngine {
         Ok(())
     }
 
+    pub fn render(&self, name: &str, data: &serde_json::Value) -> Result<String, TEngineError> {
+        if !self.handlebars.has_template(name) {
+            return Err(TEngineError::TemplateNotFoundError(name.to_string()));
+        }
+        let result = self.handlebars.render(name, data)?;
+        Ok(result)
+    }
+
     pub fn render_template(
         &self,
         template: &str,
@@ -72,6 +80,20 @@ mod tests {
         assert_eq!(rendered, "Hello, World!");
     }
 
+    #[test]
+    fn it_renders_registered_templates() {
+        let mut engine = TEngine::new();
+        engine
+            .register_template_string("greeting", "Hello, {{name}}!")
+            .unwrap();
+        let data = serde_json::json!({"name": "World"});
+        assert_eq!(engine.render("greeting", &data).unwrap(), "Hello, World!");
+        assert!(matches!(
+            engine.render("missing", &data),
+            Err(TEngineError::TemplateNotFoundError(_))
+        ));
+    }
+
     #[test]
     fn it_works_with_complex_data() {
         let engine = TEngine::new();